@@ -177,10 +177,9 @@ impl Dispatch<wl_seat::WlSeat, ()> for State {
         if let wl_seat::Event::Capabilities {
             capabilities: WEnum::Value(capabilities),
         } = event
+            && capabilities.contains(wl_seat::Capability::Keyboard)
         {
-            if capabilities.contains(wl_seat::Capability::Keyboard) {
-                seat.get_keyboard(qh, ());
-            }
+            seat.get_keyboard(qh, ());
         }
     }
 }
@@ -194,11 +193,9 @@ impl Dispatch<wl_keyboard::WlKeyboard, ()> for State {
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        if let wl_keyboard::Event::Key { key, .. } = event {
-            if key == 1 {
-                // ESC key
-                state.running = false;
-            }
+        // ESC key
+        if let wl_keyboard::Event::Key { key: 1, .. } = event {
+            state.running = false;
         }
     }
 }
@@ -0,0 +1,251 @@
+//! Async variants of the capture functions, enabled with the `async` feature.
+//!
+//! Instead of blocking in `EventQueue::blocking_dispatch` these functions wait for the Wayland
+//! socket to become readable through [`async_io`], so they work with any executor and several
+//! captures can be in flight at the same time without occupying a thread each.
+//!
+//! Only the waiting is async. The `screenshot_*_async` functions convert, rotate and scale the
+//! captured frames on the task that awaits them, which keeps the executor thread busy for the
+//! length of a blocking screenshot's compositing. Run them where that is acceptable, e.g. on a
+//! runtime's blocking thread pool, or capture with
+//! [`WayshotConnection::capture_frame_copies_async`] and process the frames elsewhere.
+
+use std::{fs::File, future::poll_fn, io, task::Poll};
+
+use async_io::Async;
+use futures_util::future::try_join_all;
+use image::DynamicImage;
+use memmap2::MmapMut;
+use wayland_client::{DispatchError, EventQueue, backend::WaylandError};
+
+use crate::{
+    Error, Result, WayshotConnection,
+    dispatch::CaptureFrameState,
+    image_util,
+    output::OutputInfo,
+    region::{EmbeddedRegion, LogicalRegion},
+    screencopy::{FrameCopy, FrameGuard, create_shm_fd, select_shm_format},
+};
+
+impl WayshotConnection {
+    /// Dispatch `event_queue` until `done` returns true, waiting for new events on the Wayland
+    /// socket without blocking the calling thread.
+    async fn dispatch_until<F>(
+        &self,
+        event_queue: &mut EventQueue<CaptureFrameState>,
+        state: &mut CaptureFrameState,
+        done: F,
+    ) -> Result<()>
+    where
+        F: Fn(&CaptureFrameState) -> bool,
+    {
+        // Every waiting capture registers its own duplicate of the socket, so wakeups are not
+        // lost when several captures poll the connection from the same task.
+        let socket = Async::new(self.conn.backend().poll_fd().try_clone_to_owned()?)?;
+
+        poll_fn(|cx| {
+            loop {
+                // Also registers the waker for events another capture reads from the socket.
+                if let Poll::Ready(Err(e)) = event_queue.poll_dispatch_pending(cx, state) {
+                    return Poll::Ready(Err(e.into()));
+                }
+                if done(state) {
+                    return Poll::Ready(Ok(()));
+                }
+
+                match self.conn.flush() {
+                    Ok(()) => {}
+                    Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Poll::Ready(Err(backend_error(e))),
+                }
+
+                // Events are already queued up, dispatch them first.
+                let Some(guard) = event_queue.prepare_read() else {
+                    continue;
+                };
+                match socket.poll_readable(cx) {
+                    Poll::Ready(Ok(())) => match guard.read() {
+                        Ok(_) => {}
+                        Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(e) => return Poll::Ready(Err(backend_error(e))),
+                    },
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    // The read guard must not be held across an await point, other captures
+                    // would not be able to read from the socket until it is dropped.
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        })
+        .await
+    }
+
+    /// Async version of [`WayshotConnection::capture_frame_copies`] for a single output.
+    #[tracing::instrument(skip_all, fields(output = format!("{output_info}"), region = capture_region.map(|r| format!("{r:}")).unwrap_or("fullscreen".to_string())))]
+    pub async fn capture_frame_copy_async(
+        &self,
+        cursor_overlay: bool,
+        output_info: &OutputInfo,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<(FrameCopy, FrameGuard)> {
+        let mut state = CaptureFrameState::default();
+        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+
+        let frame = self.request_output_frame(
+            &qh,
+            (),
+            cursor_overlay as i32,
+            &output_info.wl_output,
+            capture_region,
+        )?;
+        self.dispatch_until(&mut event_queue, &mut state, |state| {
+            frame.formats_received(state)
+        })
+        .await?;
+        if state.session_stopped {
+            return Err(Error::SessionStopped);
+        }
+        tracing::trace!(
+            "Received compositor frame buffer formats: {:#?}",
+            state.formats
+        );
+        let frame_format = select_shm_format(&state.formats)?;
+        tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
+
+        let mem_file = File::from(create_shm_fd()?);
+        mem_file.set_len(frame_format.byte_size())?;
+        let frame_guard = self.attach_shm_buffer(&qh, &frame, frame_format, &mem_file)?;
+        self.dispatch_until(&mut event_queue, &mut state, |state| state.state.is_some())
+            .await?;
+        if let Some(result) = state.frame_result() {
+            result?;
+        }
+
+        let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+        let frame_copy = FrameCopy::from_shm_mmap(
+            frame_format,
+            frame_mmap,
+            output_info,
+            capture_region,
+            state.damage.as_deref(),
+            frame.needs_crop(capture_region),
+        )?;
+        tracing::debug!("Created frame copy: {:#?}", frame_copy);
+        Ok((frame_copy, frame_guard))
+    }
+
+    /// Async version of [`WayshotConnection::capture_frame_copies`].
+    ///
+    /// All frames are requested at once and captured concurrently.
+    pub async fn capture_frame_copies_async(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        try_join_all(output_capture_regions.iter().map(
+            |(output_info, capture_region)| async move {
+                let (frame_copy, frame_guard) = self
+                    .capture_frame_copy_async(cursor_overlay, output_info, *capture_region)
+                    .await?;
+                Ok((frame_copy, frame_guard, output_info.clone()))
+            },
+        ))
+        .await
+    }
+
+    /// Capture the given outputs concurrently and composite them into `capture_region`.
+    ///
+    /// The compositing runs synchronously once the frames arrived, see the module documentation.
+    async fn screenshot_outputs_regions_async(
+        &self,
+        outputs_capture_regions: Vec<(OutputInfo, Option<EmbeddedRegion>)>,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let frames = self
+            .capture_frame_copies_async(&outputs_capture_regions, cursor_overlay)
+            .await?;
+        let scale = self.scale_policy.canvas_scale(
+            outputs_capture_regions
+                .iter()
+                .map(|(output_info, _)| output_info.scale()),
+        );
+
+        image_util::composite_frames(
+            frames
+                .into_iter()
+                .map(|(frame_copy, _, _)| frame_copy)
+                .collect(),
+            capture_region,
+            scale,
+            self.scale_policy,
+            self.resample_filter,
+        )
+    }
+
+    /// Async version of [`WayshotConnection::screenshot`].
+    pub async fn screenshot_async(
+        &self,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_outputs_regions_async(
+            self.intersecting_outputs(capture_region),
+            capture_region,
+            cursor_overlay,
+        )
+        .await
+    }
+
+    /// Async version of [`WayshotConnection::screenshot_single_output`].
+    pub async fn screenshot_single_output_async(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let (mut frame_copy, _) = self
+            .capture_frame_copy_async(cursor_overlay, output_info, None)
+            .await?;
+        frame_copy.get_image()
+    }
+
+    /// Async version of [`WayshotConnection::screenshot_outputs`].
+    pub async fn screenshot_outputs_async(
+        &self,
+        outputs: &[OutputInfo],
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        if outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+
+        self.screenshot_outputs_regions_async(
+            outputs
+                .iter()
+                .map(|output_info| (output_info.clone(), None))
+                .collect(),
+            outputs.try_into()?,
+            cursor_overlay,
+        )
+        .await
+    }
+
+    /// Async version of [`WayshotConnection::screenshot_region`].
+    pub async fn screenshot_region_async(
+        &self,
+        region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_async(region, cursor_overlay).await
+    }
+
+    /// Async version of [`WayshotConnection::screenshot_all`].
+    pub async fn screenshot_all_async(&self, cursor_overlay: bool) -> Result<DynamicImage> {
+        self.screenshot_outputs_async(self.get_all_outputs(), cursor_overlay)
+            .await
+    }
+}
+
+fn backend_error(e: WaylandError) -> Error {
+    Error::Dispatch(DispatchError::Backend(e))
+}
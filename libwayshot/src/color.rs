@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+
+use image::ColorType;
+use wayland_client::protocol::wl_shm;
+
+use crate::{
+    Error, Result,
+    convert::create_converter,
+    image_util::write_rgba,
+    region::{EmbeddedRegion, LogicalRegion},
+    screencopy::{FrameData, FrozenFrame, buffer_region, read_gbm_bo},
+};
+
+/// Average colour of a region of the screen, as returned by
+/// [`crate::WayshotConnection::pick_color`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PickedColor {
+    /// Red, green, blue and alpha scaled to 16 bits, encoded like the captured buffer.
+    pub rgba: [u16; 4],
+    /// Bits per channel of the captured pixel format, e.g. 10 for 2101010 formats.
+    pub bit_depth: u32,
+}
+
+impl PickedColor {
+    /// Average the pixels of `frames` inside `region`.
+    ///
+    /// All buffer pixels covering `region` are taken into account, so on scaled outputs a single
+    /// logical pixel averages several physical ones.
+    pub fn from_frames(frames: &[FrozenFrame<'_>], region: LogicalRegion) -> Result<Self> {
+        let mut sum = [0u64; 4];
+        let mut count = 0u64;
+        let mut bit_depth = 0;
+        for FrozenFrame { frame, .. } in frames {
+            let Some(embedded_region) = EmbeddedRegion::new(region, frame.logical_region) else {
+                continue;
+            };
+            let frame_format = &frame.frame_format;
+            let converter = create_converter(frame_format.format).ok_or_else(|| {
+                tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
+                Error::NoSupportedBufferFormat
+            })?;
+            let bytes_per_pixel = frame_format.bytes_per_pixel() as usize;
+            let (data, stride) = match &frame.frame_data {
+                FrameData::Mmap(mmap) => (Cow::Borrowed(&mmap[..]), frame_format.stride as usize),
+                FrameData::GBMBo(bo) => (
+                    Cow::Owned(read_gbm_bo(bo, frame_format.size, bytes_per_pixel as u32)?),
+                    frame_format.size.width as usize * bytes_per_pixel,
+                ),
+            };
+
+            let region = buffer_region(embedded_region, frame_format, frame.transform);
+            let (x, y) = (region.position.x as usize, region.position.y as usize);
+            let width = region.size.width as usize;
+            let color_type = converter.color_type();
+            let mut converted = vec![0; width * color_type.bytes_per_pixel() as usize];
+            let mut pixels = vec![0u16; width * 4];
+            for row in y..y + region.size.height as usize {
+                let start = row * stride + x * bytes_per_pixel;
+                let src = data
+                    .get(start..start + width * bytes_per_pixel)
+                    .ok_or(Error::BufferTooSmall)?;
+                converter.convert_row(src, &mut converted);
+                write_rgba(color_type, &converted, &mut pixels);
+                for pixel in pixels.chunks_exact(4) {
+                    for (sum, channel) in sum.iter_mut().zip(pixel) {
+                        *sum += *channel as u64;
+                    }
+                }
+                count += width as u64;
+            }
+            bit_depth = bit_depth.max(format_bit_depth(frame_format.format, color_type));
+        }
+
+        if count == 0 {
+            tracing::error!("Region {region} doesn't intersect with any frame");
+            return Err(Error::NoOutputs);
+        }
+        Ok(Self {
+            rgba: sum.map(|sum| ((sum + count / 2) / count) as u16),
+            bit_depth,
+        })
+    }
+
+    /// The colour with 8 bits per channel, which is sRGB for SDR outputs.
+    pub fn srgb8(&self) -> [u8; 4] {
+        self.rgba.map(|channel| rescale(channel, 8) as u8)
+    }
+
+    /// The colour at the bit depth it was captured with, e.g. in `0..=1023` for 10-bit formats.
+    pub fn raw(&self) -> [u16; 4] {
+        self.rgba.map(|channel| rescale(channel, self.bit_depth))
+    }
+
+    /// The colour in linear light with channels in `[0, 1]`, assuming the captured buffer is sRGB
+    /// encoded. Alpha is returned as it is.
+    pub fn linear(&self) -> [f32; 4] {
+        let [r, g, b, a] = self.rgba.map(|channel| channel as f32 / u16::MAX as f32);
+        let decode = |value: f32| {
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        [decode(r), decode(g), decode(b), a]
+    }
+}
+
+/// Scale a 16-bit channel to `bits` bits, rounding to the nearest value.
+fn rescale(channel: u16, bits: u32) -> u16 {
+    let max = (1u32 << bits.clamp(1, 16)) - 1;
+    ((channel as u32 * max + u16::MAX as u32 / 2) / u16::MAX as u32) as u16
+}
+
+/// Bits per color channel of `format`, converted to `color_type`.
+fn format_bit_depth(format: wl_shm::Format, color_type: ColorType) -> u32 {
+    use wl_shm::Format::*;
+    match format {
+        Xrgb2101010 | Argb2101010 | Xbgr2101010 | Abgr2101010 | Rgbx1010102 | Rgba1010102 => 10,
+        _ => color_type.bits_per_pixel() as u32 / color_type.channel_count() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rescale_known_values() {
+        assert_eq!(rescale(0, 8), 0);
+        assert_eq!(rescale(u16::MAX, 8), 255);
+        assert_eq!(rescale(0x8080, 8), 128);
+        assert_eq!(rescale(u16::MAX, 10), 1023);
+        assert_eq!(rescale(u16::MAX, 16), u16::MAX);
+        assert_eq!(rescale(0x1234, 16), 0x1234);
+        // Bit depths out of range are clamped.
+        assert_eq!(rescale(u16::MAX, 0), 1);
+        assert_eq!(rescale(0x1234, 32), 0x1234);
+    }
+
+    #[test]
+    fn raw_round_trips_10_bit_channels() {
+        for value in 0..1024u16 {
+            // Expanded to 16 bits like the converters do, by repeating the high bits.
+            let expanded = (value << 6) | (value >> 4);
+            let color = PickedColor {
+                rgba: [expanded, 0, u16::MAX, u16::MAX],
+                bit_depth: 10,
+            };
+            assert_eq!(color.raw(), [value, 0, 1023, 1023]);
+        }
+    }
+
+    #[test]
+    fn linear_known_values() {
+        let color = |channel: u16| PickedColor {
+            rgba: [channel, channel, channel, 0x8000],
+            bit_depth: 8,
+        };
+        let assert_linear = |channel: u16, expected: f32| {
+            let linear = color(channel).linear();
+            for value in &linear[..3] {
+                assert!(
+                    (value - expected).abs() < 1e-5,
+                    "{channel}: {value} != {expected}"
+                );
+            }
+            assert_eq!(linear[3], 0x8000 as f32 / u16::MAX as f32);
+        };
+        assert_linear(0, 0.0);
+        assert_linear(u16::MAX, 1.0);
+        // sRGB 128 is about 21.6% of the light of white.
+        assert_linear(128 * 257, 0.215_861);
+        // Dark values are on the linear segment of the transfer function.
+        assert_linear(10 * 257, 10.0 / 255.0 / 12.92);
+
+        let primary = PickedColor {
+            rgba: [u16::MAX, 0, 0, u16::MAX],
+            bit_depth: 8,
+        };
+        assert_eq!(primary.linear(), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(primary.raw(), [255, 0, 0, 255]);
+        assert_eq!(primary.srgb8(), [255, 0, 0, 255]);
+    }
+}
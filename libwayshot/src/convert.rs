@@ -1,48 +1,503 @@
+//! Conversion of raw wl_shm pixel data into pixel layouts the `image` crate understands.
+//!
+//! Every supported [`wl_shm::Format`] has a [`Convert`] implementation that turns one row of the
+//! captured buffer into one row of an image with the converter's [`ColorType`]. Converters for
+//! formats libwayshot does not know about, or replacements for the built-in ones, can be added
+//! with [`register_converter`].
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
 use image::ColorType;
 use wayland_client::protocol::wl_shm;
 
-pub trait Convert {
-    /// Convert raw image data into output type, return said type
-    fn convert_inplace(&self, data: &mut [u8]) -> ColorType;
-}
+pub trait Convert: Send + Sync {
+    /// Color type of the converted pixels.
+    fn color_type(&self) -> ColorType;
 
-#[derive(Default)]
-struct ConvertNone {}
+    /// Convert one row of pixels.
+    ///
+    /// `src` holds the row in the wl_shm format without any stride padding, `dst` has room for
+    /// the same number of pixels in [`Convert::color_type`]. Channels wider than a byte are
+    /// written in native endianness.
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]);
+}
 
-#[derive(Default)]
-struct ConvertRGB8 {}
+static CONVERTERS: LazyLock<RwLock<HashMap<wl_shm::Format, Arc<dyn Convert>>>> =
+    LazyLock::new(Default::default);
 
-#[derive(Default)]
-struct ConvertBGR888 {}
+/// Use `converter` for frames captured in `format`, replacing any previously registered or
+/// built-in converter for it.
+///
+/// Captures only pick wl_shm formats a converter exists for, so registering a converter also
+/// makes libwayshot accept buffers in that format.
+pub fn register_converter(format: wl_shm::Format, converter: impl Convert + 'static) {
+    CONVERTERS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(format, Arc::new(converter));
+}
 
 /// Creates format converter based of input format, return None if conversion
-/// isn't possible. Conversion is happening inplace.
-pub fn create_converter(format: wl_shm::Format) -> Option<Box<dyn Convert>> {
-    match format {
-        wl_shm::Format::Xbgr8888 | wl_shm::Format::Abgr8888 => Some(Box::<ConvertNone>::default()),
-        wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888 => Some(Box::<ConvertRGB8>::default()),
-        wl_shm::Format::Bgr888 => Some(Box::<ConvertBGR888>::default()),
-        _ => None,
+/// isn't possible.
+pub fn create_converter(format: wl_shm::Format) -> Option<Arc<dyn Convert>> {
+    if let Some(converter) = CONVERTERS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&format)
+    {
+        return Some(converter.clone());
     }
+
+    use wl_shm::Format::*;
+    Some(match format {
+        Abgr8888 => Arc::new(Convert8888 {
+            order: ChannelOrder::Rgb,
+            alpha: true,
+        }),
+        Xbgr8888 => Arc::new(Convert8888 {
+            order: ChannelOrder::Rgb,
+            alpha: false,
+        }),
+        Argb8888 => Arc::new(Convert8888 {
+            order: ChannelOrder::Bgr,
+            alpha: true,
+        }),
+        Xrgb8888 => Arc::new(Convert8888 {
+            order: ChannelOrder::Bgr,
+            alpha: false,
+        }),
+        Bgr888 => Arc::new(Convert888 {
+            order: ChannelOrder::Rgb,
+        }),
+        Rgb888 => Arc::new(Convert888 {
+            order: ChannelOrder::Bgr,
+        }),
+        Rgb565 => Arc::new(Convert565 {
+            order: ChannelOrder::Rgb,
+        }),
+        Bgr565 => Arc::new(Convert565 {
+            order: ChannelOrder::Bgr,
+        }),
+        Xrgb2101010 => Arc::new(Convert2101010 {
+            order: ChannelOrder::Rgb,
+            alpha: false,
+        }),
+        Argb2101010 => Arc::new(Convert2101010 {
+            order: ChannelOrder::Rgb,
+            alpha: true,
+        }),
+        Xbgr2101010 => Arc::new(Convert2101010 {
+            order: ChannelOrder::Bgr,
+            alpha: false,
+        }),
+        Abgr2101010 => Arc::new(Convert2101010 {
+            order: ChannelOrder::Bgr,
+            alpha: true,
+        }),
+        Rgbx1010102 => Arc::new(Convert1010102 { alpha: false }),
+        Rgba1010102 => Arc::new(Convert1010102 { alpha: true }),
+        Xrgb16161616 => Arc::new(Convert16161616 {
+            order: ChannelOrder::Bgr,
+            alpha: false,
+            float: false,
+        }),
+        Argb16161616 => Arc::new(Convert16161616 {
+            order: ChannelOrder::Bgr,
+            alpha: true,
+            float: false,
+        }),
+        Xbgr16161616 => Arc::new(Convert16161616 {
+            order: ChannelOrder::Rgb,
+            alpha: false,
+            float: false,
+        }),
+        Abgr16161616 => Arc::new(Convert16161616 {
+            order: ChannelOrder::Rgb,
+            alpha: true,
+            float: false,
+        }),
+        Xbgr16161616f => Arc::new(Convert16161616 {
+            order: ChannelOrder::Rgb,
+            alpha: false,
+            float: true,
+        }),
+        Abgr16161616f => Arc::new(Convert16161616 {
+            order: ChannelOrder::Rgb,
+            alpha: true,
+            float: true,
+        }),
+        _ => return None,
+    })
 }
 
-impl Convert for ConvertNone {
-    fn convert_inplace(&self, _data: &mut [u8]) -> ColorType {
+/// Whether the first color channel of a pixel is red or blue. Which channel counts as the first
+/// depends on the layout of the format, see the `order` field of the converters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ChannelOrder {
+    Rgb,
+    Bgr,
+}
+
+impl ChannelOrder {
+    fn rgb<T>(self, first: T, second: T, third: T) -> [T; 3] {
+        match self {
+            ChannelOrder::Rgb => [first, second, third],
+            ChannelOrder::Bgr => [third, second, first],
+        }
+    }
+}
+
+/// 32-bit formats with 8-bit channels, converted to `Rgba8`.
+struct Convert8888 {
+    /// Channel order from the lowest address up.
+    order: ChannelOrder,
+    alpha: bool,
+}
+
+impl Convert for Convert8888 {
+    fn color_type(&self) -> ColorType {
         ColorType::Rgba8
     }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+            let [r, g, b] = self.order.rgb(src[0], src[1], src[2]);
+            // The padding byte of X formats is undefined.
+            let a = if self.alpha { src[3] } else { u8::MAX };
+            dst.copy_from_slice(&[r, g, b, a]);
+        }
+    }
+}
+
+/// 24-bit formats, converted to `Rgb8`.
+struct Convert888 {
+    /// Channel order from the lowest address up.
+    order: ChannelOrder,
 }
 
-impl Convert for ConvertRGB8 {
-    fn convert_inplace(&self, data: &mut [u8]) -> ColorType {
-        for chunk in data.chunks_exact_mut(4) {
-            chunk.swap(0, 2);
+impl Convert for Convert888 {
+    fn color_type(&self) -> ColorType {
+        ColorType::Rgb8
+    }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        for (src, dst) in src.chunks_exact(3).zip(dst.chunks_exact_mut(3)) {
+            dst.copy_from_slice(&self.order.rgb(src[0], src[1], src[2]));
         }
-        ColorType::Rgba8
     }
 }
 
-impl Convert for ConvertBGR888 {
-    fn convert_inplace(&self, _data: &mut [u8]) -> ColorType {
+/// 16-bit formats with 5, 6 and 5 bits per channel, converted to `Rgb8`.
+struct Convert565 {
+    /// Channel order from the most significant bits down.
+    order: ChannelOrder,
+}
+
+impl Convert for Convert565 {
+    fn color_type(&self) -> ColorType {
         ColorType::Rgb8
     }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        for (src, dst) in src.chunks_exact(2).zip(dst.chunks_exact_mut(3)) {
+            let pixel = u16::from_le_bytes([src[0], src[1]]);
+            let low = expand_bits(pixel & 0x1f, 5);
+            let middle = expand_bits((pixel >> 5) & 0x3f, 6);
+            let high = expand_bits(pixel >> 11, 5);
+            let color = self.order.rgb(high, middle, low).map(|c| (c >> 8) as u8);
+            dst.copy_from_slice(&color);
+        }
+    }
+}
+
+/// 32-bit formats with 10-bit color channels in the low bits, converted to `Rgb16` or `Rgba16`.
+struct Convert2101010 {
+    /// Channel order from the most significant bits down.
+    order: ChannelOrder,
+    alpha: bool,
+}
+
+impl Convert for Convert2101010 {
+    fn color_type(&self) -> ColorType {
+        if self.alpha {
+            ColorType::Rgba16
+        } else {
+            ColorType::Rgb16
+        }
+    }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        let channels = if self.alpha { 4 } else { 3 };
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(channels * 2)) {
+            let pixel = u32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+            let high = expand_bits((pixel >> 20) as u16 & 0x3ff, 10);
+            let middle = expand_bits((pixel >> 10) as u16 & 0x3ff, 10);
+            let low = expand_bits(pixel as u16 & 0x3ff, 10);
+            let alpha = expand_bits((pixel >> 30) as u16, 2);
+            write_u16_channels(
+                dst,
+                &self.order.rgb(high, middle, low),
+                self.alpha.then_some(alpha),
+            );
+        }
+    }
+}
+
+/// 32-bit formats with 10-bit color channels in the high bits, converted to `Rgb16` or `Rgba16`.
+struct Convert1010102 {
+    alpha: bool,
+}
+
+impl Convert for Convert1010102 {
+    fn color_type(&self) -> ColorType {
+        if self.alpha {
+            ColorType::Rgba16
+        } else {
+            ColorType::Rgb16
+        }
+    }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        let channels = if self.alpha { 4 } else { 3 };
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(channels * 2)) {
+            let pixel = u32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+            let r = expand_bits((pixel >> 22) as u16 & 0x3ff, 10);
+            let g = expand_bits((pixel >> 12) as u16 & 0x3ff, 10);
+            let b = expand_bits((pixel >> 2) as u16 & 0x3ff, 10);
+            let alpha = expand_bits(pixel as u16 & 0x3, 2);
+            write_u16_channels(dst, &[r, g, b], self.alpha.then_some(alpha));
+        }
+    }
+}
+
+/// 64-bit formats with 16-bit integer or half float channels, converted to `Rgb16` or `Rgba16`.
+///
+/// Half float values are clamped to `[0, 1]`, extended range content is not preserved.
+struct Convert16161616 {
+    /// Channel order from the lowest address up.
+    order: ChannelOrder,
+    alpha: bool,
+    float: bool,
+}
+
+impl Convert16161616 {
+    fn channel(&self, bytes: &[u8]) -> u16 {
+        let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        if self.float {
+            (half_to_f32(value).clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+        } else {
+            value
+        }
+    }
+}
+
+impl Convert for Convert16161616 {
+    fn color_type(&self) -> ColorType {
+        if self.alpha {
+            ColorType::Rgba16
+        } else {
+            ColorType::Rgb16
+        }
+    }
+
+    fn convert_row(&self, src: &[u8], dst: &mut [u8]) {
+        let channels = if self.alpha { 4 } else { 3 };
+        for (src, dst) in src.chunks_exact(8).zip(dst.chunks_exact_mut(channels * 2)) {
+            let color = self.order.rgb(
+                self.channel(&src[0..2]),
+                self.channel(&src[2..4]),
+                self.channel(&src[4..6]),
+            );
+            let alpha = self.channel(&src[6..8]);
+            write_u16_channels(dst, &color, self.alpha.then_some(alpha));
+        }
+    }
+}
+
+fn write_u16_channels(dst: &mut [u8], color: &[u16; 3], alpha: Option<u16>) {
+    for (dst, channel) in dst.chunks_exact_mut(2).zip(color.iter().chain(&alpha)) {
+        dst.copy_from_slice(&channel.to_ne_bytes());
+    }
+}
+
+/// Scale a `bits` wide channel value to 16 bits by repeating its bit pattern.
+fn expand_bits(value: u16, bits: u32) -> u16 {
+    let mut expanded = 0u32;
+    let mut shift = 16i32 - bits as i32;
+    while shift > -(bits as i32) {
+        expanded |= if shift >= 0 {
+            (value as u32) << shift
+        } else {
+            (value as u32) >> -shift
+        };
+        shift -= bits as i32;
+    }
+    expanded as u16
+}
+
+/// Decode an IEEE 754 half precision float.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little endian pixels of a format made of 16-bit words.
+    fn le16(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Little endian pixels of a format made of 32-bit words.
+    fn le32(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Converted 16-bit channels, which are native endian.
+    fn ne16(channels: &[u16]) -> Vec<u8> {
+        channels
+            .iter()
+            .flat_map(|channel| channel.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn convert_known_pixels() {
+        use wl_shm::Format::*;
+        let cases = [
+            (
+                Argb8888,
+                vec![0x30, 0x20, 0x10, 0x80, 0xff, 0x00, 0x00, 0x00],
+                ColorType::Rgba8,
+                vec![0x10, 0x20, 0x30, 0x80, 0x00, 0x00, 0xff, 0x00],
+            ),
+            (
+                Xrgb8888,
+                vec![0x30, 0x20, 0x10, 0x00],
+                ColorType::Rgba8,
+                vec![0x10, 0x20, 0x30, 0xff],
+            ),
+            (
+                Abgr8888,
+                vec![0x10, 0x20, 0x30, 0x80],
+                ColorType::Rgba8,
+                vec![0x10, 0x20, 0x30, 0x80],
+            ),
+            (
+                Xbgr8888,
+                vec![0x10, 0x20, 0x30, 0x12],
+                ColorType::Rgba8,
+                vec![0x10, 0x20, 0x30, 0xff],
+            ),
+            (
+                Rgb888,
+                vec![0x30, 0x20, 0x10],
+                ColorType::Rgb8,
+                vec![0x10, 0x20, 0x30],
+            ),
+            (
+                Bgr888,
+                vec![0x10, 0x20, 0x30],
+                ColorType::Rgb8,
+                vec![0x10, 0x20, 0x30],
+            ),
+            (
+                Rgb565,
+                le16(&[0xf800, 0x07e0, 0x801f]),
+                ColorType::Rgb8,
+                vec![255, 0, 0, 0, 255, 0, 132, 0, 255],
+            ),
+            (
+                Bgr565,
+                le16(&[0xf800, 0x001f]),
+                ColorType::Rgb8,
+                vec![0, 0, 255, 255, 0, 0],
+            ),
+            (
+                // The unused top bits are set and must be ignored.
+                Xrgb2101010,
+                le32(&[0xfff8_0000]),
+                ColorType::Rgb16,
+                ne16(&[0xffff, 0x8020, 0x0000]),
+            ),
+            (
+                Argb2101010,
+                le32(&[0xbff8_0000]),
+                ColorType::Rgba16,
+                ne16(&[0xffff, 0x8020, 0x0000, 0xaaaa]),
+            ),
+            (
+                Abgr2101010,
+                le32(&[0xfff0_0001]),
+                ColorType::Rgba16,
+                ne16(&[0x0040, 0x0000, 0xffff, 0xffff]),
+            ),
+            (
+                Rgba1010102,
+                le32(&[0x003f_f801]),
+                ColorType::Rgba16,
+                ne16(&[0x0000, 0xffff, 0x8020, 0x5555]),
+            ),
+            (
+                Rgbx1010102,
+                le32(&[0x003f_f801]),
+                ColorType::Rgb16,
+                ne16(&[0x0000, 0xffff, 0x8020]),
+            ),
+            (
+                Xrgb16161616,
+                le16(&[0x1111, 0x2222, 0x3333, 0x4444]),
+                ColorType::Rgb16,
+                ne16(&[0x3333, 0x2222, 0x1111]),
+            ),
+            (
+                Abgr16161616,
+                le16(&[0x1111, 0x2222, 0x3333, 0x4444]),
+                ColorType::Rgba16,
+                ne16(&[0x1111, 0x2222, 0x3333, 0x4444]),
+            ),
+            (
+                // 1.0, 0.5, -1.0 and 0.25, negative values are clamped to 0.
+                Abgr16161616f,
+                le16(&[0x3c00, 0x3800, 0xbc00, 0x3400]),
+                ColorType::Rgba16,
+                ne16(&[0xffff, 0x8000, 0x0000, 0x4000]),
+            ),
+            (
+                // 2.0 is clamped to 1.0, the smallest subnormal rounds to 0.
+                Xbgr16161616f,
+                le16(&[0x4000, 0x0001, 0x3c00, 0x0000]),
+                ColorType::Rgb16,
+                ne16(&[0xffff, 0x0000, 0xffff]),
+            ),
+        ];
+
+        for (format, src, color_type, expected) in cases {
+            let converter = create_converter(format).unwrap();
+            assert_eq!(converter.color_type(), color_type, "{format:?}");
+            let mut dst = vec![0; expected.len()];
+            converter.convert_row(&src, &mut dst);
+            assert_eq!(dst, expected, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn expand_bits_fills_the_range() {
+        assert_eq!(expand_bits(0, 10), 0);
+        assert_eq!(expand_bits(0x3ff, 10), u16::MAX);
+        assert_eq!(expand_bits(0x1f, 5), u16::MAX);
+        assert_eq!(expand_bits(0b01, 2), 0x5555);
+    }
 }
@@ -0,0 +1,215 @@
+//! Capturing the pointer cursor as its own image through ext-image-copy-capture cursor sessions.
+//!
+//! Frames captured without `cursor_overlay` don't show the cursor. A [`CursorCapture`] holds
+//! the cursor image together with where it was on the output, so it can be composited onto such
+//! frames later, scaled or highlighted.
+
+use std::fs::File;
+
+use memmap2::MmapMut;
+use wayland_client::{
+    EventQueue, Proxy,
+    protocol::{wl_output::Transform, wl_seat, wl_seat::WlSeat},
+};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+        ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+    },
+};
+
+use crate::{
+    Error, Result, WayshotConnection, WayshotFrame,
+    convert::create_converter,
+    dispatch::CursorCaptureState,
+    image_util::transform_region,
+    output::OutputInfo,
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{
+        FrameCopy, FrameData, FrameGuard, create_shm_fd, damage_regions, select_shm_format,
+    },
+};
+
+/// The pointer cursor over an output, captured separately from the output's frames.
+#[derive(Debug)]
+pub struct CursorCapture {
+    /// The cursor image. Its `logical_region` is where the image covers the output in the global
+    /// logical coordinate space.
+    pub image: FrameCopy,
+    /// Offset of the pointer position from the top left corner of the image, in the pixels of the
+    /// image with its transform applied.
+    pub hotspot: Position,
+    /// Position of the hotspot relative to the top left corner of the output, in the pixels of a
+    /// frame captured from the output with its transform applied. It can lie outside of the
+    /// output when only part of the cursor image covers it.
+    pub position: Position,
+}
+
+impl WayshotConnection {
+    /// Capture the pointer cursor over `output_info` as its own image.
+    ///
+    /// Returns `None` when the seat has no pointer or the cursor is not over the output.
+    /// Requires the ext-image-copy-capture protocol.
+    pub fn capture_cursor(&self, output_info: &OutputInfo) -> Result<Option<CursorCapture>> {
+        let mut state = CursorCaptureState::default();
+        let mut event_queue = self.conn.new_event_queue::<CursorCaptureState>();
+        let qh = event_queue.handle();
+
+        let manager = self
+            .globals
+            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
+            .map_err(|e| {
+                tracing::error!("Cursor captures require ext-image-copy-capture: {e}");
+                Error::ProtocolNotFound("ExtImageCopyCaptureManagerV1 not found".to_string())
+            })?;
+        let seat = self.globals.bind::<WlSeat, _, _>(&qh, 1..=3, ())?;
+        event_queue.roundtrip(&mut state)?;
+        if !state
+            .seat_capabilities
+            .is_some_and(|capabilities| capabilities.contains(wl_seat::Capability::Pointer))
+        {
+            tracing::debug!("Seat has no pointer, there is no cursor to capture");
+            return Ok(None);
+        }
+
+        let pointer = seat.get_pointer(&qh, ());
+        let source = self
+            .globals
+            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())?
+            .create_source(&output_info.wl_output, &qh, ());
+        let cursor_session = manager.create_pointer_cursor_session(&source, &pointer, &qh, ());
+        let session = cursor_session.get_capture_session(&qh, ());
+
+        let result = self.capture_cursor_image(&mut state, &mut event_queue, &session, output_info);
+
+        session.destroy();
+        cursor_session.destroy();
+        source.destroy();
+        if pointer.version() >= 3 {
+            pointer.release();
+        }
+        result
+    }
+
+    /// Capture the frame of `output_info` without the cursor, then the cursor as its own image.
+    ///
+    /// These are two captures one after the other, not one synchronised capture: the cursor may
+    /// have moved or changed its image in between, so its position can disagree with the frame.
+    pub fn capture_frame_copy_then_cursor(
+        &self,
+        output_info: &OutputInfo,
+    ) -> Result<(FrameCopy, FrameGuard, Option<CursorCapture>)> {
+        let (frame_copy, frame_guard) = self.capture_frame_copy(false, output_info, None)?;
+        let cursor = self.capture_cursor(output_info)?;
+        Ok((frame_copy, frame_guard, cursor))
+    }
+
+    fn capture_cursor_image(
+        &self,
+        state: &mut CursorCaptureState,
+        event_queue: &mut EventQueue<CursorCaptureState>,
+        session: &ExtImageCopyCaptureSessionV1,
+        output_info: &OutputInfo,
+    ) -> Result<Option<CursorCapture>> {
+        while !state.frame.session_done && !state.frame.session_stopped {
+            event_queue.blocking_dispatch(state)?;
+        }
+        // The enter and position events may come after the buffer constraints.
+        event_queue.roundtrip(state)?;
+        if state.frame.session_stopped {
+            return Err(Error::SessionStopped);
+        }
+        let (true, Some(position)) = (state.entered, state.position) else {
+            tracing::debug!("Cursor is not over {output_info}");
+            return Ok(None);
+        };
+
+        let frame_format = select_shm_format(&state.frame.formats)?;
+        let mem_file = File::from(create_shm_fd()?);
+        mem_file.set_len(frame_format.byte_size())?;
+        let qh = event_queue.handle();
+        let frame = WayshotFrame::ExtImageCopy(session.create_frame(&qh, ()));
+        let _frame_guard = self.attach_shm_buffer(&qh, &frame, frame_format, &mem_file)?;
+        let result = loop {
+            if let Some(result) = state.frame.frame_result() {
+                break result;
+            }
+            event_queue.blocking_dispatch(state)?;
+        };
+        if let WayshotFrame::ExtImageCopy(frame) = &frame {
+            frame.destroy();
+        }
+        result?;
+        let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+
+        // The position and the hotspot are in buffer coordinates, of the output and of the cursor
+        // image respectively. Both are moved to where the transform puts them, like
+        // `PhysicalRegion::to_logical` does, before scaling them to logical coordinates.
+        let transform = state.frame.transform.unwrap_or(Transform::Normal);
+        let pixel = |position: Position| Region {
+            position,
+            size: Size {
+                width: 1,
+                height: 1,
+            },
+        };
+        let position = transform_region(
+            pixel(position),
+            output_info.physical_size,
+            output_info.transform,
+        )
+        .position;
+        // The hotspot sent with the frame applies to it, so it is only read now.
+        let hotspot = transform_region(pixel(state.hotspot), frame_format.size, transform).position;
+        let physical_size = transform_region(
+            Region {
+                position: Position::default(),
+                size: frame_format.size,
+            },
+            frame_format.size,
+            transform,
+        )
+        .size;
+
+        let scale = output_info.scale();
+        let output_position = output_info.logical_region.inner.position;
+        let to_logical = |pixels: i32| (pixels as f64 / scale).round() as i32;
+        let logical_region = LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: output_position.x + to_logical(position.x - hotspot.x),
+                    y: output_position.y + to_logical(position.y - hotspot.y),
+                },
+                size: Size {
+                    width: to_logical(physical_size.width as i32).max(1) as u32,
+                    height: to_logical(physical_size.height as i32).max(1) as u32,
+                },
+            },
+        };
+        let frame_color_type = create_converter(frame_format.format)
+            .ok_or(Error::NoSupportedBufferFormat)?
+            .color_type();
+        let image = FrameCopy {
+            frame_format,
+            frame_color_type,
+            frame_data: FrameData::Mmap(frame_mmap),
+            transform,
+            logical_region,
+            physical_size,
+            damage: damage_regions(
+                state.frame.damage.as_deref(),
+                &frame_format,
+                transform,
+                logical_region,
+            ),
+        };
+        tracing::debug!("Captured cursor at {position:?} with hotspot {hotspot:?}");
+
+        Ok(Some(CursorCapture {
+            image,
+            hotspot,
+            position,
+        }))
+    }
+}
@@ -12,7 +12,9 @@ use wayland_client::{
         wl_buffer::WlBuffer,
         wl_compositor::WlCompositor,
         wl_output::{self, WlOutput},
+        wl_pointer::WlPointer,
         wl_registry::{self, WlRegistry},
+        wl_seat::{self, WlSeat},
         wl_shm::WlShm,
         wl_shm_pool::WlShmPool,
         wl_surface::WlSurface,
@@ -20,17 +22,14 @@ use wayland_client::{
 };
 use wayland_protocols::{
     ext::{
-        foreign_toplevel_list::v1::client::{
-            ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
-            ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
-        },
         image_capture_source::v1::client::ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
         image_capture_source::v1::client::{
             ext_image_capture_source_v1::ExtImageCaptureSourceV1,
             ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
         },
         image_copy_capture::v1::client::{
-            ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+            ext_image_copy_capture_cursor_session_v1::{self, ExtImageCopyCaptureCursorSessionV1},
+            ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1, FailureReason},
             ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
             ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
         },
@@ -59,9 +58,11 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
+    Error, Result,
+    dmabuf::DmabufFeedback,
     output::OutputInfo,
-    region::{LogicalRegion, Position, Size, TopLevel},
-    screencopy::{DMAFrameFormat, FrameFormat},
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{BufferConstraints, DMAFrameFormat, DmabufFormatModifiers, FrameFormat},
 };
 
 #[derive(Debug)]
@@ -99,6 +100,8 @@ impl Dispatch<WlRegistry, ()> for OutputCaptureState {
                     transform: wl_output::Transform::Normal,
                     physical_size: Size::default(),
                     logical_region: LogicalRegion::default(),
+                    integer_scale: 1,
+                    preferred_scale: None,
                 });
             } else {
                 tracing::error!("Ignoring a wl_output with version < 4.");
@@ -135,7 +138,12 @@ impl Dispatch<WlOutput, ()> for OutputCaptureState {
             wl_output::Event::Description { description } => {
                 output.description = description;
             }
-            wl_output::Event::Mode { width, height, .. } => {
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(wl_output::Mode::Current) => {
                 output.physical_size = Size {
                     width: width as u32,
                     height: height as u32,
@@ -147,7 +155,9 @@ impl Dispatch<WlOutput, ()> for OutputCaptureState {
             } => {
                 output.transform = transform;
             }
-            wl_output::Event::Scale { .. } => {}
+            wl_output::Event::Scale { factor } => {
+                output.integer_scale = factor;
+            }
             wl_output::Event::Done => {}
             _ => {}
         }
@@ -203,12 +213,205 @@ pub enum FrameState {
     Finished,
 }
 
+#[derive(Default)]
 pub struct CaptureFrameState {
     pub formats: Vec<FrameFormat>,
     pub dmabuf_formats: Vec<DMAFrameFormat>,
     pub state: Option<FrameState>,
     pub buffer_done: AtomicBool,
-    pub toplevels: Vec<TopLevel>,
+    /// Damage reported for the current frame, in buffer coordinates. `None` when the compositor
+    /// did not report any, which means the whole frame may have changed.
+    pub damage: Option<Vec<Region>>,
+    /// Transform of the buffer contents reported with an ext-image-copy-capture frame.
+    pub(crate) transform: Option<wl_output::Transform>,
+    /// Set once an ext-image-copy-capture session sent its `done` event.
+    pub session_done: bool,
+    /// The last complete set of buffer constraints of an ext-image-copy-capture session.
+    pub constraints: BufferConstraints,
+    /// Set whenever a new set of constraints arrived, consumers reset it once they adapted.
+    pub(crate) constraints_changed: bool,
+    /// Constraints of a batch that has not been terminated by `done` yet.
+    pub(crate) pending_constraints: Option<BufferConstraints>,
+    /// The ext-image-copy-capture session was stopped and cannot capture any more frames.
+    pub session_stopped: bool,
+    /// Why the compositor failed the last ext-image-copy-capture frame.
+    pub(crate) failure_reason: Option<FailureReason>,
+}
+
+impl CaptureFrameState {
+    /// Forget everything about the previous frame before capturing a new one.
+    pub(crate) fn reset_frame(&mut self) {
+        self.state = None;
+        self.failure_reason = None;
+        self.damage = None;
+        self.transform = None;
+    }
+
+    /// Outcome of the pending frame copy, `None` while the compositor is still copying.
+    pub(crate) fn frame_result(&self) -> Option<Result<()>> {
+        if self.state.is_none() && self.session_stopped {
+            tracing::error!("Capture session stopped before the frame was copied");
+            return Some(Err(Error::SessionStopped));
+        }
+        match self.state? {
+            FrameState::Failed if self.failure_reason == Some(FailureReason::Stopped) => {
+                tracing::error!("Frame copy failed, the capture session was stopped");
+                Some(Err(Error::SessionStopped))
+            }
+            FrameState::Failed => {
+                tracing::error!("Frame copy failed");
+                Some(Err(Error::FramecopyFailed))
+            }
+            FrameState::Finished => {
+                tracing::trace!("Frame copy finished");
+                Some(Ok(()))
+            }
+        }
+    }
+
+    /// Handle an event of the ext-image-copy-capture frame this state tracks.
+    pub(crate) fn handle_ext_frame_event(&mut self, event: ext_image_copy_capture_frame_v1::Event) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Ready => {
+                self.buffer_done.store(true, Ordering::Relaxed);
+                self.state = Some(FrameState::Finished);
+            }
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                tracing::debug!("Frame capture failed: {reason:?}");
+                self.buffer_done.store(true, Ordering::Relaxed);
+                self.failure_reason = reason.into_result().ok();
+                self.state = Some(FrameState::Failed);
+            }
+            ext_image_copy_capture_frame_v1::Event::Transform {
+                transform: WEnum::Value(transform),
+            } => self.transform = Some(transform),
+            ext_image_copy_capture_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                self.damage.get_or_insert_default().push(Region {
+                    position: Position { x, y },
+                    size: Size {
+                        width: width as u32,
+                        height: height as u32,
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle an event of the ext-image-copy-capture session this state tracks.
+    pub(crate) fn handle_session_event(&mut self, event: ext_image_copy_capture_session_v1::Event) {
+        // Every batch of constraints replaces the previous one completely.
+        let pending = self
+            .pending_constraints
+            .get_or_insert_with(Default::default);
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                pending.size = Size { width, height };
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format } => match format {
+                WEnum::Value(format) => pending.shm_formats.push(format),
+                WEnum::Unknown(format) => {
+                    tracing::debug!("Ignoring unknown shm format {format:#x}");
+                }
+            },
+            ext_image_copy_capture_session_v1::Event::DmabufDevice { device } => {
+                pending.dmabuf_device = device.try_into().ok().map(u64::from_ne_bytes);
+            }
+            ext_image_copy_capture_session_v1::Event::DmabufFormat { format, modifiers } => {
+                pending.dmabuf_formats.push(DmabufFormatModifiers {
+                    format,
+                    modifiers: modifiers
+                        .chunks_exact(8)
+                        .map(|modifier| {
+                            u64::from_ne_bytes(modifier.try_into().expect("chunk of 8 bytes"))
+                        })
+                        .collect(),
+                });
+            }
+            ext_image_copy_capture_session_v1::Event::Done => {
+                let constraints = self.pending_constraints.take().unwrap_or_default();
+                tracing::debug!("Received session buffer constraints: {:#?}", constraints);
+                self.formats = constraints.shm_frame_formats();
+                self.dmabuf_formats = constraints.dmabuf_frame_formats();
+                self.constraints = constraints;
+                self.constraints_changed = true;
+                self.session_done = true;
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                tracing::debug!("Capture session stopped by the compositor");
+                self.pending_constraints = None;
+                self.session_stopped = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle an event of the wlr-screencopy frame this state tracks.
+    pub(crate) fn handle_wlr_frame_event(&mut self, event: zwlr_screencopy_frame_v1::Event) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format,
+                width,
+                height,
+                stride,
+            } => {
+                if let Value(f) = format {
+                    tracing::debug!("Received Buffer event with format: {f:?}");
+                    self.formats.push(FrameFormat {
+                        format: f,
+                        size: Size { width, height },
+                        stride,
+                    })
+                } else {
+                    tracing::debug!("Received Buffer event with unidentified format");
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                // If the frame is successfully copied, a “flags” and a “ready” events are sent. Otherwise, a “failed” event is sent.
+                // This is useful when we call .copy on the frame object.
+                self.state.replace(FrameState::Finished);
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                self.state.replace(FrameState::Failed);
+            }
+            zwlr_screencopy_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                self.damage.get_or_insert_default().push(Region {
+                    position: Position {
+                        x: x as i32,
+                        y: y as i32,
+                    },
+                    size: Size { width, height },
+                });
+            }
+            zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
+                format,
+                width,
+                height,
+            } => {
+                tracing::debug!(
+                    "Received wlr-screencopy linux_dmabuf event with format: {format} and size {width}x{height}"
+                );
+                self.dmabuf_formats.push(DMAFrameFormat {
+                    format,
+                    size: Size { width, height },
+                });
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => {
+                self.buffer_done.store(true, Ordering::SeqCst);
+            }
+            _ => {}
+        };
+    }
 }
 
 impl Dispatch<ZwpLinuxDmabufV1, ()> for CaptureFrameState {
@@ -244,22 +447,12 @@ impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for CaptureFrameState {
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        match event {
-            ext_image_copy_capture_frame_v1::Event::Ready => {
-                state.buffer_done.store(true, Ordering::Relaxed);
-                state.state = Some(FrameState::Finished);
-            }
-            ext_image_copy_capture_frame_v1::Event::Failed { .. } => {
-                state.buffer_done.store(true, Ordering::Relaxed);
-                state.state = Some(FrameState::Failed);
-            }
-            ext_image_copy_capture_frame_v1::Event::Transform { .. } => {}
-            _ => {}
-        }
+        state.handle_ext_frame_event(event);
     }
 }
 
 impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
+    #[tracing::instrument(skip(state, _proxy), level = "trace")]
     fn event(
         state: &mut Self,
         _proxy: &ExtImageCopyCaptureSessionV1,
@@ -268,165 +461,211 @@ impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        if state.formats.is_empty() {
-            state.formats.push(FrameFormat {
-                format: wayland_client::protocol::wl_shm::Format::Rgb888A8,
-                size: Size {
-                    width: 0,
-                    height: 0,
-                },
-                stride: 0,
-            });
-        }
-        match event {
-            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
-                let format = state.formats.first_mut().unwrap();
-                format.size = Size { width, height };
-                format.stride = 4 * width;
-                for DMAFrameFormat {
-                    size:
-                        Size {
-                            width: dma_width,
-                            height: dma_height,
-                        },
-                    ..
-                } in &mut state.dmabuf_formats
-                {
-                    *dma_width = width;
-                    *dma_height = height;
-                }
-            }
-            ext_image_copy_capture_session_v1::Event::ShmFormat {
-                format: WEnum::Value(format),
-            } => {
-                let set_format = state.formats.first_mut().unwrap();
-                set_format.format = format;
-                //set_format.format = wayland_client::protocol::wl_shm::Format::Xbgr8888; // <-- For Cosmic
-            }
-            ext_image_copy_capture_session_v1::Event::DmabufFormat { format, .. } => {
-                state.dmabuf_formats.push(DMAFrameFormat {
-                    format,
-                    size: Size {
-                        width: 0,
-                        height: 0,
-                    },
-                });
-            }
-            _ => {}
-        }
+        state.handle_session_event(event);
     }
 }
 
-impl Dispatch<ExtForeignToplevelListV1, ()> for CaptureFrameState {
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for CaptureFrameState {
+    #[tracing::instrument(skip(frame), ret, level = "trace")]
+    fn event(
+        frame: &mut Self,
+        _: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        frame.handle_wlr_frame_event(event);
+    }
+}
+
+delegate_noop!(CaptureFrameState: ignore WlShm);
+delegate_noop!(CaptureFrameState: ignore WlShmPool);
+delegate_noop!(CaptureFrameState: ignore WlBuffer);
+delegate_noop!(CaptureFrameState: ignore ZwlrScreencopyManagerV1);
+delegate_noop!(CaptureFrameState: ignore ExtImageCopyCaptureManagerV1);
+delegate_noop!(CaptureFrameState: ignore ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(CaptureFrameState: ignore ExtImageCaptureSourceV1);
+delegate_noop!(CaptureFrameState: ignore ExtForeignToplevelImageCaptureSourceManagerV1);
+
+/// Every object a frame request and its shm buffer need, with `U` as the user data of the frame
+/// and session objects. Lets the same request code drive [`CaptureFrameState`] and
+/// [`MultiCaptureState`] queues.
+pub(crate) trait CaptureDispatch<U>:
+    Dispatch<ZwlrScreencopyManagerV1, ()>
+    + Dispatch<ZwlrScreencopyFrameV1, U>
+    + Dispatch<ExtImageCopyCaptureManagerV1, ()>
+    + Dispatch<ExtOutputImageCaptureSourceManagerV1, ()>
+    + Dispatch<ExtImageCaptureSourceV1, ()>
+    + Dispatch<ExtImageCopyCaptureSessionV1, U>
+    + Dispatch<ExtImageCopyCaptureFrameV1, U>
+    + Dispatch<WlShm, ()>
+    + Dispatch<WlShmPool, ()>
+    + Dispatch<WlBuffer, ()>
+    + 'static
+{
+}
+
+impl<D, U> CaptureDispatch<U> for D where
+    D: Dispatch<ZwlrScreencopyManagerV1, ()>
+        + Dispatch<ZwlrScreencopyFrameV1, U>
+        + Dispatch<ExtImageCopyCaptureManagerV1, ()>
+        + Dispatch<ExtOutputImageCaptureSourceManagerV1, ()>
+        + Dispatch<ExtImageCaptureSourceV1, ()>
+        + Dispatch<ExtImageCopyCaptureSessionV1, U>
+        + Dispatch<ExtImageCopyCaptureFrameV1, U>
+        + Dispatch<WlShm, ()>
+        + Dispatch<WlShmPool, ()>
+        + Dispatch<WlBuffer, ()>
+        + 'static
+{
+}
+
+/// State of several frames captured on the same event queue. The frame and session objects
+/// carry the index of their [`CaptureFrameState`] as user data.
+#[derive(Default)]
+pub(crate) struct MultiCaptureState {
+    pub frames: Vec<CaptureFrameState>,
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, usize> for MultiCaptureState {
     fn event(
         state: &mut Self,
-        _proxy: &ExtForeignToplevelListV1,
-        event: <ExtForeignToplevelListV1 as wayland_client::Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qhandle: &QueueHandle<Self>,
+        _: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
     ) {
-        use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::Event;
+        state.frames[*index].handle_wlr_frame_event(event);
+    }
+}
 
-        if let Event::Toplevel { toplevel } = event {
-            state.toplevels.push(TopLevel::new(toplevel));
-        }
+impl Dispatch<ExtImageCopyCaptureFrameV1, usize> for MultiCaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureFrameV1,
+        event: ext_image_copy_capture_frame_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.frames[*index].handle_ext_frame_event(event);
     }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, usize> for MultiCaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureSessionV1,
+        event: ext_image_copy_capture_session_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.frames[*index].handle_session_event(event);
+    }
+}
+
+delegate_noop!(MultiCaptureState: ignore WlShm);
+delegate_noop!(MultiCaptureState: ignore WlShmPool);
+delegate_noop!(MultiCaptureState: ignore WlBuffer);
+delegate_noop!(MultiCaptureState: ignore ZwlrScreencopyManagerV1);
+delegate_noop!(MultiCaptureState: ignore ExtImageCopyCaptureManagerV1);
+delegate_noop!(MultiCaptureState: ignore ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(MultiCaptureState: ignore ExtImageCaptureSourceV1);
 
-    wayland_client::event_created_child!(CaptureFrameState, ExtForeignToplevelHandleV1, [
-        wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ())
-    ]);
+/// State of a capture of the pointer cursor through an ext-image-copy-capture cursor session.
+#[derive(Default)]
+pub(crate) struct CursorCaptureState {
+    /// The session and frame delivering the cursor image.
+    pub frame: CaptureFrameState,
+    pub seat_capabilities: Option<wl_seat::Capability>,
+    /// Whether the cursor is inside the captured output.
+    pub entered: bool,
+    /// Hotspot position relative to the output buffer, in buffer pixels.
+    pub position: Option<Position>,
+    /// Hotspot offset from the top left corner of the cursor image, in buffer pixels.
+    pub hotspot: Position,
 }
 
-impl Dispatch<ExtForeignToplevelHandleV1, ()> for CaptureFrameState {
+impl Dispatch<WlSeat, ()> for CursorCaptureState {
     fn event(
         state: &mut Self,
-        toplevel: &ExtForeignToplevelHandleV1,
-        event: <ExtForeignToplevelHandleV1 as wayland_client::Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qhandle: &QueueHandle<Self>,
+        _: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
     ) {
-        use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::Event;
-
-        if let Some(toplevel_obj) = state.toplevels.iter_mut().find(|t| t.handle == *toplevel) {
-            match event {
-                Event::Title { title } => toplevel_obj.title = title,
-                Event::AppId { app_id } => toplevel_obj.app_id = app_id,
-                Event::Identifier { identifier } => toplevel_obj.identifier = identifier,
-                Event::Closed => toplevel_obj.active = false,
-                _ => {}
-            }
+        if let wl_seat::Event::Capabilities {
+            capabilities: Value(capabilities),
+        } = event
+        {
+            state.seat_capabilities = Some(capabilities);
         }
     }
 }
 
-impl Dispatch<ZwlrScreencopyFrameV1, ()> for CaptureFrameState {
-    #[tracing::instrument(skip(frame), ret, level = "trace")]
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, ()> for CursorCaptureState {
     fn event(
-        frame: &mut Self,
-        _: &ZwlrScreencopyFrameV1,
-        event: zwlr_screencopy_frame_v1::Event,
+        state: &mut Self,
+        _: &ExtImageCopyCaptureCursorSessionV1,
+        event: ext_image_copy_capture_cursor_session_v1::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
         match event {
-            zwlr_screencopy_frame_v1::Event::Buffer {
-                format,
-                width,
-                height,
-                stride,
-            } => {
-                if let Value(f) = format {
-                    tracing::debug!("Received Buffer event with format: {f:?}");
-                    frame.formats.push(FrameFormat {
-                        format: f,
-                        size: Size { width, height },
-                        stride,
-                    })
-                } else {
-                    tracing::debug!("Received Buffer event with unidentified format");
-                }
+            ext_image_copy_capture_cursor_session_v1::Event::Enter => state.entered = true,
+            ext_image_copy_capture_cursor_session_v1::Event::Leave => {
+                state.entered = false;
+                state.position = None;
             }
-            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
-                // If the frame is successfully copied, a “flags” and a “ready” events are sent. Otherwise, a “failed” event is sent.
-                // This is useful when we call .copy on the frame object.
-                frame.state.replace(FrameState::Finished);
-            }
-            zwlr_screencopy_frame_v1::Event::Failed => {
-                frame.state.replace(FrameState::Failed);
+            ext_image_copy_capture_cursor_session_v1::Event::Position { x, y } => {
+                state.position = Some(Position { x, y });
             }
-            zwlr_screencopy_frame_v1::Event::Damage { .. } => {}
-            zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
-                format,
-                width,
-                height,
-            } => {
-                tracing::debug!(
-                    "Received wlr-screencopy linux_dmabuf event with format: {format} and size {width}x{height}"
-                );
-                frame.dmabuf_formats.push(DMAFrameFormat {
-                    format,
-                    size: Size { width, height },
-                });
-            }
-            zwlr_screencopy_frame_v1::Event::BufferDone => {
-                frame.buffer_done.store(true, Ordering::SeqCst);
+            ext_image_copy_capture_cursor_session_v1::Event::Hotspot { x, y } => {
+                state.hotspot = Position { x, y };
             }
             _ => {}
-        };
+        }
     }
 }
 
-delegate_noop!(CaptureFrameState: ignore WlShm);
-delegate_noop!(CaptureFrameState: ignore WlShmPool);
-delegate_noop!(CaptureFrameState: ignore WlBuffer);
-delegate_noop!(CaptureFrameState: ignore ZwlrScreencopyManagerV1);
-delegate_noop!(CaptureFrameState: ignore ExtImageCopyCaptureManagerV1);
-delegate_noop!(CaptureFrameState: ignore ExtOutputImageCaptureSourceManagerV1);
-delegate_noop!(CaptureFrameState: ignore ExtImageCaptureSourceV1);
-delegate_noop!(CaptureFrameState: ignore ExtForeignToplevelImageCaptureSourceManagerV1);
+impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CursorCaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureSessionV1,
+        event: ext_image_copy_capture_session_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.frame.handle_session_event(event);
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for CursorCaptureState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureFrameV1,
+        event: ext_image_copy_capture_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.frame.handle_ext_frame_event(event);
+    }
+}
+
+delegate_noop!(CursorCaptureState: ignore WlPointer);
+delegate_noop!(CursorCaptureState: ignore WlShm);
+delegate_noop!(CursorCaptureState: ignore WlShmPool);
+delegate_noop!(CursorCaptureState: ignore WlBuffer);
+delegate_noop!(CursorCaptureState: ignore ExtImageCopyCaptureManagerV1);
+delegate_noop!(CursorCaptureState: ignore ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(CursorCaptureState: ignore ExtImageCaptureSourceV1);
 
 // TODO: Create a xdg-shell surface, check for the enter event, grab the output from it.
 
@@ -509,4 +748,5 @@ impl Card {
 pub(crate) struct DMABUFState {
     pub linux_dmabuf: ZwpLinuxDmabufV1,
     pub gbmdev: gbm::Device<Card>,
+    pub feedback: Option<DmabufFeedback>,
 }
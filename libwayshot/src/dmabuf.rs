@@ -0,0 +1,601 @@
+//! DMA-BUF format and modifier negotiation.
+//!
+//! The compositor tells us which formats it can copy a frame into, `zwp_linux_dmabuf_v1`
+//! feedback tells us which format/modifier pairs it can import at all and the GBM device decides
+//! what we are able to allocate. [`select_dmabuf_format`] intersects the three and orders the
+//! result by a [`DmabufPreference`].
+
+use std::{fs::File, os::fd::OwnedFd};
+
+use drm::buffer::DrmFourcc;
+use gbm::{BufferObject, BufferObjectFlags, Modifier};
+use memmap2::MmapOptions;
+use wayland_client::{Connection, Dispatch, QueueHandle, WEnum};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_dmabuf_feedback_v1::{self, TrancheFlags, ZwpLinuxDmabufFeedbackV1},
+    zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+};
+
+use crate::{
+    Error, Result,
+    dispatch::Card,
+    screencopy::{DMAFrameFormat, DmabufFormatModifiers},
+};
+
+/// `DRM_FORMAT_MOD_LINEAR`
+const MODIFIER_LINEAR: u64 = 0;
+/// `DRM_FORMAT_MOD_INVALID`, the buffer uses an implicit, driver specific layout.
+const MODIFIER_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// How to pick between the DMA-BUF formats and modifiers that every party supports.
+///
+/// Ties are always broken by the order the compositor advertised the formats in, so the choice
+/// is deterministic for a given compositor and GPU.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DmabufPreference {
+    /// Take the formats in the order the compositor advertised them. The first one usually is
+    /// the format the output is scanned out with.
+    #[default]
+    Native,
+    /// Prefer formats with more than 8 bits per channel, so HDR content is not truncated.
+    HighBitDepth,
+    /// Prefer the linear modifier, which is the cheapest layout to read back on the CPU.
+    Linear,
+}
+
+/// A set of format/modifier pairs the compositor prefers for a device.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct DmabufTranche {
+    pub target_device: Option<u64>,
+    pub formats: Vec<(u32, u64)>,
+    pub scanout: bool,
+}
+
+/// Default `zwp_linux_dmabuf_feedback_v1` of the compositor.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct DmabufFeedback {
+    pub main_device: Option<u64>,
+    /// Tranches in order of decreasing preference.
+    pub tranches: Vec<DmabufTranche>,
+}
+
+impl DmabufFeedback {
+    /// Every modifier the compositor can import `format` with, most preferred first.
+    fn modifiers(&self, format: u32) -> Vec<u64> {
+        let mut modifiers = Vec::new();
+        for (_, modifier) in self
+            .tranches
+            .iter()
+            .flat_map(|tranche| &tranche.formats)
+            .filter(|(tranche_format, _)| *tranche_format == format)
+        {
+            if !modifiers.contains(modifier) {
+                modifiers.push(*modifier);
+            }
+        }
+        modifiers
+    }
+}
+
+#[derive(Default)]
+struct DmabufFeedbackState {
+    format_table: Vec<(u32, u64)>,
+    feedback: DmabufFeedback,
+    pending_tranche: DmabufTranche,
+    done: bool,
+}
+
+/// Read the compositor's default DMA-BUF feedback.
+pub(crate) fn fetch_dmabuf_feedback(
+    conn: &Connection,
+    linux_dmabuf: &ZwpLinuxDmabufV1,
+) -> Result<DmabufFeedback> {
+    let mut state = DmabufFeedbackState::default();
+    let mut event_queue = conn.new_event_queue::<DmabufFeedbackState>();
+    let feedback = linux_dmabuf.get_default_feedback(&event_queue.handle(), ());
+    while !state.done {
+        event_queue.blocking_dispatch(&mut state)?;
+    }
+    feedback.destroy();
+    tracing::debug!("Received DMA-BUF feedback: {:#?}", state.feedback);
+    Ok(state.feedback)
+}
+
+fn read_format_table(fd: OwnedFd, size: u32) -> Result<Vec<(u32, u64)>> {
+    // The table has to be mapped privately, see zwp_linux_dmabuf_feedback_v1::format_table.
+    let table = unsafe {
+        MmapOptions::new()
+            .len(size as usize)
+            .map_copy_read_only(&File::from(fd))?
+    };
+    Ok(table
+        .chunks_exact(16)
+        .map(|entry| {
+            let format = u32::from_ne_bytes(entry[0..4].try_into().expect("4 bytes"));
+            let modifier = u64::from_ne_bytes(entry[8..16].try_into().expect("8 bytes"));
+            (format, modifier)
+        })
+        .collect())
+}
+
+fn parse_dev_t(device: Vec<u8>) -> Option<u64> {
+    device.try_into().ok().map(u64::from_ne_bytes)
+}
+
+impl Dispatch<ZwpLinuxDmabufFeedbackV1, ()> for DmabufFeedbackState {
+    #[tracing::instrument(skip(state, _proxy), level = "trace")]
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwpLinuxDmabufFeedbackV1,
+        event: zwp_linux_dmabuf_feedback_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_linux_dmabuf_feedback_v1::Event::FormatTable { fd, size } => {
+                match read_format_table(fd, size) {
+                    Ok(format_table) => state.format_table = format_table,
+                    Err(e) => tracing::error!("Failed to read DMA-BUF format table: {e}"),
+                }
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::MainDevice { device } => {
+                state.feedback.main_device = parse_dev_t(device);
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::TrancheTargetDevice { device } => {
+                state.pending_tranche.target_device = parse_dev_t(device);
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::TrancheFormats { indices } => {
+                let format_table = &state.format_table;
+                state.pending_tranche.formats.extend(
+                    indices
+                        .chunks_exact(2)
+                        .map(|index| u16::from_ne_bytes([index[0], index[1]]) as usize)
+                        .filter_map(|index| format_table.get(index).copied()),
+                );
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::TrancheFlags { flags } => {
+                state.pending_tranche.scanout = matches!(
+                    flags,
+                    WEnum::Value(flags) if flags.contains(TrancheFlags::Scanout)
+                );
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::TrancheDone => {
+                let tranche = std::mem::take(&mut state.pending_tranche);
+                state.feedback.tranches.push(tranche);
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::Done => {
+                state.done = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A DMA-BUF format the compositor, its importer and the GBM device all support.
+#[derive(Debug, Clone)]
+pub(crate) struct DmabufCandidate {
+    pub frame_format: DMAFrameFormat,
+    /// Explicit modifiers the buffer may be allocated with, most preferred first.
+    pub modifiers: Vec<u64>,
+    /// Whether a buffer with an implicit modifier, as picked by GBM, is acceptable.
+    pub implicit_modifier: bool,
+}
+
+impl DmabufCandidate {
+    fn allows_linear(&self) -> bool {
+        self.modifiers.contains(&MODIFIER_LINEAR)
+            || (self.modifiers.is_empty() && self.implicit_modifier)
+    }
+}
+
+/// Formats storing more than 8 bits per color channel.
+fn is_high_bit_depth(format: u32) -> bool {
+    use DrmFourcc::*;
+    matches!(
+        DrmFourcc::try_from(format),
+        Ok(Xrgb2101010
+            | Xbgr2101010
+            | Rgbx1010102
+            | Bgrx1010102
+            | Argb2101010
+            | Abgr2101010
+            | Rgba1010102
+            | Bgra1010102
+            | Xrgb16161616f
+            | Xbgr16161616f
+            | Argb16161616f
+            | Abgr16161616f)
+    )
+}
+
+/// Pick the DMA-BUF format and modifiers to allocate a frame buffer with.
+///
+/// - `frame_formats`: formats the compositor offered for the frame, in its order.
+/// - `constraints`: modifiers per format of an ext-image-copy-capture session, empty for
+///   wlr-screencopy which does not advertise modifiers.
+/// - `feedback`: the linux-dmabuf feedback of the compositor, if available.
+/// - `gbm_supports`: whether the GBM device can allocate buffers of a format.
+pub(crate) fn select_dmabuf_format(
+    frame_formats: &[DMAFrameFormat],
+    constraints: &[DmabufFormatModifiers],
+    feedback: Option<&DmabufFeedback>,
+    gbm_supports: impl Fn(u32) -> bool,
+    preference: DmabufPreference,
+) -> Option<DmabufCandidate> {
+    let mut candidates: Vec<DmabufCandidate> = frame_formats
+        .iter()
+        .filter_map(|frame_format| {
+            let format = frame_format.format;
+            if !gbm_supports(format) {
+                tracing::debug!("GBM device cannot allocate format {format:#x}");
+                return None;
+            }
+
+            let offered = constraints
+                .iter()
+                .find(|constraint| constraint.format == format)
+                .map(|constraint| constraint.modifiers.clone());
+            let modifiers = match (offered, feedback) {
+                (Some(offered), Some(feedback)) => {
+                    let importable = feedback.modifiers(format);
+                    // Keep the order of the feedback, it reflects the compositor's preference.
+                    importable
+                        .into_iter()
+                        .filter(|modifier| offered.contains(modifier))
+                        .collect()
+                }
+                (Some(offered), None) => offered,
+                (None, Some(feedback)) => feedback.modifiers(format),
+                // Nothing is known about modifiers, leave the choice to GBM.
+                (None, None) => vec![MODIFIER_INVALID],
+            };
+            if modifiers.is_empty() {
+                tracing::debug!("No usable modifier for format {format:#x}");
+                return None;
+            }
+
+            let implicit_modifier = modifiers.contains(&MODIFIER_INVALID);
+            Some(DmabufCandidate {
+                frame_format: *frame_format,
+                modifiers: modifiers
+                    .into_iter()
+                    .filter(|&modifier| modifier != MODIFIER_INVALID)
+                    .collect(),
+                implicit_modifier,
+            })
+        })
+        .collect();
+
+    // Stable sort, so candidates of equal rank keep the compositor's order.
+    match preference {
+        DmabufPreference::Native => {}
+        DmabufPreference::HighBitDepth => {
+            candidates.sort_by_key(|candidate| !is_high_bit_depth(candidate.frame_format.format))
+        }
+        DmabufPreference::Linear => {
+            candidates.sort_by_key(|candidate| !candidate.allows_linear());
+        }
+    }
+    let candidate = candidates.into_iter().next()?;
+    tracing::debug!("Selected DMA-BUF candidate {:#?}", candidate);
+    Some(candidate)
+}
+
+/// Whether `gbm` can allocate buffers of the DRM `format` for any of our uses.
+pub(crate) fn gbm_supports_format(gbm: &gbm::Device<Card>, format: u32) -> bool {
+    let Ok(format) = DrmFourcc::try_from(format) else {
+        return false;
+    };
+    [
+        BufferObjectFlags::RENDERING,
+        BufferObjectFlags::WRITE,
+        BufferObjectFlags::LINEAR,
+    ]
+    .into_iter()
+    .any(|usage| gbm.is_format_supported(format, usage))
+}
+
+/// Allocate a buffer object for `candidate`, honoring its modifiers and `preference`.
+pub(crate) fn allocate_buffer_object(
+    gbm: &gbm::Device<Card>,
+    candidate: &DmabufCandidate,
+    preference: DmabufPreference,
+) -> Result<BufferObject<()>> {
+    let DMAFrameFormat { format, size } = candidate.frame_format;
+    let gbm_format = DrmFourcc::try_from(format)?;
+
+    let mut modifier_sets = Vec::new();
+    if preference == DmabufPreference::Linear && candidate.modifiers.contains(&MODIFIER_LINEAR) {
+        modifier_sets.push(vec![MODIFIER_LINEAR]);
+    }
+    if !candidate.modifiers.is_empty() {
+        modifier_sets.push(candidate.modifiers.clone());
+    }
+    for modifiers in modifier_sets {
+        let with_usage = gbm.create_buffer_object_with_modifiers2::<()>(
+            size.width,
+            size.height,
+            gbm_format,
+            modifiers.iter().copied().map(Modifier::from),
+            BufferObjectFlags::RENDERING,
+        );
+        let result = with_usage.or_else(|_| {
+            gbm.create_buffer_object_with_modifiers::<()>(
+                size.width,
+                size.height,
+                gbm_format,
+                modifiers.iter().copied().map(Modifier::from),
+            )
+        });
+        match result {
+            Ok(bo) => {
+                tracing::debug!(
+                    "Created GBM buffer for format {format:#x} with modifiers {modifiers:x?}"
+                );
+                return Ok(bo);
+            }
+            Err(err) => {
+                tracing::debug!("Failed to create GBM buffer with modifiers {modifiers:x?}: {err}");
+            }
+        }
+    }
+
+    if candidate.implicit_modifier {
+        let usage_candidates = [
+            BufferObjectFlags::RENDERING | BufferObjectFlags::LINEAR,
+            BufferObjectFlags::RENDERING,
+            BufferObjectFlags::WRITE | BufferObjectFlags::LINEAR,
+            BufferObjectFlags::WRITE,
+            BufferObjectFlags::LINEAR,
+            BufferObjectFlags::empty(),
+        ];
+        for usage in usage_candidates {
+            if !usage.is_empty() && !gbm.is_format_supported(gbm_format, usage) {
+                continue;
+            }
+            match gbm.create_buffer_object::<()>(size.width, size.height, gbm_format, usage) {
+                Ok(bo) => {
+                    tracing::debug!(
+                        "Created GBM buffer with usage flags {usage:?} for format {format:#x}"
+                    );
+                    return Ok(bo);
+                }
+                Err(err) => {
+                    tracing::debug!("Failed to create GBM buffer with usage {usage:?}: {err}");
+                }
+            }
+        }
+    }
+
+    tracing::error!("Unable to allocate GBM buffer for format {format:#x}");
+    Err(Error::NoSupportedBufferFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Size;
+
+    const XRGB8888: u32 = DrmFourcc::Xrgb8888 as u32;
+    const ARGB8888: u32 = DrmFourcc::Argb8888 as u32;
+    const XRGB2101010: u32 = DrmFourcc::Xrgb2101010 as u32;
+    const XBGR16161616F: u32 = DrmFourcc::Xbgr16161616f as u32;
+    const X_TILED: u64 = 0x0100_0000_0000_0001;
+    const Y_TILED: u64 = 0x0100_0000_0000_0002;
+
+    struct Case {
+        name: &'static str,
+        frame_formats: &'static [u32],
+        constraints: &'static [(u32, &'static [u64])],
+        /// Tranches of the feedback, in the order the compositor sent them.
+        feedback: Option<&'static [&'static [(u32, u64)]]>,
+        gbm_formats: &'static [u32],
+        preference: DmabufPreference,
+        /// Format, explicit modifiers and whether an implicit modifier is allowed.
+        expected: Option<(u32, &'static [u64], bool)>,
+    }
+
+    const ALL_FORMATS: &[u32] = &[XRGB8888, ARGB8888, XRGB2101010, XBGR16161616F];
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "native keeps the compositor's order",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Native,
+            expected: Some((XRGB8888, &[], true)),
+        },
+        Case {
+            name: "high bit depth prefers 10 bit formats",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::HighBitDepth,
+            expected: Some((XRGB2101010, &[], true)),
+        },
+        Case {
+            name: "high bit depth ties keep the compositor's order",
+            frame_formats: &[XRGB8888, XBGR16161616F, XRGB2101010],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::HighBitDepth,
+            expected: Some((XBGR16161616F, &[], true)),
+        },
+        Case {
+            name: "high bit depth falls back to 8 bit formats",
+            frame_formats: &[ARGB8888, XRGB8888],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::HighBitDepth,
+            expected: Some((ARGB8888, &[], true)),
+        },
+        Case {
+            name: "linear prefers formats with the linear modifier",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[
+                (XRGB8888, &[X_TILED]),
+                (XRGB2101010, &[X_TILED, MODIFIER_LINEAR]),
+            ],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Linear,
+            expected: Some((XRGB2101010, &[X_TILED, MODIFIER_LINEAR], false)),
+        },
+        Case {
+            name: "linear ties keep the compositor's order",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[
+                (XRGB8888, &[MODIFIER_LINEAR]),
+                (XRGB2101010, &[MODIFIER_LINEAR]),
+            ],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Linear,
+            expected: Some((XRGB8888, &[MODIFIER_LINEAR], false)),
+        },
+        Case {
+            name: "linear counts implicit modifiers as linear",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[(XRGB8888, &[Y_TILED])],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Linear,
+            expected: Some((XRGB2101010, &[], true)),
+        },
+        Case {
+            name: "linear falls back to tiled formats",
+            frame_formats: &[XRGB8888, XRGB2101010],
+            constraints: &[(XRGB8888, &[X_TILED]), (XRGB2101010, &[Y_TILED])],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Linear,
+            expected: Some((XRGB8888, &[X_TILED], false)),
+        },
+        Case {
+            name: "formats gbm can't allocate are skipped",
+            frame_formats: &[XRGB2101010, XRGB8888],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: &[XRGB8888],
+            preference: DmabufPreference::HighBitDepth,
+            expected: Some((XRGB8888, &[], true)),
+        },
+        Case {
+            name: "nothing gbm can allocate",
+            frame_formats: &[XRGB2101010, XRGB8888],
+            constraints: &[],
+            feedback: None,
+            gbm_formats: &[ARGB8888],
+            preference: DmabufPreference::Native,
+            expected: None,
+        },
+        Case {
+            name: "feedback modifiers follow the tranche order",
+            frame_formats: &[XRGB8888],
+            constraints: &[],
+            feedback: Some(&[
+                &[(XRGB8888, Y_TILED)],
+                &[(XRGB8888, X_TILED), (XRGB8888, Y_TILED)],
+            ]),
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Native,
+            expected: Some((XRGB8888, &[Y_TILED, X_TILED], false)),
+        },
+        Case {
+            name: "tranche order wins over the order of the constraints",
+            frame_formats: &[XRGB8888],
+            constraints: &[(XRGB8888, &[X_TILED, Y_TILED])],
+            feedback: Some(&[&[(XRGB8888, Y_TILED)], &[(XRGB8888, X_TILED)]]),
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Native,
+            expected: Some((XRGB8888, &[Y_TILED, X_TILED], false)),
+        },
+        Case {
+            name: "offered modifiers the compositor can't import are dropped",
+            frame_formats: &[XRGB8888],
+            constraints: &[(XRGB8888, &[X_TILED, MODIFIER_LINEAR])],
+            feedback: Some(&[&[(XRGB8888, MODIFIER_LINEAR), (ARGB8888, X_TILED)]]),
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Native,
+            expected: Some((XRGB8888, &[MODIFIER_LINEAR], false)),
+        },
+        Case {
+            name: "formats without an importable modifier are skipped",
+            frame_formats: &[XRGB2101010, XRGB8888],
+            constraints: &[(XRGB2101010, &[X_TILED]), (XRGB8888, &[MODIFIER_LINEAR])],
+            feedback: Some(&[&[(XRGB2101010, Y_TILED), (XRGB8888, MODIFIER_LINEAR)]]),
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::HighBitDepth,
+            expected: Some((XRGB8888, &[MODIFIER_LINEAR], false)),
+        },
+        Case {
+            name: "invalid modifier allows an implicit layout",
+            frame_formats: &[XRGB8888],
+            constraints: &[(XRGB8888, &[MODIFIER_INVALID, MODIFIER_LINEAR])],
+            feedback: None,
+            gbm_formats: ALL_FORMATS,
+            preference: DmabufPreference::Native,
+            expected: Some((XRGB8888, &[MODIFIER_LINEAR], true)),
+        },
+    ];
+
+    #[test]
+    fn select_dmabuf_format_cases() {
+        let size = Size {
+            width: 64,
+            height: 32,
+        };
+        for case in CASES {
+            let frame_formats: Vec<DMAFrameFormat> = case
+                .frame_formats
+                .iter()
+                .map(|&format| DMAFrameFormat { format, size })
+                .collect();
+            let constraints: Vec<DmabufFormatModifiers> = case
+                .constraints
+                .iter()
+                .map(|(format, modifiers)| DmabufFormatModifiers {
+                    format: *format,
+                    modifiers: modifiers.to_vec(),
+                })
+                .collect();
+            let feedback = case.feedback.map(|tranches| DmabufFeedback {
+                main_device: Some(1),
+                tranches: tranches
+                    .iter()
+                    .map(|formats| DmabufTranche {
+                        target_device: Some(1),
+                        formats: formats.to_vec(),
+                        scanout: false,
+                    })
+                    .collect(),
+            });
+
+            let candidate = select_dmabuf_format(
+                &frame_formats,
+                &constraints,
+                feedback.as_ref(),
+                |format| case.gbm_formats.contains(&format),
+                case.preference,
+            );
+            let actual = candidate.map(|candidate| {
+                assert_eq!(candidate.frame_format.size, size, "{}", case.name);
+                (
+                    candidate.frame_format.format,
+                    candidate.modifiers,
+                    candidate.implicit_modifier,
+                )
+            });
+            let expected = case
+                .expected
+                .map(|(format, modifiers, implicit)| (format, modifiers.to_vec(), implicit));
+            assert_eq!(actual, expected, "{}", case.name);
+        }
+    }
+}
@@ -39,12 +39,57 @@ pub enum Error {
         "dmabuf configuration not initialized. Did you not use Wayshot::from_connection_with_dmabuf()?"
     )]
     NoDMAStateError,
+    #[error(
+        "regions can't be captured into DMA-BUFs through ext-image-copy-capture, it only captures whole outputs"
+    )]
+    DmabufRegionUnsupported,
     #[error("dmabuf color format provided by compositor is invalid")]
     UnrecognizedColorCode(#[from] UnrecognizedFourcc),
     #[error("dmabuf device has been destroyed")]
     EGLError(#[from] khronos_egl::Error),
     #[error("No EGLImageTargetTexture2DOES function located, this extension may not be supported")]
     EGLImageToTexProcNotFoundError,
+    #[error("capture session was stopped by the compositor")]
+    SessionStopped,
+    #[error("failed to capture output {output}: {source}")]
+    OutputCaptureFailed { output: String, source: Box<Error> },
+    #[error("outputs are not watched, call WayshotConnection::watch_outputs first")]
+    OutputsNotWatched,
+    #[error("toplevels are not watched, call WayshotConnection::watch_toplevels first")]
+    ToplevelsNotWatched,
+    #[error(
+        "the scale of a toplevel is unknown when outputs have different scales, capture it at its native size"
+    )]
+    ToplevelScaleUnknown,
     #[error("Capture failed: {0}")]
     CaptureFailed(String),
+    #[error("invalid geometry: {0}")]
+    InvalidGeometry(#[from] GeometryError),
+}
+
+/// Why a [`crate::region::Geometry`] could not be parsed or placed on the outputs.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GeometryError {
+    #[error("geometry is empty")]
+    Empty,
+    #[error(
+        "`{0}` is not a known geometry, expected `X,Y WxH`, `WxH+X+Y` or `ANCHOR WxH`, each optionally prefixed with `OUTPUT:`, or `OUTPUT X Y W H`"
+    )]
+    UnknownFormat(String),
+    #[error("`{0}` is not a position like `10,20` or `25%,25%`")]
+    InvalidPosition(String),
+    #[error("`{0}` is not a size like `800x600` or `50%x50%`")]
+    InvalidSize(String),
+    #[error("`{0}` is neither a whole number of pixels nor a percentage")]
+    InvalidLength(String),
+    #[error(
+        "`{0}` is not an anchor, expected one of center, top-left, top, top-right, left, right, bottom-left, bottom or bottom-right"
+    )]
+    UnknownAnchor(String),
+    #[error("no output name given before `:`")]
+    MissingOutputName,
+    #[error("no output named `{0}`")]
+    UnknownOutput(String),
+    #[error("region doesn't overlap output `{0}`")]
+    OutsideOutput(String),
 }
@@ -0,0 +1,189 @@
+//! Looking up the fractional scale compositors prefer for each output.
+//!
+//! `wp_fractional_scale_v1` only reports the preferred scale of surfaces, not of outputs. To learn
+//! it for an output, [`WayshotConnection::refresh_preferred_scales`] briefly maps a transparent 1x1
+//! layer surface without input on it and destroys it again once the compositor sent the scale.
+//! Mapping surfaces is visible to the compositor, so this only happens when asked for.
+
+use std::{fs::File, os::fd::AsFd};
+
+use wayland_client::{
+    Connection, Dispatch, QueueHandle, delegate_noop,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_compositor::WlCompositor,
+        wl_output::WlOutput,
+        wl_region::WlRegion,
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+};
+use wayland_protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
+};
+
+use crate::{Result, WayshotConnection, screencopy::create_shm_fd};
+
+/// `wp_fractional_scale_v1.preferred_scale` is sent as the numerator of a fraction over 120.
+const SCALE_DENOMINATOR: f64 = 120.0;
+
+/// State of the probe surfaces, indexed like the probed outputs.
+#[derive(Debug)]
+struct PreferredScaleState {
+    configured: Vec<bool>,
+    preferred_scales: Vec<Option<f64>>,
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, usize> for PreferredScaleState {
+    fn event(
+        state: &mut Self,
+        layer_surface: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
+                layer_surface.ack_configure(serial);
+                state.configured[*index] = true;
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                tracing::debug!("Probe surface {index} was closed before it was mapped");
+                state.configured[*index] = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, usize> for PreferredScaleState {
+    fn event(
+        state: &mut Self,
+        _: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.preferred_scales[*index] = Some(scale as f64 / SCALE_DENOMINATOR);
+        }
+    }
+}
+
+delegate_noop!(PreferredScaleState: ignore WlCompositor);
+delegate_noop!(PreferredScaleState: ignore WlSurface);
+delegate_noop!(PreferredScaleState: ignore WlRegion);
+delegate_noop!(PreferredScaleState: ignore WlShm);
+delegate_noop!(PreferredScaleState: ignore WlShmPool);
+delegate_noop!(PreferredScaleState: ignore WlBuffer);
+delegate_noop!(PreferredScaleState: ignore ZwlrLayerShellV1);
+delegate_noop!(PreferredScaleState: ignore WpFractionalScaleManagerV1);
+
+impl WayshotConnection {
+    /// Look up [`OutputInfo::preferred_scale`](crate::output::OutputInfo::preferred_scale) for
+    /// every output in [`WayshotConnection::get_all_outputs`].
+    ///
+    /// Requires `wp_fractional_scale_manager_v1` and `zwlr_layer_shell_v1`. The scales are not
+    /// looked up again when outputs change, call this again to update them.
+    pub fn refresh_preferred_scales(&mut self) -> Result<()> {
+        let wl_outputs: Vec<WlOutput> = self
+            .output_infos
+            .iter()
+            .map(|output| output.wl_output.clone())
+            .collect();
+        if wl_outputs.is_empty() {
+            return Ok(());
+        }
+        let preferred_scales = self.probe_preferred_scales(&wl_outputs)?;
+        for (output, preferred_scale) in self.output_infos.iter_mut().zip(preferred_scales) {
+            output.preferred_scale = preferred_scale;
+            if let Some(watcher) = &mut self.output_watcher {
+                watcher.set_preferred_scale(&output.wl_output, preferred_scale);
+            }
+        }
+        Ok(())
+    }
+
+    fn probe_preferred_scales(&self, outputs: &[WlOutput]) -> Result<Vec<Option<f64>>> {
+        let mut event_queue = self.conn.new_event_queue::<PreferredScaleState>();
+        let qh = event_queue.handle();
+        let fractional_scale_manager =
+            self.globals
+                .bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ())?;
+        let compositor = self.globals.bind::<WlCompositor, _, _>(&qh, 3..=3, ())?;
+        let layer_shell = self
+            .globals
+            .bind::<ZwlrLayerShellV1, _, _>(&qh, 1..=1, ())?;
+        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
+
+        let mut state = PreferredScaleState {
+            configured: vec![false; outputs.len()],
+            preferred_scales: vec![None; outputs.len()],
+        };
+        let surfaces: Vec<_> = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                let surface = compositor.create_surface(&qh, ());
+                let fractional_scale =
+                    fractional_scale_manager.get_fractional_scale(&surface, &qh, index);
+                // Let every click pass through the surface while it is mapped.
+                let input_region = compositor.create_region(&qh, ());
+                surface.set_input_region(Some(&input_region));
+                input_region.destroy();
+                let layer_surface = layer_shell.get_layer_surface(
+                    &surface,
+                    Some(output),
+                    Layer::Background,
+                    "wayshot".to_string(),
+                    &qh,
+                    index,
+                );
+                layer_surface.set_size(1, 1);
+                surface.commit();
+                (surface, fractional_scale, layer_surface)
+            })
+            .collect();
+        while !state.configured.iter().all(|configured| *configured) {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        // A single transparent pixel, shared by all probe surfaces.
+        let fd = create_shm_fd()?;
+        File::from(fd.try_clone()?).set_len(4)?;
+        let pool = shm.create_pool(fd.as_fd(), 4, &qh, ());
+        let buffer = pool.create_buffer(0, 1, 1, 4, wl_shm::Format::Argb8888, &qh, ());
+        for (surface, _, _) in &surfaces {
+            surface.attach(Some(&buffer), 0, 0);
+            surface.commit();
+        }
+        // Compositors send the preferred scale once the surface entered its output, which may
+        // take until after the commit was processed.
+        for _ in 0..2 {
+            event_queue.roundtrip(&mut state)?;
+            if state.preferred_scales.iter().all(Option::is_some) {
+                break;
+            }
+        }
+
+        for (surface, fractional_scale, layer_surface) in surfaces {
+            fractional_scale.destroy();
+            layer_surface.destroy();
+            surface.destroy();
+        }
+        buffer.destroy();
+        pool.destroy();
+        // Make sure the surfaces are gone before anything is captured.
+        event_queue.roundtrip(&mut state)?;
+        tracing::debug!("Preferred fractional scales: {:?}", state.preferred_scales);
+        Ok(state.preferred_scales)
+    }
+}
@@ -1,7 +1,14 @@
-use image::DynamicImage;
+use std::{borrow::Cow, f32::consts::PI, ops::Range, sync::Arc, thread};
+
+use image::{ColorType, DynamicImage, ImageBuffer, Pixel, Rgba, imageops::FilterType};
 use wayland_client::protocol::wl_output::Transform;
 
-use crate::region::Size;
+use crate::{
+    Error, Result,
+    convert::{Convert, create_converter},
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{FrameCopy, FrameData, read_gbm_bo},
+};
 
 #[tracing::instrument(skip(image))]
 pub(crate) fn rotate_image_buffer(
@@ -58,3 +65,640 @@ pub(crate) fn rotate_image_buffer(
     )
     .into()
 }
+
+/// Map a `region` of a `buffer_size` sized buffer to the coordinates it ends up at after
+/// [`rotate_image_buffer`] applied `transform` to the buffer.
+pub(crate) fn transform_region(region: Region, buffer_size: Size, transform: Transform) -> Region {
+    let Region {
+        position: Position { x, y },
+        size: Size { width, height },
+    } = region;
+    let (buffer_width, buffer_height) = (buffer_size.width as i32, buffer_size.height as i32);
+    let (right, bottom) = (x + width as i32, y + height as i32);
+    let flipped_x = buffer_width - right;
+
+    let (position, size) = match transform {
+        Transform::_90 => (
+            Position {
+                x: buffer_height - bottom,
+                y: x,
+            },
+            Size {
+                width: height,
+                height: width,
+            },
+        ),
+        Transform::_180 => (
+            Position {
+                x: flipped_x,
+                y: buffer_height - bottom,
+            },
+            region.size,
+        ),
+        Transform::_270 => (
+            Position {
+                x: y,
+                y: buffer_width - right,
+            },
+            Size {
+                width: height,
+                height: width,
+            },
+        ),
+        Transform::Flipped => (Position { x: flipped_x, y }, region.size),
+        Transform::Flipped90 => (
+            Position {
+                x: buffer_height - bottom,
+                y: flipped_x,
+            },
+            Size {
+                width: height,
+                height: width,
+            },
+        ),
+        Transform::Flipped180 => (
+            Position {
+                x,
+                y: buffer_height - bottom,
+            },
+            region.size,
+        ),
+        Transform::Flipped270 => (
+            Position { x: y, y: x },
+            Size {
+                width: height,
+                height: width,
+            },
+        ),
+        _ => (region.position, region.size),
+    };
+    Region { position, size }
+}
+
+/// The transform that undoes `transform`, used to map regions back into buffer coordinates
+/// with [`transform_region`].
+pub(crate) fn invert_transform(transform: Transform) -> Transform {
+    match transform {
+        Transform::_90 => Transform::_270,
+        Transform::_270 => Transform::_90,
+        // Flipped transforms are their own inverse.
+        transform => transform,
+    }
+}
+
+/// How screenshots spanning outputs with different scales are sized.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ScalePolicy {
+    /// Scale every output up to the largest scale among the captured outputs, so no output loses
+    /// detail.
+    #[default]
+    Max,
+    /// Scale every output down to the smallest scale among the captured outputs.
+    Min,
+    /// One image pixel per logical pixel.
+    Logical,
+    /// Copy the pixels of every output without resampling them. Outputs are placed at the largest
+    /// scale, so outputs with a smaller scale leave transparent space next to them.
+    Native,
+}
+
+impl ScalePolicy {
+    /// Canvas scale for a screenshot of outputs with the given scales.
+    pub(crate) fn canvas_scale(self, scales: impl IntoIterator<Item = f64>) -> f64 {
+        let mut scales = scales.into_iter().peekable();
+        if scales.peek().is_none() {
+            return 1.0;
+        }
+        match self {
+            ScalePolicy::Max | ScalePolicy::Native => scales.fold(f64::MIN, f64::max),
+            ScalePolicy::Min => scales.fold(f64::MAX, f64::min),
+            ScalePolicy::Logical => 1.0,
+        }
+    }
+}
+
+/// How toplevel (window) captures are sized.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ToplevelSize {
+    /// One image pixel per pixel of the buffer the window rendered.
+    #[default]
+    Native,
+    /// One image pixel per logical pixel of the window. ext-foreign-toplevel-list doesn't tell
+    /// which output a window is on, so this needs every output to have the same scale and fails
+    /// with [`Error::ToplevelScaleUnknown`](crate::Error::ToplevelScaleUnknown) otherwise.
+    Logical,
+}
+
+/// Paint every frame onto a transparent canvas covering `capture_region` at `scale`.
+///
+/// Every canvas row is assembled straight from the shm mappings of the frames it shows,
+/// converting, rotating and scaling the pixels on the way, so no full-frame intermediate images
+/// are allocated. Rows are split into one band per thread. With [`FilterType::Nearest`] frames are
+/// sampled at pixel centers, which keeps integer scale factors exact. Other filters use the kernels
+/// of `imageops::resize`, applied separably: each thread keeps the horizontally filtered frame rows
+/// that the vertical kernel of its current canvas row covers. [`ScalePolicy::Native`] frames are
+/// never resampled.
+///
+/// The canvas is `Rgba16` when any frame has more than 8 bits per channel, `Rgba8` otherwise.
+pub(crate) fn composite_frames(
+    frames: Vec<FrameCopy>,
+    capture_region: LogicalRegion,
+    scale: f64,
+    scale_policy: ScalePolicy,
+    filter: FilterType,
+) -> Result<DynamicImage> {
+    if frames.is_empty() {
+        tracing::error!("Provided capture region doesn't intersect with any outputs!");
+        return Err(Error::NoOutputs);
+    }
+    let size = Size {
+        width: (capture_region.inner.size.width as f64 * scale) as u32,
+        height: (capture_region.inner.size.height as f64 * scale) as u32,
+    };
+    let native = scale_policy == ScalePolicy::Native;
+    let layers = frames
+        .iter()
+        .map(|frame_copy| {
+            Ok(Layer::new(frame_copy, capture_region, scale, native)?.with_filter(filter))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let deep = layers.iter().any(|layer| {
+        let color_type = layer.converter.color_type();
+        color_type.bytes_per_pixel() > color_type.channel_count()
+    });
+    Ok(if deep {
+        DynamicImage::ImageRgba16(composite_layers(&layers, size)?)
+    } else {
+        DynamicImage::ImageRgba8(composite_layers(&layers, size)?)
+    })
+}
+
+/// Channel type of the composite canvas.
+pub(crate) trait Sample: Copy + Default + Send + Sync + 'static {
+    fn from_u16(value: u16) -> Self;
+}
+
+impl Sample for u8 {
+    fn from_u16(value: u16) -> Self {
+        (value >> 8) as u8
+    }
+}
+
+impl Sample for u16 {
+    fn from_u16(value: u16) -> Self {
+        value
+    }
+}
+
+fn composite_layers<S>(layers: &[Layer<'_>], size: Size) -> Result<ImageBuffer<Rgba<S>, Vec<S>>>
+where
+    S: Sample,
+    Rgba<S>: Pixel<Subpixel = S>,
+{
+    let row_len = size.width as usize * 4;
+    let mut canvas = vec![S::default(); row_len * size.height as usize];
+    if row_len > 0 {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let rows_per_chunk = (size.height as usize).div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            for (chunk_index, chunk) in canvas.chunks_mut(rows_per_chunk * row_len).enumerate() {
+                scope.spawn(move || {
+                    let mut scratch = Scratch::default();
+                    // Later layers still paint over earlier ones, every row is painted in order.
+                    for layer in layers {
+                        scratch.band.clear();
+                        for (index, row) in chunk.chunks_exact_mut(row_len).enumerate() {
+                            let y = (chunk_index * rows_per_chunk + index) as i64;
+                            layer.paint_row(y, row, &mut scratch);
+                        }
+                    }
+                });
+            }
+        });
+    }
+    ImageBuffer::from_vec(size.width, size.height, canvas).ok_or(Error::BufferTooSmall)
+}
+
+/// Buffers reused between the rows a thread paints.
+#[derive(Default)]
+struct Scratch {
+    gathered: Vec<u8>,
+    converted: Vec<u8>,
+    samples: Vec<u16>,
+    /// Horizontally filtered rows of the frame being painted, by row after the transform.
+    band: Vec<(u32, Vec<f32>)>,
+    accumulated: Vec<f32>,
+}
+
+/// The filter taps of every output pixel along one axis, computed like `imageops::resize` does.
+struct Taps {
+    /// First input pixel and the range of `weights` of every output pixel.
+    spans: Vec<(u32, Range<usize>)>,
+    weights: Vec<f32>,
+}
+
+impl Taps {
+    fn new(kernel: Kernel, support: f32, input: u32, output: u32) -> Self {
+        let ratio = input as f32 / output as f32;
+        let stretch = ratio.max(1.0);
+        let support = support * stretch;
+        let mut spans = Vec::with_capacity(output as usize);
+        let mut weights = Vec::new();
+        for index in 0..output {
+            // Center of the output pixel in input coordinates.
+            let center = (index as f32 + 0.5) * ratio;
+            let first = ((center - support).floor() as i64).clamp(0, input as i64 - 1) as u32;
+            let last = ((center + support).ceil() as i64).clamp(first as i64 + 1, input as i64);
+            let start = weights.len();
+            weights.extend(
+                (first..last as u32).map(|input| kernel((input as f32 - (center - 0.5)) / stretch)),
+            );
+            let sum: f32 = weights[start..].iter().sum();
+            weights[start..]
+                .iter_mut()
+                .for_each(|weight| *weight /= sum);
+            spans.push((first, start..weights.len()));
+        }
+        Self { spans, weights }
+    }
+
+    /// First input pixel and weights of output pixel `index`.
+    fn get(&self, index: usize) -> (u32, &[f32]) {
+        let (first, range) = &self.spans[index];
+        (*first, &self.weights[range.clone()])
+    }
+}
+
+/// A filter kernel, weighting input pixels by their distance from the sampled position.
+type Kernel = fn(f32) -> f32;
+
+/// Kernel and support of a resampling filter, the same `imageops::resize` uses.
+fn filter_kernel(filter: FilterType) -> Option<(Kernel, f32)> {
+    fn sinc(x: f32) -> f32 {
+        if x == 0.0 {
+            1.0
+        } else {
+            (x * PI).sin() / (x * PI)
+        }
+    }
+    fn triangle(x: f32) -> f32 {
+        (1.0 - x.abs()).max(0.0)
+    }
+    // Catmull-Rom is the cubic spline with B = 0 and C = 0.5.
+    fn catmull_rom(x: f32) -> f32 {
+        let x = x.abs();
+        if x < 1.0 {
+            (9.0 * x.powi(3) - 15.0 * x.powi(2) + 6.0) / 6.0
+        } else if x < 2.0 {
+            (-3.0 * x.powi(3) + 15.0 * x.powi(2) - 24.0 * x + 12.0) / 6.0
+        } else {
+            0.0
+        }
+    }
+    // A standard deviation of 0.5, the weights are normalized so the factor doesn't matter.
+    fn gaussian(x: f32) -> f32 {
+        (-2.0 * x.powi(2)).exp()
+    }
+    fn lanczos3(x: f32) -> f32 {
+        if x.abs() < 3.0 {
+            sinc(x) * sinc(x / 3.0)
+        } else {
+            0.0
+        }
+    }
+    match filter {
+        FilterType::Nearest => None,
+        FilterType::Triangle => Some((triangle, 1.0)),
+        FilterType::CatmullRom => Some((catmull_rom, 2.0)),
+        FilterType::Gaussian => Some((gaussian, 3.0)),
+        FilterType::Lanczos3 => Some((lanczos3, 3.0)),
+    }
+}
+
+/// A frame placed on the canvas.
+struct Layer<'a> {
+    data: Cow<'a, [u8]>,
+    stride: usize,
+    bytes_per_pixel: usize,
+    buffer_size: Size,
+    transform: Transform,
+    converter: Arc<dyn Convert>,
+    /// Size of the frame after the transform, before scaling.
+    transformed_size: Size,
+    /// Canvas pixels covered by the frame, possibly reaching outside of the canvas.
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    /// Horizontal and vertical filter taps when the frame is scaled with a filter other than
+    /// nearest neighbour.
+    taps: Option<(Taps, Taps)>,
+}
+
+impl<'a> Layer<'a> {
+    fn new(
+        frame_copy: &'a FrameCopy,
+        capture_region: LogicalRegion,
+        scale: f64,
+        native: bool,
+    ) -> Result<Self> {
+        let frame_format = &frame_copy.frame_format;
+        let converter = create_converter(frame_format.format).ok_or_else(|| {
+            tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
+            Error::NoSupportedBufferFormat
+        })?;
+        let buffer_size = frame_format.size;
+        let bytes_per_pixel = frame_format.bytes_per_pixel() as usize;
+        let (data, stride) = match &frame_copy.frame_data {
+            FrameData::Mmap(mmap) => (Cow::Borrowed(&mmap[..]), frame_format.stride as usize),
+            FrameData::GBMBo(bo) => (
+                Cow::Owned(read_gbm_bo(bo, buffer_size, bytes_per_pixel as u32)?),
+                buffer_size.width as usize * bytes_per_pixel,
+            ),
+        };
+        if buffer_size.height > 0
+            && data.len()
+                < stride * (buffer_size.height as usize - 1)
+                    + buffer_size.width as usize * bytes_per_pixel
+        {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let transformed_size = transform_region(
+            Region {
+                position: Position { x: 0, y: 0 },
+                size: buffer_size,
+            },
+            buffer_size,
+            frame_copy.transform,
+        )
+        .size;
+        // Round both edges, so neighbouring frames meet without gaps or overlaps.
+        let region = frame_copy.logical_region.inner;
+        let relative_x = (region.position.x - capture_region.inner.position.x) as f64;
+        let relative_y = (region.position.y - capture_region.inner.position.y) as f64;
+        let x = (relative_x * scale).round() as i64;
+        let y = (relative_y * scale).round() as i64;
+        let (width, height) = if native {
+            (
+                transformed_size.width as i64,
+                transformed_size.height as i64,
+            )
+        } else {
+            (
+                ((relative_x + region.size.width as f64) * scale).round() as i64 - x,
+                ((relative_y + region.size.height as f64) * scale).round() as i64 - y,
+            )
+        };
+        tracing::debug!(
+            "Placing {}x{} frame at {x},{y} with size {width}x{height}",
+            transformed_size.width,
+            transformed_size.height
+        );
+
+        Ok(Self {
+            data,
+            stride,
+            bytes_per_pixel,
+            buffer_size,
+            transform: frame_copy.transform,
+            converter,
+            transformed_size,
+            x,
+            y,
+            width,
+            height,
+            taps: None,
+        })
+    }
+
+    /// Scale the frame to its canvas size with `filter`, unless nearest neighbour sampling was
+    /// asked for or the frame is not scaled.
+    fn with_filter(self, filter: FilterType) -> Self {
+        let Size {
+            width: transformed_width,
+            height: transformed_height,
+        } = self.transformed_size;
+        let Some((kernel, support)) = filter_kernel(filter) else {
+            return self;
+        };
+        if self.width <= 0
+            || self.height <= 0
+            || transformed_width == 0
+            || transformed_height == 0
+            || (self.width, self.height) == (transformed_width as i64, transformed_height as i64)
+        {
+            return self;
+        }
+        tracing::debug!(
+            "Resampling {transformed_width}x{transformed_height} frame to {}x{} with {filter:?}",
+            self.width,
+            self.height
+        );
+        let horizontal = Taps::new(kernel, support, transformed_width, self.width as u32);
+        let vertical = Taps::new(kernel, support, transformed_height, self.height as u32);
+        Self {
+            taps: Some((horizontal, vertical)),
+            ..self
+        }
+    }
+
+    /// Paint the part of canvas row `y` covered by this frame.
+    fn paint_row<S: Sample>(&self, y: i64, row: &mut [S], scratch: &mut Scratch) {
+        let canvas_width = (row.len() / 4) as i64;
+        let start = self.x.max(0);
+        let end = (self.x + self.width).min(canvas_width);
+        if y < self.y || y >= self.y + self.height || start >= end {
+            return;
+        }
+        if let Some(taps) = &self.taps {
+            self.paint_filtered_row(
+                y,
+                start..end,
+                &mut row[start as usize * 4..end as usize * 4],
+                taps,
+                scratch,
+            );
+            return;
+        }
+        let pixels = (end - start) as usize;
+        let Size {
+            width: transformed_width,
+            height: transformed_height,
+        } = self.transformed_size;
+        // Nearest neighbour, sampling at pixel centers.
+        let sample = |position: i64, length: i64, transformed: u32| {
+            (((2 * position + 1) * transformed as i64) / (2 * length)) as u32
+        };
+        let transformed_y = sample(y - self.y, self.height, transformed_height);
+
+        let src = if self.transform == Transform::Normal && self.width == transformed_width as i64 {
+            // The row is contiguous in the buffer.
+            let offset = transformed_y as usize * self.stride
+                + (start - self.x) as usize * self.bytes_per_pixel;
+            &self.data[offset..offset + pixels * self.bytes_per_pixel]
+        } else {
+            scratch.gathered.clear();
+            for x in start..end {
+                let transformed_x = sample(x - self.x, self.width, transformed_width);
+                let (buffer_x, buffer_y) = buffer_position(
+                    self.transform,
+                    transformed_x,
+                    transformed_y,
+                    self.buffer_size,
+                );
+                let offset =
+                    buffer_y as usize * self.stride + buffer_x as usize * self.bytes_per_pixel;
+                scratch
+                    .gathered
+                    .extend_from_slice(&self.data[offset..offset + self.bytes_per_pixel]);
+            }
+            &scratch.gathered[..]
+        };
+
+        let color_type = self.converter.color_type();
+        scratch
+            .converted
+            .resize(pixels * color_type.bytes_per_pixel() as usize, 0);
+        self.converter.convert_row(src, &mut scratch.converted);
+        write_rgba(
+            color_type,
+            &scratch.converted,
+            &mut row[start as usize * 4..end as usize * 4],
+        );
+    }
+
+    /// Paint canvas pixels `xs` of row `y` by applying the vertical taps to horizontally
+    /// filtered frame rows, filtering the rows that are not in the band of `scratch` yet.
+    fn paint_filtered_row<S: Sample>(
+        &self,
+        y: i64,
+        xs: Range<i64>,
+        row: &mut [S],
+        (horizontal, vertical): &(Taps, Taps),
+        scratch: &mut Scratch,
+    ) {
+        let (first_y, weights_y) = vertical.get((y - self.y) as usize);
+        // Canvas rows are painted top to bottom, rows above the taps are not needed again.
+        scratch.band.retain(|(band_y, _)| *band_y >= first_y);
+        scratch.accumulated.clear();
+        scratch.accumulated.resize(row.len(), 0.0);
+        for (transformed_y, weight_y) in (first_y..).zip(weights_y) {
+            let filtered = match scratch
+                .band
+                .iter()
+                .position(|(band_y, _)| *band_y == transformed_y)
+            {
+                Some(index) => &scratch.band[index].1,
+                None => {
+                    let filtered = self.filter_row(transformed_y, xs.clone(), horizontal, scratch);
+                    scratch.band.push((transformed_y, filtered));
+                    &scratch.band[scratch.band.len() - 1].1
+                }
+            };
+            for (sum, value) in scratch.accumulated.iter_mut().zip(filtered) {
+                *sum += weight_y * value;
+            }
+        }
+        for (sample, sum) in row.iter_mut().zip(&scratch.accumulated) {
+            *sample = S::from_u16(sum.round().clamp(0.0, u16::MAX as f32) as u16);
+        }
+    }
+
+    /// Row `transformed_y` of the upright frame, filtered horizontally into the RGBA channels of
+    /// canvas pixels `xs`.
+    fn filter_row(
+        &self,
+        transformed_y: u32,
+        xs: Range<i64>,
+        horizontal: &Taps,
+        scratch: &mut Scratch,
+    ) -> Vec<f32> {
+        let (first_x, _) = horizontal.get((xs.start - self.x) as usize);
+        let (last_x, last_weights) = horizontal.get((xs.end - 1 - self.x) as usize);
+        let inputs = first_x..last_x + last_weights.len() as u32;
+
+        scratch.gathered.clear();
+        for transformed_x in inputs.clone() {
+            let (buffer_x, buffer_y) = buffer_position(
+                self.transform,
+                transformed_x,
+                transformed_y,
+                self.buffer_size,
+            );
+            let offset = buffer_y as usize * self.stride + buffer_x as usize * self.bytes_per_pixel;
+            scratch
+                .gathered
+                .extend_from_slice(&self.data[offset..offset + self.bytes_per_pixel]);
+        }
+        let color_type = self.converter.color_type();
+        scratch
+            .converted
+            .resize(inputs.len() * color_type.bytes_per_pixel() as usize, 0);
+        self.converter
+            .convert_row(&scratch.gathered, &mut scratch.converted);
+        scratch.samples.resize(inputs.len() * 4, 0);
+        write_rgba(color_type, &scratch.converted, &mut scratch.samples);
+
+        let mut filtered = Vec::with_capacity(xs.clone().count() * 4);
+        for x in xs {
+            let (first, weights) = horizontal.get((x - self.x) as usize);
+            let mut pixel = [0.0; 4];
+            for (input, weight) in (first..).zip(weights) {
+                let offset = (input - inputs.start) as usize * 4;
+                for (channel, sample) in pixel.iter_mut().zip(&scratch.samples[offset..offset + 4])
+                {
+                    *channel += weight * *sample as f32;
+                }
+            }
+            filtered.extend(pixel);
+        }
+        filtered
+    }
+}
+
+/// The buffer pixel that ends up at `(x, y)` after [`rotate_image_buffer`] applied `transform`
+/// to a `buffer_size` sized buffer.
+fn buffer_position(transform: Transform, x: u32, y: u32, buffer_size: Size) -> (u32, u32) {
+    let (last_x, last_y) = (buffer_size.width - 1, buffer_size.height - 1);
+    match transform {
+        Transform::_90 => (y, last_y - x),
+        Transform::_180 => (last_x - x, last_y - y),
+        Transform::_270 => (last_x - y, x),
+        Transform::Flipped => (last_x - x, y),
+        Transform::Flipped90 => (last_x - y, last_y - x),
+        Transform::Flipped180 => (x, last_y - y),
+        Transform::Flipped270 => (y, x),
+        _ => (x, y),
+    }
+}
+
+/// Write converted pixels of `color_type` into RGBA canvas pixels.
+pub(crate) fn write_rgba<S: Sample>(color_type: ColorType, src: &[u8], dst: &mut [S]) {
+    let channels = color_type.channel_count() as usize;
+    let channel_size = color_type.bytes_per_pixel() as usize / channels;
+    let read = |bytes: &[u8]| -> u16 {
+        match channel_size {
+            1 => bytes[0] as u16 * 257,
+            2 => u16::from_ne_bytes([bytes[0], bytes[1]]),
+            _ => {
+                let value = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+            }
+        }
+    };
+    for (src, dst) in src
+        .chunks_exact(channels * channel_size)
+        .zip(dst.chunks_exact_mut(4))
+    {
+        let channel = |index: usize| read(&src[index * channel_size..]);
+        let [r, g, b, a] = match channels {
+            1 => [channel(0), channel(0), channel(0), u16::MAX],
+            2 => [channel(0), channel(0), channel(0), channel(1)],
+            3 => [channel(0), channel(1), channel(2), u16::MAX],
+            _ => [channel(0), channel(1), channel(2), channel(3)],
+        };
+        dst.copy_from_slice(&[r, g, b, a].map(S::from_u16));
+    }
+}
@@ -3,45 +3,61 @@
 //!
 //! To get started, look at [`WayshotConnection`].
 
-mod convert;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod color;
+pub mod convert;
+mod cursor;
 mod dispatch;
+mod dmabuf;
 mod error;
+mod fractional_scale;
 mod image_util;
 pub mod output;
 pub mod region;
 mod screencopy;
+mod session;
+#[cfg(feature = "test-server")]
+pub mod test_server;
+mod watch;
 
 use std::{
     collections::HashSet,
     ffi::c_void,
     fs::File,
-    os::fd::{AsFd, IntoRawFd, OwnedFd},
-    sync::atomic::{AtomicBool, Ordering},
-    thread,
+    os::fd::{AsFd, IntoRawFd},
+    sync::atomic::Ordering,
 };
 
+pub use color::PickedColor;
+pub use cursor::CursorCapture;
 use dispatch::{DMABUFState, LayerShellState};
-use image::{ColorType, DynamicImage, imageops::replace};
+pub use dmabuf::DmabufPreference;
+use dmabuf::{allocate_buffer_object, gbm_supports_format, select_dmabuf_format};
+use image::{DynamicImage, imageops::FilterType};
+pub use image_util::{ScalePolicy, ToplevelSize};
 use khronos_egl::{self as egl, Instance};
 use memmap2::MmapMut;
-use screencopy::create_shm_fd;
 pub use screencopy::{
-    DMAFrameCopy, DMAFrameFormat, DMAFrameGuard, EGLImageGuard, FrameCopy, FrameData, FrameFormat,
-    FrameGuard,
+    BufferConstraints, DMAFrameCopy, DMAFrameFormat, DMAFrameGuard, DmabufFormatModifiers,
+    EGLImageGuard, FrameCopy, FrameData, FrameFormat, FrameGuard, FrozenFrame, RawFrame,
 };
+use screencopy::{create_shm_fd, damage_regions, select_shm_format};
+pub use session::CaptureSession;
 use tracing::debug;
 use wayland_client::{
-    Connection, EventQueue, Proxy,
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
     globals::{GlobalList, registry_queue_init},
     protocol::{
+        wl_buffer::WlBuffer,
         wl_compositor::WlCompositor,
         wl_output::{Transform, WlOutput},
         wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
     },
 };
 use wayland_protocols::{
     ext::{
-        foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
         image_capture_source::v1::client::{
             ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
             ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
@@ -73,19 +89,24 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
-    convert::create_converter,
-    dispatch::{CaptureFrameState, FrameState, OutputCaptureState, WayshotState},
+    dispatch::{
+        CaptureDispatch, CaptureFrameState, MultiCaptureState, OutputCaptureState, WayshotState,
+    },
     output::OutputInfo,
-    region::{EmbeddedRegion, LogicalRegion, RegionCapturer, Size, TopLevel},
+    region::{EmbeddedRegion, LogicalRegion, Position, Region, RegionCapturer, Size, TopLevel},
 };
 
-pub use crate::error::{Error, Result};
+pub use crate::error::{Error, GeometryError, Result};
+
+/// Outcome of capturing a single output with
+/// [`WayshotConnection::capture_frame_copies_per_output`].
+pub type OutputCapture = (OutputInfo, Result<(FrameCopy, FrameGuard)>);
 
 pub mod reexport {
     use wayland_client::protocol::wl_output;
     pub use wl_output::{Transform, WlOutput};
 }
-use gbm::{BufferObject, BufferObjectFlags, Device as GBMDevice};
+use gbm::{BufferObject, Device as GBMDevice};
 
 /// Struct to store wayland connection and globals list.
 /// # Example usage
@@ -102,6 +123,12 @@ pub struct WayshotConnection {
     output_infos: Vec<OutputInfo>,
     toplevel_infos: Vec<TopLevel>,
     dmabuf_state: Option<DMABUFState>,
+    dmabuf_preference: DmabufPreference,
+    scale_policy: ScalePolicy,
+    resample_filter: FilterType,
+    toplevel_size: ToplevelSize,
+    output_watcher: Option<watch::OutputWatcher>,
+    toplevel_watcher: Option<watch::ToplevelWatcher>,
 }
 
 pub enum WayshotFrame {
@@ -109,6 +136,22 @@ pub enum WayshotFrame {
     ExtImageCopy(ExtImageCopyCaptureFrameV1),
 }
 
+impl WayshotFrame {
+    /// Whether the compositor finished advertising the buffer constraints for this frame.
+    pub(crate) fn formats_received(&self, state: &CaptureFrameState) -> bool {
+        match self {
+            WayshotFrame::WlrScreenshot(_) => state.buffer_done.load(Ordering::SeqCst),
+            WayshotFrame::ExtImageCopy(_) => state.session_done || state.session_stopped,
+        }
+    }
+
+    /// Whether the compositor captures the whole output even though `capture_region` was
+    /// requested, leaving the cropping to us. ext-image-copy-capture has no region captures.
+    pub(crate) fn needs_crop(&self, capture_region: Option<EmbeddedRegion>) -> bool {
+        capture_region.is_some() && matches!(self, WayshotFrame::ExtImageCopy(_))
+    }
+}
+
 impl WayshotConnection {
     pub fn new() -> Result<Self> {
         let conn = Connection::connect_to_env()?;
@@ -132,6 +175,12 @@ impl WayshotConnection {
             output_infos: Vec::new(),
             toplevel_infos: Vec::new(),
             dmabuf_state: None,
+            dmabuf_preference: DmabufPreference::default(),
+            scale_policy: ScalePolicy::default(),
+            resample_filter: FilterType::Gaussian,
+            toplevel_size: ToplevelSize::default(),
+            output_watcher: None,
+            toplevel_watcher: None,
         };
 
         initial_state.refresh_outputs()?;
@@ -152,6 +201,9 @@ impl WayshotConnection {
         let gpu = dispatch::Card::open(device_path);
         // init a GBM device
         let gbm = GBMDevice::new(gpu).unwrap();
+        let feedback = dmabuf::fetch_dmabuf_feedback(&conn, &linux_dmabuf)
+            .inspect_err(|e| tracing::warn!("Failed to get DMA-BUF feedback: {e}"))
+            .ok();
         let mut initial_state = Self {
             conn,
             globals,
@@ -160,7 +212,14 @@ impl WayshotConnection {
             dmabuf_state: Some(DMABUFState {
                 linux_dmabuf,
                 gbmdev: gbm,
+                feedback,
             }),
+            dmabuf_preference: DmabufPreference::default(),
+            scale_policy: ScalePolicy::default(),
+            resample_filter: FilterType::Gaussian,
+            toplevel_size: ToplevelSize::default(),
+            output_watcher: None,
+            toplevel_watcher: None,
         };
 
         initial_state.refresh_outputs()?;
@@ -168,6 +227,30 @@ impl WayshotConnection {
         Ok(initial_state)
     }
 
+    /// Set how DMA-BUF captures choose between the formats and modifiers supported by both the
+    /// compositor and the GBM device. Defaults to [`DmabufPreference::Native`].
+    pub fn set_dmabuf_preference(&mut self, preference: DmabufPreference) {
+        self.dmabuf_preference = preference;
+    }
+
+    /// Set how screenshots spanning outputs with different scales are sized. Defaults to
+    /// [`ScalePolicy::Max`].
+    pub fn set_scale_policy(&mut self, scale_policy: ScalePolicy) {
+        self.scale_policy = scale_policy;
+    }
+
+    /// Set the filter used to resample outputs whose scale differs from the screenshot's.
+    /// Defaults to [`FilterType::Gaussian`], like earlier releases. [`FilterType::Nearest`] keeps
+    /// integer scale factors sharp and is the fastest.
+    pub fn set_resample_filter(&mut self, filter: FilterType) {
+        self.resample_filter = filter;
+    }
+
+    /// Set how toplevel captures are sized. Defaults to [`ToplevelSize::Native`].
+    pub fn set_toplevel_size(&mut self, toplevel_size: ToplevelSize) {
+        self.toplevel_size = toplevel_size;
+    }
+
     /// Fetch all accessible wayland outputs.
     pub fn get_all_outputs(&self) -> &[OutputInfo] {
         self.output_infos.as_slice()
@@ -231,54 +314,47 @@ impl WayshotConnection {
         self.toplevel_infos.as_slice()
     }
 
+    /// Update [`WayshotConnection::get_all_toplevels`] with the windows that are currently open.
+    ///
+    /// When [`WayshotConnection::watch_toplevels`] was called, this applies the pending changes
+    /// instead of taking a new snapshot.
     pub fn refresh_toplevels(&mut self) -> Result<()> {
-        let mut state = CaptureFrameState {
-            formats: Vec::new(),
-            dmabuf_formats: Vec::new(),
-            state: None,
-            buffer_done: AtomicBool::new(false),
-            toplevels: Vec::new(),
-        };
-
-        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
-        let qh = event_queue.handle();
-
-        let _toplevel_list = self
-            .globals
-            .bind::<ExtForeignToplevelListV1, _, _>(&qh, 1..=1, ())?;
-
-        event_queue.roundtrip(&mut state)?;
-
-        self.toplevel_infos = state.toplevels;
+        if self.toplevel_watcher.is_some() {
+            self.dispatch_toplevel_events()?;
+            return Ok(());
+        }
+        let (_event_queue, state) = self.bind_toplevel_list()?;
+        self.toplevel_infos = state.current_toplevels();
         Ok(())
     }
 
     /// print the displays' info
     pub fn print_displays_info(&self) {
-        for OutputInfo {
-            physical_size: Size { width, height },
-            logical_region:
-                LogicalRegion {
-                    inner:
-                        region::Region {
-                            position: region::Position { x, y },
-                            size:
-                                Size {
-                                    width: logical_width,
-                                    height: logical_height,
-                                },
-                        },
-                },
-            name,
-            description,
-            ..
-        } in self.get_all_outputs()
-        {
+        for output_info in self.get_all_outputs() {
+            let OutputInfo {
+                physical_size: Size { width, height },
+                logical_region:
+                    LogicalRegion {
+                        inner:
+                            region::Region {
+                                position: region::Position { x, y },
+                                size:
+                                    Size {
+                                        width: logical_width,
+                                        height: logical_height,
+                                    },
+                            },
+                    },
+                name,
+                description,
+                ..
+            } = output_info;
             println!("{name}");
             println!("description: {description}");
             println!("    Size: {width},{height}");
             println!("    LogicSize: {logical_width}, {logical_height}");
             println!("    Position: {x}, {y}");
+            println!("    Scale: {}", output_info.scale());
         }
     }
 
@@ -301,6 +377,7 @@ impl WayshotConnection {
     /// - `fd`: file descriptor where the capture buffer will be written.
     /// - `frame_format`: `wl_shm::Format` to use for screen capture.
     /// - `capture_region`: Optional region specifying a sub-area of the output to capture. If `None`, the entire output is captured.
+    ///   The ext-image-copy-capture protocol always captures the entire output, when it is used the buffer has to be cropped by the caller.
     /// # Returns
     /// - A [`FrameGuard`] instance that holds the screen capture result, if screen capture is successful and frame_format is supported.
     /// - [`Error::FramecopyFailed`] if screen capture fails.
@@ -321,7 +398,7 @@ impl WayshotConnection {
             .find(|f| f.format == frame_format)
             .copied()
         {
-            let frame_guard: FrameGuard =
+            let (frame_guard, _) =
                 self.capture_output_frame_inner(state, event_queue, frame, format, fd)?;
             Ok(frame_guard)
         } else {
@@ -330,6 +407,8 @@ impl WayshotConnection {
     }
     /// Get a FrameCopy instance with screenshot pixel data for any wl_output object.
     ///  Data will be written to fd.
+    ///  On the ext-image-copy-capture path the whole output is captured regardless of
+    ///  `capture_region`, compare the returned [`FrameFormat`] size to find out.
     pub fn capture_output_frame_shm_fd<T: AsFd>(
         &self,
         cursor_overlay: i32,
@@ -339,28 +418,31 @@ impl WayshotConnection {
     ) -> Result<(FrameFormat, FrameGuard)> {
         let (state, event_queue, frame, frame_format) =
             self.capture_output_frame_get_state_shm(cursor_overlay, output, capture_region)?;
-        let frame_guard =
+        let (frame_guard, _) =
             self.capture_output_frame_inner(state, event_queue, frame, frame_format, fd)?;
 
         Ok((frame_format, frame_guard))
     }
 
+    /// Capture `output` into `file`. The returned flag tells whether the buffer holds the whole
+    /// output although a `capture_region` was requested, see [`FrameCopy::from_shm_mmap`].
     fn capture_output_frame_shm_from_file(
         &self,
         cursor_overlay: bool,
         output: &WlOutput,
         file: &File,
         capture_region: Option<EmbeddedRegion>,
-    ) -> Result<(FrameFormat, FrameGuard)> {
+    ) -> Result<(FrameFormat, FrameGuard, Option<Vec<Region>>, bool)> {
         let (state, event_queue, frame, frame_format) =
             self.capture_output_frame_get_state_shm(cursor_overlay as i32, output, capture_region)?;
+        let crop = frame.needs_crop(capture_region);
 
         file.set_len(frame_format.byte_size())?;
 
-        let frame_guard =
+        let (frame_guard, damage) =
             self.capture_output_frame_inner(state, event_queue, frame, frame_format, file)?;
 
-        Ok((frame_format, frame_guard))
+        Ok((frame_format, frame_guard, damage, crop))
     }
     /// # Safety
     ///
@@ -537,109 +619,134 @@ impl WayshotConnection {
     ///   a guard to manage the frame's lifecycle, and the GPU-backed `BufferObject`.
     /// # Errors
     /// - Returns `NoDMAStateError` if the DMA-BUF state is not initialized a the time of initialization of this struct.
+    /// - Returns `DmabufRegionUnsupported` if a `capture_region` is given but the compositor
+    ///   captures through ext-image-copy-capture, which only captures whole outputs.
     pub fn capture_output_frame_dmabuf(
         &self,
         cursor_overlay: bool,
         output: &WlOutput,
         capture_region: Option<EmbeddedRegion>,
     ) -> Result<(DMAFrameFormat, DMAFrameGuard, BufferObject<()>)> {
-        match &self.dmabuf_state {
-            Some(dmabuf_state) => {
-                let (state, event_queue, frame, frame_format) = self
-                    .capture_output_frame_get_state_dmabuf(
-                        cursor_overlay as i32,
-                        output,
-                        capture_region,
-                    )?;
-                let gbm = &dmabuf_state.gbmdev;
-                let gbm_format = gbm::Format::try_from(frame_format.format)?;
-                let usage_candidates = [
-                    BufferObjectFlags::RENDERING | BufferObjectFlags::LINEAR,
-                    BufferObjectFlags::RENDERING,
-                    BufferObjectFlags::WRITE | BufferObjectFlags::LINEAR,
-                    BufferObjectFlags::WRITE,
-                    BufferObjectFlags::LINEAR,
-                    BufferObjectFlags::empty(),
-                ];
-                let mut buffer_object = None;
-                for usage in usage_candidates {
-                    if !usage.is_empty() && !gbm.is_format_supported(gbm_format, usage) {
-                        continue;
-                    }
-                    match gbm.create_buffer_object::<()>(
-                        frame_format.size.width,
-                        frame_format.size.height,
-                        gbm_format,
-                        usage,
-                    ) {
-                        Ok(bo) => {
-                            tracing::debug!(
-                                "Created GBM buffer with usage flags {usage:?} for format 0x{:x}",
-                                frame_format.format
-                            );
-                            buffer_object = Some(bo);
-                            break;
-                        }
-                        Err(err) => {
-                            tracing::debug!(
-                                "Failed to create GBM buffer with usage {usage:?}: {err}"
-                            );
-                        }
-                    }
-                }
-                let bo = buffer_object.ok_or_else(|| {
-                    tracing::error!(
-                        "Unable to allocate GBM buffer for format 0x{:x}",
-                        frame_format.format
-                    );
-                    Error::NoSupportedBufferFormat
-                })?;
-
-                let stride = bo.stride();
-                let modifier: u64 = bo.modifier().into();
-                tracing::debug!(
-                    "Created GBM Buffer object with input frame format {:#?}, stride {:#?} and modifier {:#?} ",
-                    frame_format,
-                    stride,
-                    modifier
-                );
-                let frame_guard = self.capture_output_frame_inner_dmabuf(
-                    state,
-                    event_queue,
-                    frame,
-                    frame_format,
-                    stride,
-                    modifier,
-                    bo.fd_for_plane(0).unwrap(),
-                )?;
-
-                Ok((frame_format, frame_guard, bo))
-            }
-            None => Err(Error::NoDMAStateError),
+        if self.dmabuf_state.is_none() {
+            return Err(Error::NoDMAStateError);
         }
+        let state = CaptureFrameState::default();
+        let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+
+        tracing::debug!("Capturing output for DMA-BUF API...");
+        if capture_region.is_some() && self.has_ext_image_copy_capture() {
+            // GPU buffers are not cropped on the client, the frame would cover the whole output.
+            return Err(Error::DmabufRegionUnsupported);
+        }
+        let frame =
+            self.request_output_frame(&qh, (), cursor_overlay as i32, output, capture_region)?;
+        let (frame_format, frame_guard, bo, _) =
+            self.capture_frame_into_dmabuf(state, event_queue, frame)?;
+        Ok((frame_format, frame_guard, bo))
     }
-    // This API is exposed to provide users with access to window manager (WM)
-    // information. For instance, enabling Vulkan in wlroots alters the display
-    // format. Consequently, using PipeWire to capture streams without knowing
-    // the current format can lead to color distortion. This function attempts
-    // a trial screenshot to determine the screen's properties.
-    fn capture_output_frame_get_state_wlr(
+
+    /// Obtain a capture of a toplevel (window) in a WlBuffer backed by a GBM Bufferobject.
+    ///
+    /// Toplevels can only be captured through ext-image-copy-capture, see
+    /// [`WayshotConnection::capture_output_frame_dmabuf`] for the returned values.
+    pub fn capture_toplevel_frame_dmabuf(
+        &self,
+        toplevel: &TopLevel,
+        cursor_overlay: bool,
+    ) -> Result<(DMAFrameFormat, DMAFrameGuard, BufferObject<()>)> {
+        if self.dmabuf_state.is_none() {
+            return Err(Error::NoDMAStateError);
+        }
+        let state = CaptureFrameState::default();
+        let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+
+        let frame = self.request_toplevel_frame(&qh, toplevel, cursor_overlay)?;
+        let (frame_format, frame_guard, bo, _) =
+            self.capture_frame_into_dmabuf(state, event_queue, WayshotFrame::ExtImageCopy(frame))?;
+        Ok((frame_format, frame_guard, bo))
+    }
+
+    /// Wait for the buffer constraints of `frame`, allocate a matching GBM buffer object and let
+    /// the compositor copy the frame into it. Also returns the transform the compositor reported
+    /// for the frame, if any.
+    fn capture_frame_into_dmabuf(
         &self,
         mut state: CaptureFrameState,
         mut event_queue: EventQueue<CaptureFrameState>,
-        cursor_overlay: i32,
-        output: &WlOutput,
-        capture_region: Option<EmbeddedRegion>,
+        frame: WayshotFrame,
     ) -> Result<(
-        CaptureFrameState,
-        EventQueue<CaptureFrameState>,
-        WayshotFrame,
+        DMAFrameFormat,
+        DMAFrameGuard,
+        BufferObject<()>,
+        Option<Transform>,
     )> {
-        let qh = event_queue.handle();
+        let Some(dmabuf_state) = &self.dmabuf_state else {
+            return Err(Error::NoDMAStateError);
+        };
+
+        // Empty internal event buffer until the compositor told us about every buffer format
+        // it supports for this frame.
+        while !frame.formats_received(&state) {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+        if state.session_stopped {
+            return Err(Error::SessionStopped);
+        }
+        tracing::trace!(
+            "Received compositor frame buffer formats: {:#?}",
+            state.dmabuf_formats
+        );
+        if let Some(device) = state.constraints.dmabuf_device {
+            tracing::debug!("Compositor wants DMA-BUFs allocated on device {device:#x}");
+        }
+
+        let gbm = &dmabuf_state.gbmdev;
+        let candidate = select_dmabuf_format(
+            &state.dmabuf_formats,
+            &state.constraints.dmabuf_formats,
+            dmabuf_state.feedback.as_ref(),
+            |format| gbm_supports_format(gbm, format),
+            self.dmabuf_preference,
+        )
+        .ok_or_else(|| {
+            tracing::error!("No DMA-BUF format is supported by both the compositor and GBM");
+            Error::NoSupportedBufferFormat
+        })?;
+        let frame_format = candidate.frame_format;
+        tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
+
+        let bo = allocate_buffer_object(gbm, &candidate, self.dmabuf_preference)?;
+        tracing::debug!(
+            "Created GBM Buffer object with input frame format {:#?}, {} plane(s) and modifier {:#x}",
+            frame_format,
+            bo.plane_count(),
+            u64::from(bo.modifier())
+        );
+        let (frame_guard, transform) =
+            self.capture_output_frame_inner_dmabuf(state, event_queue, frame, frame_format, &bo)?;
+
+        Ok((frame_format, frame_guard, bo, transform))
+    }
 
+    /// Ask the compositor for a frame of `output` through wlr-screencopy, without waiting for
+    /// any events.
+    fn request_output_frame_wlr<D, U>(
+        &self,
+        qh: &QueueHandle<D>,
+        frame_data: U,
+        cursor_overlay: i32,
+        output: &WlOutput,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<WayshotFrame>
+    where
+        D: CaptureDispatch<U>,
+        U: Send + Sync + 'static,
+    {
         // Instantiating screencopy manager.
         let screencopy_manager = match self.globals.bind::<ZwlrScreencopyManagerV1, _, _>(
-            &qh,
+            qh,
             3..=3,
             (),
         ) {
@@ -664,55 +771,138 @@ impl WayshotConnection {
                 embedded_region.inner.position.y,
                 embedded_region.inner.size.width as i32,
                 embedded_region.inner.size.height as i32,
-                &qh,
-                (),
+                qh,
+                frame_data,
             )
         } else {
-            screencopy_manager.capture_output(cursor_overlay, output, &qh, ())
+            screencopy_manager.capture_output(cursor_overlay, output, qh, frame_data)
         };
 
-        // Empty internal event buffer until buffer_done is set to true which is when the Buffer done
-        // event is fired, aka the capture from the compositor is successful.
-        while !state.buffer_done.load(Ordering::SeqCst) {
-            event_queue.blocking_dispatch(&mut state)?;
-        }
-
-        tracing::trace!(
-            "Received compositor frame buffer formats: {:#?}",
-            state.formats
-        );
-        Ok((state, event_queue, WayshotFrame::WlrScreenshot(frame)))
+        Ok(WayshotFrame::WlrScreenshot(frame))
     }
 
-    fn capture_output_frame_get_state_ext(
+    /// Ask the compositor for a frame of `output` through ext-image-copy-capture, without
+    /// waiting for any events.
+    fn request_output_frame_ext<D, U>(
         &self,
-        mut state: CaptureFrameState,
-        mut event_queue: EventQueue<CaptureFrameState>,
+        qh: &QueueHandle<D>,
+        frame_data: U,
         manager: ExtImageCopyCaptureManagerV1,
         cursor_overlay: i32,
         output: &WlOutput,
-    ) -> Result<(
-        CaptureFrameState,
-        EventQueue<CaptureFrameState>,
-        WayshotFrame,
-    )> {
-        let qh = event_queue.handle();
+    ) -> Result<WayshotFrame>
+    where
+        D: CaptureDispatch<U>,
+        U: Clone + Send + Sync + 'static,
+    {
         let output_management = self
             .globals
-            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
-            .expect("Should have");
-        let source = output_management.create_source(output, &qh, ());
+            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(qh, 1..=1, ())?;
+        let source = output_management.create_source(output, qh, ());
         let options = Options::from_bits(cursor_overlay.try_into().unwrap_or(0))
             .unwrap_or(Options::PaintCursors);
-        let session = manager.create_session(&source, options, &qh, ());
-        let frame = session.create_frame(&qh, ());
-        event_queue.blocking_dispatch(&mut state)?;
-        tracing::trace!(
-            "Received compositor frame buffer formats: {:#?}",
-            state.formats
+        let session = manager.create_session(&source, options, qh, frame_data.clone());
+        let frame = session.create_frame(qh, frame_data);
+
+        Ok(WayshotFrame::ExtImageCopy(frame))
+    }
+
+    /// Whether the compositor advertises ext-image-copy-capture, which
+    /// [`WayshotConnection::request_output_frame`] prefers over wlr-screencopy.
+    fn has_ext_image_copy_capture(&self) -> bool {
+        self.globals.contents().with_list(|globals| {
+            globals
+                .iter()
+                .any(|global| global.interface == ExtImageCopyCaptureManagerV1::interface().name)
+        })
+    }
+
+    /// Ask the compositor for a frame of `output`, preferring ext-image-copy-capture over
+    /// wlr-screencopy. No events are dispatched, use [`WayshotFrame::formats_received`] to
+    /// find out when the buffer constraints of the frame are known.
+    ///
+    /// `frame_data` becomes the user data of the frame and, for ext-image-copy-capture, of its
+    /// session.
+    pub(crate) fn request_output_frame<D, U>(
+        &self,
+        qh: &QueueHandle<D>,
+        frame_data: U,
+        cursor_overlay: i32,
+        output: &WlOutput,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<WayshotFrame>
+    where
+        D: CaptureDispatch<U>,
+        U: Clone + Send + Sync + 'static,
+    {
+        match self
+            .globals
+            .bind::<ExtImageCopyCaptureManagerV1, _, _>(qh, 1..=1, ())
+        {
+            Ok(manager) => {
+                self.request_output_frame_ext(qh, frame_data, manager, cursor_overlay, output)
+            }
+            Err(_) => self.request_output_frame_wlr(
+                qh,
+                frame_data,
+                cursor_overlay,
+                output,
+                capture_region,
+            ),
+        }
+    }
+
+    /// Create a shm buffer backed by `fd` and ask the compositor to copy `frame` into it.
+    ///
+    /// The copy has finished once [`CaptureFrameState::frame_result`] returns a result.
+    pub(crate) fn attach_shm_buffer<D, T>(
+        &self,
+        qh: &QueueHandle<D>,
+        frame: &WayshotFrame,
+        frame_format: FrameFormat,
+        fd: T,
+    ) -> Result<FrameGuard>
+    where
+        D: Dispatch<WlShm, ()> + Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+        T: AsFd,
+    {
+        // Instantiate shm global.
+        let shm = self.globals.bind::<WlShm, _, _>(qh, 1..=1, ())?;
+        let shm_pool = shm.create_pool(
+            fd.as_fd(),
+            frame_format
+                .byte_size()
+                .try_into()
+                .map_err(|_| Error::BufferTooSmall)?,
+            qh,
+            (),
+        );
+        let buffer = shm_pool.create_buffer(
+            0,
+            frame_format.size.width as i32,
+            frame_format.size.height as i32,
+            frame_format.stride as i32,
+            frame_format.format,
+            qh,
+            (),
         );
 
-        Ok((state, event_queue, WayshotFrame::ExtImageCopy(frame)))
+        // Copy the pixel data advertised by the compositor into the buffer we just created.
+        match frame {
+            WayshotFrame::WlrScreenshot(frame) => frame.copy(&buffer),
+            WayshotFrame::ExtImageCopy(frame) => {
+                frame.attach_buffer(&buffer);
+                frame.damage_buffer(
+                    0,
+                    0,
+                    frame_format.size.width as i32,
+                    frame_format.size.height as i32,
+                );
+                frame.capture();
+            }
+        }
+
+        Ok(FrameGuard { buffer, shm_pool })
     }
 
     fn capture_output_frame_get_state_shm(
@@ -728,28 +918,7 @@ impl WayshotConnection {
     )> {
         let (state, event_queue, frame) =
             self.capture_output_frame_get_state(cursor_overlay, output, capture_region)?;
-        // Filter advertised wl_shm formats and select the first one that matches.
-        let frame_format = state
-            .formats
-            .iter()
-            .find(|frame| {
-                matches!(
-                    frame.format,
-                    wl_shm::Format::Xbgr2101010
-                        | wl_shm::Format::Xrgb2101010
-                        | wl_shm::Format::Abgr2101010
-                        | wl_shm::Format::Argb8888
-                        | wl_shm::Format::Xrgb8888
-                        | wl_shm::Format::Xbgr8888
-                        | wl_shm::Format::Bgr888
-                )
-            })
-            .copied()
-            // Check if frame format exists.
-            .ok_or_else(|| {
-                tracing::error!("No suitable frame format found");
-                Error::NoSupportedBufferFormat
-            })?;
+        let frame_format = select_shm_format(&state.formats)?;
         tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
 
         Ok((state, event_queue, frame, frame_format))
@@ -770,106 +939,25 @@ impl WayshotConnection {
         EventQueue<CaptureFrameState>,
         WayshotFrame,
     )> {
-        let state = CaptureFrameState {
-            formats: Vec::new(),
-            dmabuf_formats: Vec::new(),
-            state: None,
-            buffer_done: AtomicBool::new(false),
-            toplevels: Vec::new(),
-        };
-        let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
-        let qh = event_queue.handle();
-        match self
-            .globals
-            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
-        {
-            Ok(manager) => self.capture_output_frame_get_state_ext(
-                state,
-                event_queue,
-                manager,
-                cursor_overlay,
-                output,
-            ),
-            Err(_) => self.capture_output_frame_get_state_wlr(
-                state,
-                event_queue,
-                cursor_overlay,
-                output,
-                capture_region,
-            ),
-        }
-    }
-
-    fn capture_output_frame_get_state_dmabuf(
-        &self,
-        cursor_overlay: i32,
-        output: &WlOutput,
-        capture_region: Option<EmbeddedRegion>,
-    ) -> Result<(
-        CaptureFrameState,
-        EventQueue<CaptureFrameState>,
-        ZwlrScreencopyFrameV1,
-        DMAFrameFormat,
-    )> {
-        let mut state = CaptureFrameState {
-            formats: Vec::new(),
-            dmabuf_formats: Vec::new(),
-            state: None,
-            buffer_done: AtomicBool::new(false),
-            toplevels: Vec::new(),
-        };
+        let mut state = CaptureFrameState::default();
         let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
         let qh = event_queue.handle();
+        let frame = self.request_output_frame(&qh, (), cursor_overlay, output, capture_region)?;
 
-        // Instantiating screencopy manager.
-        let screencopy_manager = match self.globals.bind::<ZwlrScreencopyManagerV1, _, _>(
-            &qh,
-            3..=3,
-            (),
-        ) {
-            Ok(x) => x,
-            Err(e) => {
-                tracing::error!(
-                    "Failed to create screencopy manager. Does your compositor implement ZwlrScreencopy?"
-                );
-                tracing::error!("err: {e}");
-                return Err(Error::ProtocolNotFound(
-                    "ZwlrScreencopy Manager not found".to_string(),
-                ));
-            }
-        };
-
-        tracing::debug!("Capturing output for DMA-BUF API...");
-        let frame = if let Some(embedded_region) = capture_region {
-            screencopy_manager.capture_output_region(
-                cursor_overlay,
-                output,
-                embedded_region.inner.position.x,
-                embedded_region.inner.position.y,
-                embedded_region.inner.size.width as i32,
-                embedded_region.inner.size.height as i32,
-                &qh,
-                (),
-            )
-        } else {
-            screencopy_manager.capture_output(cursor_overlay, output, &qh, ())
-        };
-
-        // Empty internal event buffer until buffer_done is set to true which is when the Buffer done
-        // event is fired, aka the capture from the compositor is successful.
-        while !state.buffer_done.load(Ordering::SeqCst) {
+        // Empty internal event buffer until the compositor told us about every buffer format
+        // it supports for this frame.
+        while !frame.formats_received(&state) {
             event_queue.blocking_dispatch(&mut state)?;
         }
+        if state.session_stopped {
+            return Err(Error::SessionStopped);
+        }
 
         tracing::trace!(
             "Received compositor frame buffer formats: {:#?}",
             state.formats
         );
-        // TODO select appropriate format if there is more than one
-        let frame_format = state.dmabuf_formats[0];
-        tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
-
-        Ok((state, event_queue, frame, frame_format))
+        Ok((state, event_queue, frame))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -877,12 +965,10 @@ impl WayshotConnection {
         &self,
         mut state: CaptureFrameState,
         mut event_queue: EventQueue<CaptureFrameState>,
-        frame: ZwlrScreencopyFrameV1,
+        frame: WayshotFrame,
         frame_format: DMAFrameFormat,
-        stride: u32,
-        modifier: u64,
-        fd: OwnedFd,
-    ) -> Result<DMAFrameGuard> {
+        bo: &BufferObject<()>,
+    ) -> Result<(DMAFrameGuard, Option<Transform>)> {
         let Some(dmabuf_state) = &self.dmabuf_state else {
             return Err(Error::NoDMAStateError);
         };
@@ -896,14 +982,20 @@ impl WayshotConnection {
 
         let dma_params = linux_dmabuf.create_params(&qh, ());
 
-        dma_params.add(
-            fd.as_fd(),
-            0,
-            0,
-            stride,
-            (modifier >> 32) as u32,
-            (modifier & 0xffffffff) as u32,
-        );
+        let modifier: u64 = bo.modifier().into();
+        for plane in 0..bo.plane_count() {
+            let plane_index = plane as i32;
+            dma_params.add(
+                bo.fd_for_plane(plane_index)
+                    .map_err(std::io::Error::other)?
+                    .as_fd(),
+                plane,
+                bo.offset(plane_index),
+                bo.stride_for_plane(plane_index),
+                (modifier >> 32) as u32,
+                (modifier & 0xffffffff) as u32,
+            );
+        }
         tracing::trace!("Called  ZwpLinuxBufferParamsV1::create_params ");
         let dmabuf_wlbuf = dma_params.create_immed(
             dma_width as i32,
@@ -915,152 +1007,50 @@ impl WayshotConnection {
         );
         tracing::trace!("Called  ZwpLinuxBufferParamsV1::create_immed to create WlBuffer ");
         // Copy the pixel data advertised by the compositor into the buffer we just created.
-        frame.copy(&dmabuf_wlbuf);
-        tracing::debug!("wlr-screencopy copy() with dmabuf complete");
+        match &frame {
+            WayshotFrame::WlrScreenshot(frame) => {
+                frame.copy(&dmabuf_wlbuf);
+                tracing::debug!("wlr-screencopy copy() with dmabuf complete");
+            }
+            WayshotFrame::ExtImageCopy(frame) => {
+                frame.attach_buffer(&dmabuf_wlbuf);
+                frame.damage_buffer(0, 0, dma_width as i32, dma_height as i32);
+                frame.capture();
+                tracing::debug!("ext-image-copy-capture capture() with dmabuf requested");
+            }
+        }
 
         // On copy the Ready / Failed events are fired by the frame object, so here we check for them.
         loop {
-            // Basically reads, if frame state is not None then...
-            if let Some(state) = state.state {
-                match state {
-                    FrameState::Failed => {
-                        tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailed);
-                    }
-                    FrameState::Finished => {
-                        tracing::trace!("Frame copy finished");
-
-                        return Ok(DMAFrameGuard {
-                            buffer: dmabuf_wlbuf,
-                        });
-                    }
-                }
-            }
+            if let Some(result) = state.frame_result() {
+                result?;
+                let frame_guard = DMAFrameGuard {
+                    buffer: dmabuf_wlbuf,
+                };
+                return Ok((frame_guard, state.transform));
+            }
 
             event_queue.blocking_dispatch(&mut state)?;
         }
     }
 
     fn capture_output_frame_inner<T: AsFd>(
-        &self,
-        state: CaptureFrameState,
-        event_queue: EventQueue<CaptureFrameState>,
-        frame: WayshotFrame,
-        frame_format: FrameFormat,
-        fd: T,
-    ) -> Result<FrameGuard> {
-        match frame {
-            WayshotFrame::WlrScreenshot(frame) => {
-                self.capture_output_frame_inner_wlr(state, event_queue, frame, frame_format, fd)
-            }
-            WayshotFrame::ExtImageCopy(frame) => {
-                self.capture_output_frame_inner_ext(state, event_queue, frame, frame_format, fd)
-            }
-        }
-    }
-
-    fn capture_output_frame_inner_wlr<T: AsFd>(
         &self,
         mut state: CaptureFrameState,
         mut event_queue: EventQueue<CaptureFrameState>,
-        frame: ZwlrScreencopyFrameV1,
+        frame: WayshotFrame,
         frame_format: FrameFormat,
         fd: T,
-    ) -> Result<FrameGuard> {
+    ) -> Result<(FrameGuard, Option<Vec<Region>>)> {
         // Connecting to wayland environment.
         let qh = event_queue.handle();
+        let frame_guard = self.attach_shm_buffer(&qh, &frame, frame_format, fd)?;
 
-        // Instantiate shm global.
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            fd.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
-
-        // Copy the pixel data advertised by the compositor into the buffer we just created.
-        frame.copy(&buffer);
         // On copy the Ready / Failed events are fired by the frame object, so here we check for them.
         loop {
-            // Basically reads, if frame state is not None then...
-            if let Some(state) = state.state {
-                match state {
-                    FrameState::Failed => {
-                        tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailed);
-                    }
-                    FrameState::Finished => {
-                        tracing::trace!("Frame copy finished");
-                        return Ok(FrameGuard { buffer, shm_pool });
-                    }
-                }
-            }
-
-            event_queue.blocking_dispatch(&mut state)?;
-        }
-    }
-    fn capture_output_frame_inner_ext<T: AsFd>(
-        &self,
-        mut state: CaptureFrameState,
-        mut event_queue: EventQueue<CaptureFrameState>,
-        frame: ExtImageCopyCaptureFrameV1,
-        frame_format: FrameFormat,
-        fd: T,
-    ) -> Result<FrameGuard> {
-        // Connecting to wayland environment.
-        let qh = event_queue.handle();
-
-        // Instantiate shm global.
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            fd.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
-
-        // Copy the pixel data advertised by the compositor into the buffer we just created.
-        frame.attach_buffer(&buffer);
-        frame.capture();
-        // On copy the Ready / Failed events are fired by the frame object, so here we check for them.
-        loop {
-            // Basically reads, if frame state is not None then...
-            if let Some(state) = state.state {
-                match state {
-                    FrameState::Failed => {
-                        tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailed);
-                    }
-                    FrameState::Finished => {
-                        tracing::trace!("Frame copy finished");
-                        return Ok(FrameGuard { buffer, shm_pool });
-                    }
-                }
+            if let Some(result) = state.frame_result() {
+                result?;
+                return Ok((frame_guard, state.damage));
             }
 
             event_queue.blocking_dispatch(&mut state)?;
@@ -1080,47 +1070,21 @@ impl WayshotConnection {
         // Create a writeable memory map backed by a mem_file.
         let mem_file = File::from(fd);
 
-        let (frame_format, frame_guard) = self.capture_output_frame_shm_from_file(
+        let (frame_format, frame_guard, damage, crop) = self.capture_output_frame_shm_from_file(
             cursor_overlay,
             &output_info.wl_output,
             &mem_file,
             capture_region,
         )?;
-        let mut frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
-        let data = &mut *frame_mmap;
-        let frame_color_type = match frame_format.format {
-            wl_shm::Format::Xrgb2101010 | wl_shm::Format::Xbgr2101010 => ColorType::Rgb16,
-            wl_shm::Format::Argb2101010 | wl_shm::Format::Abgr2101010 => ColorType::Rgba16,
-            _ => match create_converter(frame_format.format) {
-                Some(converter) => converter.convert_inplace(data),
-                _ => {
-                    tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
-                    tracing::error!(
-                        "You can send a feature request for the above format to the mailing list for wayshot over at https://sr.ht/~shinyzenith/wayshot."
-                    );
-                    return Err(Error::NoSupportedBufferFormat);
-                }
-            },
-        };
-        let rotated_physical_size = match output_info.transform {
-            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                Size {
-                    width: frame_format.size.height,
-                    height: frame_format.size.width,
-                }
-            }
-            _ => frame_format.size,
-        };
-        let frame_copy = FrameCopy {
+        let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+        let frame_copy = FrameCopy::from_shm_mmap(
             frame_format,
-            frame_color_type,
-            frame_data: FrameData::Mmap(frame_mmap),
-            transform: output_info.transform,
-            logical_region: capture_region
-                .map(|capture_region| capture_region.logical())
-                .unwrap_or(output_info.logical_region),
-            physical_size: rotated_physical_size,
-        };
+            frame_mmap,
+            output_info,
+            capture_region,
+            damage.as_deref(),
+            crop,
+        )?;
         tracing::debug!("Created frame copy: {:#?}", frame_copy);
         Ok((frame_copy, frame_guard))
     }
@@ -1163,21 +1127,163 @@ impl WayshotConnection {
         Ok((frame_copy, frame_guard))
     }
 
+    /// Capture a toplevel (window) into a DMA-BUF backed frame.
+    ///
+    /// The logical region of the returned frame starts at the origin, as toplevels are not placed
+    /// in the global compositor space, and is sized according to the [`ToplevelSize`] of the
+    /// connection.
+    pub fn capture_toplevel_frame_copy_dmabuf(
+        &self,
+        toplevel: &TopLevel,
+        cursor_overlay: bool,
+    ) -> Result<(DMAFrameCopy, DMAFrameGuard)> {
+        if self.dmabuf_state.is_none() {
+            return Err(Error::NoDMAStateError);
+        }
+        let state = CaptureFrameState::default();
+        let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+
+        let frame = self.request_toplevel_frame(&qh, toplevel, cursor_overlay)?;
+        let (frame_format, frame_guard, buffer_object, transform) =
+            self.capture_frame_into_dmabuf(state, event_queue, WayshotFrame::ExtImageCopy(frame))?;
+        let transform = transform.unwrap_or(Transform::Normal);
+        let (physical_size, logical_region) =
+            self.toplevel_geometry(frame_format.size, transform)?;
+        let frame_copy = DMAFrameCopy {
+            frame_format,
+            buffer_object,
+            transform,
+            logical_region,
+            physical_size,
+        };
+        tracing::debug!("Created DMA frame copy: {:#?}", frame_copy);
+        Ok((frame_copy, frame_guard))
+    }
+
+    /// Capture every output/region pair concurrently.
+    ///
+    /// Fails with [`Error::OutputCaptureFailed`] naming the first output that could not be
+    /// captured, see [`WayshotConnection::capture_frame_copies_per_output`] to get the frames of
+    /// the other outputs anyway.
     pub fn capture_frame_copies(
         &self,
         output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
         cursor_overlay: bool,
     ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
-        output_capture_regions
-            .iter()
-            .map(|(output_info, capture_region)| {
-                self.capture_frame_copy(cursor_overlay, output_info, *capture_region)
-                    .map(|(frame_copy, frame_guard)| (frame_copy, frame_guard, output_info.clone()))
+        self.capture_frame_copies_per_output(output_capture_regions, cursor_overlay)?
+            .into_iter()
+            .map(|(output_info, result)| match result {
+                Ok((frame_copy, frame_guard)) => Ok((frame_copy, frame_guard, output_info)),
+                Err(source) => Err(Error::OutputCaptureFailed {
+                    output: output_info.name,
+                    source: Box::new(source),
+                }),
             })
             .collect()
     }
 
+    /// Like [`WayshotConnection::capture_frame_copies`], but reports the outcome of every
+    /// output separately instead of failing as a whole when one output could not be captured.
+    ///
+    /// All frames are requested before any event is dispatched and are then driven on a single
+    /// event queue, so the outputs are captured at (nearly) the same moment. The outer error is
+    /// only returned when the Wayland connection itself fails.
+    pub fn capture_frame_copies_per_output(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+    ) -> Result<Vec<OutputCapture>> {
+        let mut state = MultiCaptureState {
+            frames: output_capture_regions
+                .iter()
+                .map(|_| CaptureFrameState::default())
+                .collect(),
+        };
+        let mut event_queue = self.conn.new_event_queue::<MultiCaptureState>();
+        let qh = event_queue.handle();
+
+        let frames: Vec<Result<WayshotFrame>> = output_capture_regions
+            .iter()
+            .enumerate()
+            .map(|(index, (output_info, capture_region))| {
+                self.request_output_frame(
+                    &qh,
+                    index,
+                    cursor_overlay as i32,
+                    &output_info.wl_output,
+                    *capture_region,
+                )
+            })
+            .collect();
+
+        // Wait until the compositor told us the buffer constraints of every frame.
+        while frames.iter().enumerate().any(
+            |(index, frame)| matches!(frame, Ok(frame) if !frame.formats_received(&state.frames[index])),
+        ) {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        let captures: Vec<Result<(WayshotFrame, FrameFormat, File, FrameGuard)>> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let frame = frame?;
+                let frame_state = &state.frames[index];
+                if frame_state.session_stopped {
+                    return Err(Error::SessionStopped);
+                }
+                let frame_format = select_shm_format(&frame_state.formats)?;
+                tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
+
+                let mem_file = File::from(create_shm_fd()?);
+                mem_file.set_len(frame_format.byte_size())?;
+                let frame_guard = self.attach_shm_buffer(&qh, &frame, frame_format, &mem_file)?;
+                Ok((frame, frame_format, mem_file, frame_guard))
+            })
+            .collect();
+
+        // Wait until every copy finished, one way or another.
+        while captures.iter().enumerate().any(|(index, capture)| {
+            let frame_state = &state.frames[index];
+            capture.is_ok() && frame_state.state.is_none() && !frame_state.session_stopped
+        }) {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        Ok(output_capture_regions
+            .iter()
+            .zip(captures)
+            .enumerate()
+            .map(|(index, ((output_info, capture_region), capture))| {
+                let result = capture.and_then(|(frame, frame_format, mem_file, frame_guard)| {
+                    let frame_state = &state.frames[index];
+                    if let Some(result) = frame_state.frame_result() {
+                        result?;
+                    }
+                    let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+                    let frame_copy = FrameCopy::from_shm_mmap(
+                        frame_format,
+                        frame_mmap,
+                        output_info,
+                        *capture_region,
+                        frame_state.damage.as_deref(),
+                        frame.needs_crop(*capture_region),
+                    )?;
+                    tracing::debug!("Created frame copy: {:#?}", frame_copy);
+                    Ok((frame_copy, frame_guard))
+                });
+                if let Err(e) = &result {
+                    tracing::warn!("Failed to capture output {output_info}: {e}");
+                }
+                (output_info.clone(), result)
+            })
+            .collect())
+    }
+
     /// Capture DMA-BUF backed frames for each provided output/region pair.
+    ///
+    /// Regions need wlr-screencopy, see [`WayshotConnection::capture_output_frame_dmabuf`].
     pub fn capture_frame_copies_dmabuf(
         &self,
         output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
@@ -1192,6 +1298,23 @@ impl WayshotConnection {
             .collect()
     }
 
+    /// Start a [`CaptureSession`] for repeatedly capturing the same output.
+    ///
+    /// The session keeps the capture objects and the shm buffer alive between frames, which
+    /// makes it much cheaper than calling the one-shot capture functions in a loop.
+    /// # Parameters
+    /// - `output_info`: The output to capture.
+    /// - `cursor_overlay`: A boolean flag indicating whether the cursor should be included in the capture.
+    /// - `capture_region`: Optional region specifying a sub-area of the output to capture. If `None`, the entire output is captured.
+    pub fn capture_session(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<CaptureSession<'_>> {
+        CaptureSession::new(self, output_info, cursor_overlay, capture_region)
+    }
+
     /// Create a layer shell surface for each output,
     /// render the screen captures on them and use the callback to select a region from them
     fn overlay_frames_and_select_region<F>(
@@ -1200,7 +1323,7 @@ impl WayshotConnection {
         callback: F,
     ) -> Result<LogicalRegion>
     where
-        F: Fn(&WayshotConnection) -> Result<LogicalRegion, Error>,
+        F: Fn(&WayshotConnection, &[FrozenFrame<'_>]) -> Result<LogicalRegion, Error>,
     {
         let mut state = LayerShellState {
             configured_outputs: HashSet::new(),
@@ -1292,7 +1415,11 @@ impl WayshotConnection {
             })?;
         }
 
-        let callback_result = callback(self);
+        let frozen_frames: Vec<FrozenFrame> = frames
+            .iter()
+            .map(|(frame, _, output)| FrozenFrame { frame, output })
+            .collect();
+        let callback_result = callback(self, &frozen_frames);
 
         debug!("Unmapping and destroying layer shell surfaces.");
         for (surface, layer_shell_surface) in layer_shell_surfaces.iter() {
@@ -1306,45 +1433,19 @@ impl WayshotConnection {
     }
 
     /// Take a screenshot from the specified region.
-    #[tracing::instrument(skip_all, fields(max_scale = tracing::field::Empty))]
+    #[tracing::instrument(skip_all, fields(scale = tracing::field::Empty))]
     fn screenshot_region_capturer(
         &self,
         region_capturer: RegionCapturer,
         cursor_overlay: bool,
     ) -> Result<DynamicImage> {
-        let outputs_capture_regions: Vec<(OutputInfo, Option<EmbeddedRegion>)> =
+        let mut outputs_capture_regions: Vec<(OutputInfo, Option<EmbeddedRegion>)> =
             match region_capturer {
                 RegionCapturer::Outputs(ref outputs) => outputs
                     .iter()
                     .map(|output_info| (output_info.clone(), None))
                     .collect(),
-                RegionCapturer::Region(capture_region) => self
-                    .get_all_outputs()
-                    .iter()
-                    .filter_map(|output_info| {
-                        tracing::span!(
-                            tracing::Level::DEBUG,
-                            "filter_map",
-                            output = format!(
-                                "{output_info} at {region}",
-                                output_info = format!("{output_info}"),
-                                region = LogicalRegion::from(output_info),
-                            ),
-                            capture_region = format!("{}", capture_region),
-                        )
-                        .in_scope(|| {
-                            if let Some(relative_region) =
-                                EmbeddedRegion::new(capture_region, output_info.into())
-                            {
-                                tracing::debug!("Intersection found: {}", relative_region);
-                                Some((output_info.clone(), Some(relative_region)))
-                            } else {
-                                tracing::debug!("No intersection found");
-                                None
-                            }
-                        })
-                    })
-                    .collect(),
+                RegionCapturer::Region(capture_region) => self.intersecting_outputs(capture_region),
                 RegionCapturer::TopLevel(ref toplevel) => {
                     return self.capture_toplevel_using_ext_protocol(toplevel, cursor_overlay);
                 }
@@ -1355,93 +1456,86 @@ impl WayshotConnection {
                     .collect(),
             };
 
-        let frames = self.capture_frame_copies(&outputs_capture_regions, cursor_overlay)?;
+        let mut frames = self.capture_frame_copies(&outputs_capture_regions, cursor_overlay)?;
 
         let capture_region: LogicalRegion = match region_capturer {
             RegionCapturer::Outputs(outputs) => outputs.as_slice().try_into()?,
             RegionCapturer::Region(region) => region,
             RegionCapturer::Freeze(callback) => {
-                self.overlay_frames_and_select_region(&frames, callback)?
+                let capture_region = self.overlay_frames_and_select_region(&frames, callback)?;
+                // Only keep the part of the frozen outputs that was selected.
+                let intersecting = self.intersecting_outputs(capture_region);
+                frames = frames
+                    .into_iter()
+                    .filter_map(|(frame_copy, frame_guard, output_info)| {
+                        let (_, embedded_region) =
+                            intersecting.iter().find(|(intersecting, _)| {
+                                intersecting.wl_output == output_info.wl_output
+                            })?;
+                        Some(match embedded_region {
+                            Some(embedded_region) => frame_copy
+                                .crop(*embedded_region)
+                                .map(|frame_copy| (frame_copy, frame_guard, output_info)),
+                            None => Ok((frame_copy, frame_guard, output_info)),
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                outputs_capture_regions = intersecting;
+                capture_region
             }
             RegionCapturer::TopLevel(_) => unreachable!("TopLevel handled earlier"),
         };
 
-        // TODO When freeze was used, we can still further remove the outputs
-        // that don't intersect with the capture region.
-
-        thread::scope(|scope| {
-            let max_scale = outputs_capture_regions
+        let scale = self.scale_policy.canvas_scale(
+            outputs_capture_regions
                 .iter()
-                .map(|(output_info, _)| output_info.scale())
-                .fold(1.0, f64::max);
-
-            tracing::Span::current().record("max_scale", max_scale);
+                .map(|(output_info, _)| output_info.scale()),
+        );
+        tracing::Span::current().record("scale", scale);
 
-            let rotate_join_handles = frames
+        image_util::composite_frames(
+            frames
                 .into_iter()
-                .map(|(mut frame_copy, _, _)| {
-                    scope.spawn(move || {
-                        let image = frame_copy.get_image()?;
-                        Ok((
-                            image_util::rotate_image_buffer(
-                                image,
-                                frame_copy.transform,
-                                frame_copy.logical_region.inner.size,
-                                max_scale,
-                            ),
-                            frame_copy,
-                        ))
-                    })
-                })
-                .collect::<Vec<_>>();
+                .map(|(frame_copy, _, _)| frame_copy)
+                .collect(),
+            capture_region,
+            scale,
+            self.scale_policy,
+            self.resample_filter,
+        )
+    }
 
-            rotate_join_handles
-                .into_iter()
-                .flat_map(|join_handle| join_handle.join())
-                .fold(
-                    None,
-                    |composite_image: Option<Result<_>>, image: Result<_>| {
-                        // Default to a transparent image.
-                        let composite_image = composite_image.unwrap_or_else(|| {
-                            Ok(DynamicImage::new_rgba8(
-                                (capture_region.inner.size.width as f64 * max_scale) as u32,
-                                (capture_region.inner.size.height as f64 * max_scale) as u32,
-                            ))
-                        });
-
-                        Some(|| -> Result<_> {
-                            let mut composite_image = composite_image?;
-                            let (image, frame_copy) = image?;
-                            let (x, y) = (
-                                ((frame_copy.logical_region.inner.position.x as f64
-                                    - capture_region.inner.position.x as f64)
-                                    * max_scale) as i64,
-                                ((frame_copy.logical_region.inner.position.y as f64
-                                    - capture_region.inner.position.y as f64)
-                                    * max_scale) as i64,
-                            );
-                            tracing::span!(
-                                tracing::Level::DEBUG,
-                                "replace",
-                                frame_copy_region = format!("{}", frame_copy.logical_region),
-                                capture_region = format!("{}", capture_region),
-                                x = x,
-                                y = y,
-                            )
-                            .in_scope(|| {
-                                tracing::debug!("Replacing parts of the final image");
-                                replace(&mut composite_image, &image, x, y);
-                            });
-
-                            Ok(composite_image)
-                        }())
-                    },
+    /// Find the part of every output that intersects with `capture_region`.
+    pub(crate) fn intersecting_outputs(
+        &self,
+        capture_region: LogicalRegion,
+    ) -> Vec<(OutputInfo, Option<EmbeddedRegion>)> {
+        self.get_all_outputs()
+            .iter()
+            .filter_map(|output_info| {
+                tracing::span!(
+                    tracing::Level::DEBUG,
+                    "filter_map",
+                    output = format!(
+                        "{output_info} at {region}",
+                        output_info = format!("{output_info}"),
+                        region = LogicalRegion::from(output_info),
+                    ),
+                    capture_region = format!("{}", capture_region),
                 )
-                .ok_or_else(|| {
-                    tracing::error!("Provided capture region doesn't intersect with any outputs!");
-                    Error::NoOutputs
-                })?
-        })
+                .in_scope(|| {
+                    if let Some(relative_region) =
+                        EmbeddedRegion::new(capture_region, output_info.into())
+                    {
+                        tracing::debug!("Intersection found: {}", relative_region);
+                        Some((output_info.clone(), Some(relative_region)))
+                    } else {
+                        tracing::debug!("No intersection found");
+                        None
+                    }
+                })
+            })
+            .collect()
     }
 
     /// Take a screenshot from the specified region.
@@ -1455,13 +1549,46 @@ impl WayshotConnection {
 
     /// Take a screenshot, overlay the screenshot, run the callback, and then
     /// unfreeze the screenshot and return the selected region.
+    ///
+    /// The callback gets read-only access to the frozen frames and the outputs they show.
     pub fn screenshot_freeze<F>(&self, callback: F, cursor_overlay: bool) -> Result<DynamicImage>
     where
-        F: Fn(&WayshotConnection) -> Result<LogicalRegion> + 'static,
+        F: Fn(&WayshotConnection, &[FrozenFrame<'_>]) -> Result<LogicalRegion> + 'static,
     {
         self.screenshot_region_capturer(RegionCapturer::Freeze(Box::new(callback)), cursor_overlay)
     }
 
+    /// Pick the colour of the logical pixel at `position`.
+    ///
+    /// Only that pixel is captured, not the whole output.
+    pub fn pick_color(&self, position: Position) -> Result<PickedColor> {
+        self.pick_color_averaged(position, 1)
+    }
+
+    /// Average the colour of the `size`x`size` logical pixels centred on `position`.
+    pub fn pick_color_averaged(&self, position: Position, size: u32) -> Result<PickedColor> {
+        let size = size.max(1);
+        let half = (size / 2) as i32;
+        let region = LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: position.x - half,
+                    y: position.y - half,
+                },
+                size: Size {
+                    width: size,
+                    height: size,
+                },
+            },
+        };
+        let frames = self.capture_frame_copies(&self.intersecting_outputs(region), false)?;
+        let frozen_frames: Vec<FrozenFrame> = frames
+            .iter()
+            .map(|(frame, _, output)| FrozenFrame { frame, output })
+            .collect();
+        PickedColor::from_frames(&frozen_frames, region)
+    }
+
     /// Take a screenshot from one output
     pub fn screenshot_single_output(
         &self,
@@ -1472,6 +1599,28 @@ impl WayshotConnection {
         frame_copy.get_image()
     }
 
+    /// Take a screenshot from one output through a DMA-BUF backed buffer.
+    ///
+    /// The buffer is read back on the CPU once the capture finished. 10-bit formats are kept as
+    /// 16-bit images, so this is the way to get HDR screenshots out of compositors that only
+    /// offer deep formats for DMA-BUF.
+    /// Requires a connection created with [`WayshotConnection::from_connection_with_dmabuf`].
+    pub fn screenshot_single_output_dmabuf(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let (frame_copy, _frame_guard) =
+            self.capture_frame_copy_dmabuf(cursor_overlay, output_info, None)?;
+        let image = DynamicImage::try_from(&frame_copy)?;
+        Ok(image_util::rotate_image_buffer(
+            image,
+            frame_copy.transform,
+            frame_copy.logical_region.inner.size,
+            1.0,
+        ))
+    }
+
     /// Take a screenshot from all of the specified outputs.
     pub fn screenshot_outputs(
         &self,
@@ -1500,6 +1649,9 @@ impl WayshotConnection {
     }
 
     /// Take a screenshot from a specific toplevel (window).
+    ///
+    /// The buffer transform of the window is applied, the image is sized according to
+    /// [`WayshotConnection::set_toplevel_size`].
     pub fn screenshot_toplevel(
         &mut self,
         toplevel: TopLevel,
@@ -1514,37 +1666,13 @@ impl WayshotConnection {
         cursor_overlay: bool,
     ) -> Result<DynamicImage> {
         // Create state and event queue similar to other ext-image flows
-        let state = CaptureFrameState {
-            formats: Vec::new(),
-            dmabuf_formats: Vec::new(),
-            state: None,
-            buffer_done: AtomicBool::new(false),
-            toplevels: Vec::new(),
-        };
+        let state = CaptureFrameState::default();
         let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
         let qh = event_queue.handle();
-
-        // Bind managers
-        let manager = self
-            .globals
-            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())?;
-        let toplevel_source_manager = self
-            .globals
-            .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())?;
-
-        let source = toplevel_source_manager.create_source(&toplevel.handle, &qh, ());
-
-        // Options follow the same pattern as output capture
-        let options = if cursor_overlay {
-            Options::PaintCursors
-        } else {
-            Options::empty()
-        };
-        let session = manager.create_session(&source, options, &qh, ());
-        let frame = session.create_frame(&qh, ());
+        let frame = self.request_toplevel_frame(&qh, toplevel, cursor_overlay)?;
 
         // Determine a suitable shm FrameFormat for this frame
-        let (state, event_queue, frame, frame_format) =
+        let (mut state, mut event_queue, frame, frame_format) =
             self.capture_output_frame_get_state_shm_for_toplevel(state, event_queue, frame)?;
 
         // Back the buffer with a shm file of the required size
@@ -1552,27 +1680,127 @@ impl WayshotConnection {
         let memfile = File::from(fd);
         memfile.set_len(frame_format.byte_size())?;
 
-        // Perform the copy using the existing ext-image path
-        let _guard =
-            self.capture_output_frame_inner_ext(state, event_queue, frame, frame_format, &memfile)?;
+        // Perform the copy, keeping the state around for the transform of the frame
+        let _guard = self.attach_shm_buffer(
+            &qh,
+            &WayshotFrame::ExtImageCopy(frame),
+            frame_format,
+            &memfile,
+        )?;
+        loop {
+            if let Some(result) = state.frame_result() {
+                result?;
+                break;
+            }
+            event_queue.blocking_dispatch(&mut state)?;
+        }
 
         // Map and convert to image
         let frame_mmap = unsafe { MmapMut::map_mut(&memfile)? };
+        let transform = state.transform.unwrap_or(Transform::Normal);
+        let (transformed_size, logical_region) =
+            self.toplevel_geometry(frame_format.size, transform)?;
+        let frame_color_type = convert::create_converter(frame_format.format)
+            .ok_or(Error::NoSupportedBufferFormat)?
+            .color_type();
         let mut frame_copy = FrameCopy {
             frame_format,
-            frame_color_type: image::ColorType::Rgb8, // will be updated by get_image
+            frame_color_type,
             frame_data: FrameData::Mmap(frame_mmap),
-            transform: Transform::Normal,
-            logical_region: LogicalRegion {
-                inner: crate::region::Region {
-                    position: crate::region::Position { x: 0, y: 0 },
-                    size: frame_format.size,
+            transform,
+            logical_region,
+            physical_size: transformed_size,
+            damage: damage_regions(
+                state.damage.as_deref(),
+                &frame_format,
+                transform,
+                logical_region,
+            ),
+        };
+
+        // The buffer size is passed in its own orientation, so only the rotation is applied.
+        let image = image_util::rotate_image_buffer(
+            frame_copy.get_image()?,
+            transform,
+            frame_format.size,
+            1.0,
+        );
+        let Size { width, height } = logical_region.inner.size;
+        if image.width() == width && image.height() == height {
+            return Ok(image);
+        }
+        Ok(image.resize_exact(width, height, self.resample_filter))
+    }
+
+    /// The size of a toplevel buffer once `transform` is applied, and the logical region it
+    /// covers according to the [`ToplevelSize`] of the connection.
+    fn toplevel_geometry(
+        &self,
+        buffer_size: Size,
+        transform: Transform,
+    ) -> Result<(Size, LogicalRegion)> {
+        let transformed_size = image_util::transform_region(
+            Region {
+                position: Position { x: 0, y: 0 },
+                size: buffer_size,
+            },
+            buffer_size,
+            transform,
+        )
+        .size;
+        let scale = match self.toplevel_size {
+            ToplevelSize::Native => 1.0,
+            ToplevelSize::Logical => {
+                // Without the output of the toplevel its scale is only known when all outputs agree.
+                let outputs = self.get_all_outputs();
+                let mut scales = outputs.iter().map(|output_info| output_info.scale());
+                let scale = scales.next().ok_or(Error::NoOutputs)?;
+                if scales.any(|other| other != scale) {
+                    return Err(Error::ToplevelScaleUnknown);
+                }
+                scale
+            }
+        };
+        tracing::debug!("Toplevel buffer has transform {transform:?}, using scale {scale}");
+        let to_logical = |pixels: u32| ((pixels as f64 / scale).round() as u32).max(1);
+        let logical_region = LogicalRegion {
+            inner: Region {
+                position: Position { x: 0, y: 0 },
+                size: Size {
+                    width: to_logical(transformed_size.width),
+                    height: to_logical(transformed_size.height),
                 },
             },
-            physical_size: frame_format.size,
         };
+        Ok((transformed_size, logical_region))
+    }
 
-        frame_copy.get_image()
+    /// Ask the compositor for a frame of `toplevel` through ext-image-copy-capture, without
+    /// waiting for any events.
+    fn request_toplevel_frame(
+        &self,
+        qh: &QueueHandle<CaptureFrameState>,
+        toplevel: &TopLevel,
+        cursor_overlay: bool,
+    ) -> Result<ExtImageCopyCaptureFrameV1> {
+        // Bind managers
+        let manager = self
+            .globals
+            .bind::<ExtImageCopyCaptureManagerV1, _, _>(qh, 1..=1, ())?;
+        let toplevel_source_manager = self
+            .globals
+            .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(qh, 1..=1, ())?;
+
+        let source = toplevel_source_manager.create_source(&toplevel.handle, qh, ());
+
+        // Options follow the same pattern as output capture
+        let options = if cursor_overlay {
+            Options::PaintCursors
+        } else {
+            Options::empty()
+        };
+        let session = manager.create_session(&source, options, qh, ());
+        Ok(session.create_frame(qh, ()))
     }
 
     // Helper method to get frame format for toplevel using ext-image session events
@@ -1587,28 +1815,15 @@ impl WayshotConnection {
         ExtImageCopyCaptureFrameV1,
         FrameFormat,
     )> {
-        // Drain events at least once to populate formats from the session
-        event_queue.blocking_dispatch(&mut state)?;
+        // Wait for the complete set of buffer constraints of the session.
+        while !(state.session_done || state.session_stopped) {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+        if state.session_stopped {
+            return Err(Error::SessionStopped);
+        }
 
-        let frame_format = state
-            .formats
-            .iter()
-            .find(|frame| {
-                matches!(
-                    frame.format,
-                    wl_shm::Format::Xbgr2101010
-                        | wl_shm::Format::Abgr2101010
-                        | wl_shm::Format::Argb8888
-                        | wl_shm::Format::Xrgb8888
-                        | wl_shm::Format::Xbgr8888
-                        | wl_shm::Format::Bgr888
-                )
-            })
-            .copied()
-            .ok_or_else(|| {
-                tracing::error!("No suitable frame format found");
-                Error::NoSupportedBufferFormat
-            })?;
+        let frame_format = select_shm_format(&state.formats)?;
 
         Ok((state, event_queue, frame, frame_format))
     }
@@ -1,4 +1,7 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
 
 use wayland_client::protocol::{wl_output, wl_output::WlOutput};
 
@@ -7,14 +10,40 @@ use crate::region::{LogicalRegion, Size};
 /// Represents an accessible wayland output.
 ///
 /// Do not instantiate, instead use [`crate::WayshotConnection::get_all_outputs`].
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OutputInfo {
     pub wl_output: WlOutput,
     pub name: String,
     pub description: String,
     pub transform: wl_output::Transform,
+    /// Size of the current mode in physical pixels, before `transform` is applied.
     pub physical_size: Size,
+    /// Logical region with the transform and scale already applied.
     pub logical_region: LogicalRegion,
+    /// Integer buffer scale advertised through `wl_output.scale`, 1 if the compositor did not
+    /// send one.
+    pub integer_scale: i32,
+    /// Fractional scale the compositor prefers for surfaces on this output, as sent through
+    /// `wp_fractional_scale_v1.preferred_scale`. `None` until
+    /// [`crate::WayshotConnection::refresh_preferred_scales`] looked it up. Informational only,
+    /// captures use [`OutputInfo::scale`].
+    pub preferred_scale: Option<f64>,
+}
+
+// The preferred scale is a multiple of 1/120, never NaN.
+impl Eq for OutputInfo {}
+
+impl Hash for OutputInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.wl_output.hash(state);
+        self.name.hash(state);
+        self.description.hash(state);
+        self.transform.hash(state);
+        self.physical_size.hash(state);
+        self.logical_region.hash(state);
+        self.integer_scale.hash(state);
+        self.preferred_scale.map(f64::to_bits).hash(state);
+    }
 }
 
 impl Display for OutputInfo {
@@ -29,7 +58,42 @@ impl Display for OutputInfo {
 }
 
 impl OutputInfo {
-    pub(crate) fn scale(&self) -> f64 {
-        self.physical_size.height as f64 / self.logical_region.inner.size.height as f64
+    /// Size of the output in physical pixels with `transform` applied, so it has the same
+    /// orientation as `logical_region`.
+    pub fn transformed_physical_size(&self) -> Size {
+        match self.transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => Size {
+                width: self.physical_size.height,
+                height: self.physical_size.width,
+            },
+            _ => self.physical_size,
+        }
     }
+
+    /// Number of physical pixels per logical pixel.
+    ///
+    /// This is fractional on outputs the compositor scales by a fractional factor, as it is
+    /// derived from the physical and logical size. Without a logical size, e.g. when xdg-output is
+    /// missing, [`OutputInfo::integer_scale`] is used.
+    pub fn scale(&self) -> f64 {
+        let logical_size = self.logical_region.inner.size;
+        if logical_size.width == 0 {
+            return self.integer_scale.max(1) as f64;
+        }
+        self.transformed_physical_size().width as f64 / logical_size.width as f64
+    }
+}
+
+/// A change of the outputs reported by [`crate::WayshotConnection::dispatch_output_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    /// An output was plugged in.
+    Added(OutputInfo),
+    /// An output was removed. Frames can no longer be captured from it.
+    Removed(OutputInfo),
+    /// The mode, transform, scale, position or description of an output changed.
+    Changed(OutputInfo),
 }
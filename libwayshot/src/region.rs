@@ -1,12 +1,14 @@
 use crate::{
     WayshotConnection,
-    error::{Error, Result},
+    error::{Error, GeometryError, Result},
+    image_util::{invert_transform, transform_region},
     output::OutputInfo,
+    screencopy::FrozenFrame,
 };
-use std::cmp;
 use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
 
-pub type FreezeCallback = Box<dyn Fn(&WayshotConnection) -> Result<LogicalRegion>>;
+pub type FreezeCallback =
+    Box<dyn Fn(&WayshotConnection, &[FrozenFrame<'_>]) -> Result<LogicalRegion>>;
 
 /// Ways to say how a region for a screenshot should be captured.
 pub enum RegionCapturer {
@@ -17,8 +19,8 @@ pub enum RegionCapturer {
     /// Capture a specific toplevel window.
     TopLevel(TopLevel),
     /// The outputs will be "frozen" to the user at which point the given
-    /// callback is called to get the region to capture. This callback is often
-    /// a user interaction to let the user select a region.
+    /// callback is called with the frozen frames to get the region to capture.
+    /// This callback is often a user interaction to let the user select a region.
     Freeze(FreezeCallback),
 }
 
@@ -27,10 +29,24 @@ pub struct TopLevel {
     pub handle: ExtForeignToplevelHandleV1,
     pub title: String,
     pub app_id: String,
+    /// Identifier that stays the same for the lifetime of the window, see
+    /// [`crate::WayshotConnection::find_toplevel`].
     pub identifier: String,
+    /// `false` once the compositor closed the window.
     pub active: bool,
 }
 
+/// A change of the toplevels reported by [`crate::WayshotConnection::dispatch_toplevel_events`].
+#[derive(Debug, Clone)]
+pub enum TopLevelEvent {
+    /// A window was opened.
+    Added(TopLevel),
+    /// The title or app id of a window changed.
+    Changed(TopLevel),
+    /// A window was closed and can no longer be captured.
+    Removed(TopLevel),
+}
+
 impl TopLevel {
     pub fn new(handle: ExtForeignToplevelHandleV1) -> Self {
         Self {
@@ -56,6 +72,17 @@ pub struct LogicalRegion {
     pub inner: Region,
 }
 
+/// `Region` in the physical pixels of an output's buffer, as the compositor hands them out: top
+/// left of the buffer is (0, 0) and neither the output's transform nor its scale have been
+/// applied.
+///
+/// Convert from and to a `LogicalRegion` with [`PhysicalRegion::from_logical`] and
+/// [`PhysicalRegion::to_logical`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PhysicalRegion {
+    pub inner: Region,
+}
+
 /// An embedded region is a region entirely inside of another (often an output).
 ///
 /// It can only be contained inside of another and cannot exceed its bounds.
@@ -122,44 +149,12 @@ impl EmbeddedRegion {
     /// See `EmbeddedRegion` for an example ASCII visualisation.
     #[tracing::instrument(ret, level = "debug")]
     pub fn new(viewport: LogicalRegion, relative_to: LogicalRegion) -> Option<Self> {
-        let x_relative: i32 = viewport.inner.position.x - relative_to.inner.position.x;
-        let y_relative = viewport.inner.position.y - relative_to.inner.position.y;
-
-        let x1 = cmp::max(x_relative, 0);
-        let x2 = cmp::min(
-            x_relative + viewport.inner.size.width as i32,
-            relative_to.inner.size.width as i32,
-        );
-        let width = if let Ok(width) = (x2 - x1).try_into() {
-            if width < 1 {
-                return None;
-            };
-            width
-        } else {
-            return None;
-        };
-
-        let y1 = cmp::max(y_relative, 0);
-        let y2 = cmp::min(
-            y_relative + viewport.inner.size.height as i32,
-            relative_to.inner.size.height as i32,
-        );
-        let height = if let Ok(height) = (y2 - y1).try_into() {
-            if height < 1 {
-                return None;
-            };
-            height
-        } else {
-            return None;
-        };
-
-        Some(Self {
-            relative_to,
-            inner: Region {
-                position: Position { x: x1, y: y1 },
-                size: Size { width, height },
-            },
-        })
+        let origin = relative_to.inner.position;
+        let inner = viewport
+            .inner
+            .intersection(&relative_to.inner)?
+            .translate(-origin.x, -origin.y);
+        Some(Self { relative_to, inner })
     }
 
     /// Return the `LogicalRegion` of the embedded region.
@@ -180,6 +175,205 @@ impl EmbeddedRegion {
     }
 }
 
+impl Region {
+    /// Whether the region covers no pixels at all.
+    pub fn is_empty(&self) -> bool {
+        self.size.width == 0 || self.size.height == 0
+    }
+
+    /// The area covered by both regions, `None` if they don't overlap.
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        let (x1, y1, x2, y2) = self.edges();
+        let (other_x1, other_y1, other_x2, other_y2) = other.edges();
+        let (x1, y1) = (x1.max(other_x1), y1.max(other_y1));
+        let (x2, y2) = (x2.min(other_x2), y2.min(other_y2));
+        if x2 <= x1 || y2 <= y1 {
+            return None;
+        }
+        Some(Region::from_edges(x1, y1, x2, y2))
+    }
+
+    /// The smallest region containing both regions.
+    pub fn union(&self, other: &Region) -> Region {
+        let (x1, y1, x2, y2) = self.edges();
+        let (other_x1, other_y1, other_x2, other_y2) = other.edges();
+        Region::from_edges(
+            x1.min(other_x1),
+            y1.min(other_y1),
+            x2.max(other_x2),
+            y2.max(other_y2),
+        )
+    }
+
+    /// Whether `other` lies entirely inside of this region.
+    pub fn contains(&self, other: &Region) -> bool {
+        let (x1, y1, x2, y2) = self.edges();
+        let (other_x1, other_y1, other_x2, other_y2) = other.edges();
+        x1 <= other_x1 && y1 <= other_y1 && other_x2 <= x2 && other_y2 <= y2
+    }
+
+    /// Move the region by `x` and `y`, keeping its size.
+    pub fn translate(&self, x: i32, y: i32) -> Region {
+        let (x1, y1, x2, y2) = self.edges();
+        Region::from_edges(x1 + x as i64, y1 + y as i64, x2 + x as i64, y2 + y as i64)
+    }
+
+    /// Multiply the position and size of the region by `factor`.
+    ///
+    /// Fractional results are rounded outwards, so the result covers every pixel the exact
+    /// scaled region touches.
+    pub fn scale(&self, factor: f64) -> Region {
+        self.map_outwards(|edge| edge * factor)
+    }
+
+    /// Shrink the region so it lies inside of `bounds`.
+    ///
+    /// Unlike [`Region::intersection`] this always returns a region: one that doesn't overlap
+    /// `bounds` is collapsed to an empty region on the closest edge of `bounds`.
+    pub fn clamp_to(&self, bounds: &Region) -> Region {
+        let (x1, y1, x2, y2) = self.edges();
+        let (bounds_x1, bounds_y1, bounds_x2, bounds_y2) = bounds.edges();
+        Region::from_edges(
+            x1.clamp(bounds_x1, bounds_x2),
+            y1.clamp(bounds_y1, bounds_y2),
+            x2.clamp(bounds_x1, bounds_x2),
+            y2.clamp(bounds_y1, bounds_y2),
+        )
+    }
+
+    /// Left, top, right and bottom edge, wide enough that they cannot overflow.
+    fn edges(&self) -> (i64, i64, i64, i64) {
+        let (x, y) = (self.position.x as i64, self.position.y as i64);
+        (
+            x,
+            y,
+            x + self.size.width as i64,
+            y + self.size.height as i64,
+        )
+    }
+
+    /// Region between the given edges, saturating at the limits of `Position` and `Size`.
+    fn from_edges(x1: i64, y1: i64, x2: i64, y2: i64) -> Region {
+        let x = x1.clamp(i32::MIN as i64, i32::MAX as i64);
+        let y = y1.clamp(i32::MIN as i64, i32::MAX as i64);
+        Region {
+            position: Position {
+                x: x as i32,
+                y: y as i32,
+            },
+            size: Size {
+                width: (x2 - x).clamp(0, u32::MAX as i64) as u32,
+                height: (y2 - y).clamp(0, u32::MAX as i64) as u32,
+            },
+        }
+    }
+
+    /// Map every edge through `map`, rounding the left and top edge down and the right and
+    /// bottom edge up.
+    fn map_outwards(&self, map: impl Fn(f64) -> f64) -> Region {
+        let (x1, y1, x2, y2) = self.edges();
+        Region::from_edges(
+            map(x1 as f64).floor() as i64,
+            map(y1 as f64).floor() as i64,
+            map(x2 as f64).ceil() as i64,
+            map(y2 as f64).ceil() as i64,
+        )
+    }
+}
+
+/// Forward the operations of [`Region`] to a wrapper, keeping its coordinate system.
+macro_rules! region_operations {
+    ($region:ident) => {
+        impl $region {
+            /// See [`Region::is_empty`].
+            pub fn is_empty(&self) -> bool {
+                self.inner.is_empty()
+            }
+
+            /// See [`Region::intersection`].
+            pub fn intersection(&self, other: &Self) -> Option<Self> {
+                self.inner
+                    .intersection(&other.inner)
+                    .map(|inner| Self { inner })
+            }
+
+            /// See [`Region::union`].
+            pub fn union(&self, other: &Self) -> Self {
+                Self {
+                    inner: self.inner.union(&other.inner),
+                }
+            }
+
+            /// See [`Region::contains`].
+            pub fn contains(&self, other: &Self) -> bool {
+                self.inner.contains(&other.inner)
+            }
+
+            /// See [`Region::translate`].
+            pub fn translate(&self, x: i32, y: i32) -> Self {
+                Self {
+                    inner: self.inner.translate(x, y),
+                }
+            }
+
+            /// See [`Region::scale`].
+            pub fn scale(&self, factor: f64) -> Self {
+                Self {
+                    inner: self.inner.scale(factor),
+                }
+            }
+
+            /// See [`Region::clamp_to`].
+            pub fn clamp_to(&self, bounds: &Self) -> Self {
+                Self {
+                    inner: self.inner.clamp_to(&bounds.inner),
+                }
+            }
+        }
+    };
+}
+
+region_operations!(LogicalRegion);
+region_operations!(PhysicalRegion);
+
+impl PhysicalRegion {
+    /// Map `region` into the buffer of `output`, undoing the output's position, scale and
+    /// transform.
+    ///
+    /// Partially covered pixels are included. Parts of `region` outside of the output are kept,
+    /// clamp the result to `PhysicalRegion::from(output)` to drop them.
+    pub fn from_logical(region: LogicalRegion, output: &OutputInfo) -> Self {
+        let origin = output.logical_region.inner.position;
+        let scaled = region
+            .inner
+            .translate(-origin.x, -origin.y)
+            .scale(output.scale());
+        Self {
+            inner: transform_region(
+                scaled,
+                output.transformed_physical_size(),
+                invert_transform(output.transform),
+            ),
+        }
+    }
+
+    /// Map the region back into the logical coordinate system, given the `output` whose buffer
+    /// it is in.
+    ///
+    /// This is the inverse of [`PhysicalRegion::from_logical`] on outputs with an integer scale.
+    /// With fractional scales, partially covered logical pixels are included.
+    pub fn to_logical(&self, output: &OutputInfo) -> LogicalRegion {
+        let origin = output.logical_region.inner.position;
+        let scale = output.scale();
+        let transformed = transform_region(self.inner, output.physical_size, output.transform);
+        LogicalRegion {
+            inner: transformed
+                .map_outwards(|edge| edge / scale)
+                .translate(origin.x, origin.y),
+        }
+    }
+}
+
 impl std::fmt::Display for EmbeddedRegion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -225,6 +419,12 @@ impl std::fmt::Display for LogicalRegion {
     }
 }
 
+impl std::fmt::Display for PhysicalRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{inner}", inner = self.inner)
+    }
+}
+
 impl From<&OutputInfo> for LogicalRegion {
     fn from(output_info: &OutputInfo) -> Self {
         LogicalRegion {
@@ -233,44 +433,310 @@ impl From<&OutputInfo> for LogicalRegion {
     }
 }
 
+/// The whole buffer of the output.
+impl From<&OutputInfo> for PhysicalRegion {
+    fn from(output_info: &OutputInfo) -> Self {
+        PhysicalRegion {
+            inner: Region {
+                position: Position::default(),
+                size: output_info.physical_size,
+            },
+        }
+    }
+}
+
 impl TryFrom<&[OutputInfo]> for LogicalRegion {
     type Error = Error;
 
     fn try_from(output_info: &[OutputInfo]) -> std::result::Result<Self, Self::Error> {
-        let x1 = output_info
+        output_info
             .iter()
-            .map(|output| output.logical_region.inner.position.x)
-            .min()
-            .ok_or(Error::NoOutputs)?;
-        let y1 = output_info
-            .iter()
-            .map(|output| output.logical_region.inner.position.y)
-            .min()
-            .ok_or(Error::NoOutputs)?;
-        let x2 = output_info
-            .iter()
-            .map(|output| {
-                output.logical_region.inner.position.x
-                    + output.logical_region.inner.size.width as i32
-            })
-            .max()
-            .ok_or(Error::NoOutputs)?;
-        let y2 = output_info
-            .iter()
-            .map(|output| {
-                output.logical_region.inner.position.y
-                    + output.logical_region.inner.size.height as i32
-            })
-            .max()
-            .ok_or(Error::NoOutputs)?;
-        Ok(LogicalRegion {
-            inner: Region {
-                position: Position { x: x1, y: y1 },
-                size: Size {
-                    width: (x2 - x1) as u32,
-                    height: (y2 - y1) as u32,
-                },
-            },
+            .map(LogicalRegion::from)
+            .reduce(|bounds, region| bounds.union(&region))
+            .ok_or(Error::NoOutputs)
+    }
+}
+
+/// A region written as text, e.g. on the command line, before it is placed on the outputs with
+/// [`Geometry::resolve`].
+///
+/// Parse it with [`str::parse`]. The accepted formats are:
+///
+/// - `X,Y WxH` as printed by slurp, e.g. `10,20 800x600`.
+/// - X11 geometries `WxH+X+Y`, e.g. `800x600+10+20`. `-X` and `-Y` measure from the right and
+///   bottom edge instead, so `800x600-0-0` is the bottom right corner.
+/// - An anchor followed by a size, e.g. `center 800x600` or `bottom-right 400x300`.
+/// - Any of the above prefixed with an output name, e.g. `DP-1:10,20 800x600`, to place the region
+///   relative to that output instead of the global coordinate system.
+/// - `OUTPUT X Y W H`, as printed by `slurp -f '%o %x %y %w %h'`, which is a region in global
+///   coordinates cropped to the output it was selected on.
+///
+/// Every position and size may also be a percentage of the output, or of the bounding box of all
+/// outputs without one, e.g. `25%,25% 50%x50%`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    /// What the position and percentages refer to.
+    pub origin: GeometryOrigin,
+    /// Horizontal position.
+    pub x: GeometryOffset,
+    /// Vertical position.
+    pub y: GeometryOffset,
+    /// Width, never negative.
+    pub width: GeometryLength,
+    /// Height, never negative.
+    pub height: GeometryLength,
+}
+
+/// What the position and percentages of a [`Geometry`] refer to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeometryOrigin {
+    /// The global logical coordinate system. Percentages, anchors and offsets from the right or
+    /// bottom refer to the bounding box of all outputs.
+    Global,
+    /// The output with the given name, positions are relative to its top left corner.
+    Output(String),
+    /// The global logical coordinate system, with the region cropped to the output with the given
+    /// name.
+    CroppedToOutput(String),
+}
+
+/// Position of a [`Geometry`] along one axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GeometryOffset {
+    /// Distance of the left or top edge from the origin.
+    Start(GeometryLength),
+    /// Distance of the right or bottom edge from the right or bottom edge of the reference area.
+    End(GeometryLength),
+    /// Centred on the reference area.
+    Center,
+}
+
+/// A distance along one axis of a [`Geometry`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GeometryLength {
+    /// Logical pixels.
+    Pixels(i32),
+    /// Percent of the width or height of the reference area.
+    Percent(f64),
+}
+
+impl Geometry {
+    /// Place the geometry on `outputs`, resolving output names, percentages and anchors.
+    pub fn resolve(&self, outputs: &[OutputInfo]) -> Result<LogicalRegion> {
+        let find_output = |name: &String| {
+            outputs
+                .iter()
+                .find(|output| &output.name == name)
+                .map(LogicalRegion::from)
+                .ok_or_else(|| GeometryError::UnknownOutput(name.clone()))
+        };
+        let (origin, area) = match &self.origin {
+            GeometryOrigin::Global => (Position::default(), LogicalRegion::try_from(outputs)?),
+            GeometryOrigin::Output(name) => {
+                let area = find_output(name)?;
+                (area.inner.position, area)
+            }
+            GeometryOrigin::CroppedToOutput(name) => (Position::default(), find_output(name)?),
+        };
+        let Region {
+            position: area_position,
+            size: area_size,
+        } = area.inner;
+
+        let width = self.width.resolve(area_size.width).max(0);
+        let height = self.height.resolve(area_size.height).max(0);
+        let x = self
+            .x
+            .resolve(origin.x, area_position.x, area_size.width, width);
+        let y = self
+            .y
+            .resolve(origin.y, area_position.y, area_size.height, height);
+        let region = LogicalRegion {
+            inner: Region::from_edges(x, y, x + width, y + height),
+        };
+
+        match &self.origin {
+            GeometryOrigin::CroppedToOutput(name) => Ok(region
+                .intersection(&area)
+                .ok_or_else(|| GeometryError::OutsideOutput(name.clone()))?),
+            _ => Ok(region),
+        }
+    }
+}
+
+impl GeometryOffset {
+    /// Left or top edge of a region `size` long, given where the origin and the reference area
+    /// start on this axis and how long the area is.
+    fn resolve(&self, origin: i32, start: i32, length: u32, size: i64) -> i64 {
+        let (start, end) = (start as i64, start as i64 + length as i64);
+        match self {
+            GeometryOffset::Start(GeometryLength::Pixels(pixels)) => origin as i64 + *pixels as i64,
+            GeometryOffset::Start(percent) => start + percent.resolve(length),
+            GeometryOffset::End(offset) => end - offset.resolve(length) - size,
+            GeometryOffset::Center => start + (length as i64 - size) / 2,
+        }
+    }
+}
+
+impl GeometryLength {
+    fn resolve(&self, length: u32) -> i64 {
+        match self {
+            GeometryLength::Pixels(pixels) => *pixels as i64,
+            GeometryLength::Percent(percent) => (length as f64 * percent / 100.0).round() as i64,
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        match text.strip_suffix('%') {
+            Some(percent) => percent
+                .parse()
+                .ok()
+                .filter(|percent: &f64| percent.is_finite())
+                .map(GeometryLength::Percent),
+            None => text.parse().ok().map(GeometryLength::Pixels),
+        }
+    }
+
+    fn is_negative(&self) -> bool {
+        match self {
+            GeometryLength::Pixels(pixels) => *pixels < 0,
+            GeometryLength::Percent(percent) => *percent < 0.0,
+        }
+    }
+}
+
+impl std::str::FromStr for Geometry {
+    type Err = GeometryError;
+
+    fn from_str(geometry: &str) -> std::result::Result<Self, Self::Err> {
+        let geometry = geometry.trim();
+        if geometry.is_empty() {
+            return Err(GeometryError::Empty);
+        }
+
+        let words: Vec<&str> = geometry.split_whitespace().collect();
+        if let [output, x, y, width, height] = words[..] {
+            let (x, y) = (parse_pixels(x)?, parse_pixels(y)?);
+            let (width, height) = (parse_pixels(width)?, parse_pixels(height)?);
+            if width < 0 || height < 0 {
+                return Err(GeometryError::InvalidSize(format!("{width}x{height}")));
+            }
+            return Ok(Geometry {
+                origin: GeometryOrigin::CroppedToOutput(output.to_string()),
+                x: GeometryOffset::Start(GeometryLength::Pixels(x)),
+                y: GeometryOffset::Start(GeometryLength::Pixels(y)),
+                width: GeometryLength::Pixels(width),
+                height: GeometryLength::Pixels(height),
+            });
+        }
+
+        let (origin, body) = match geometry.split_once(':') {
+            Some((output, _)) if output.trim().is_empty() => {
+                return Err(GeometryError::MissingOutputName);
+            }
+            Some((output, body)) => (GeometryOrigin::Output(output.trim().to_string()), body),
+            None => (GeometryOrigin::Global, geometry),
+        };
+        let words: Vec<&str> = body.split_whitespace().collect();
+        let ((x, y), (width, height)) = match words[..] {
+            [position, size] if position.contains(',') => {
+                (parse_position(position)?, parse_size(size)?)
+            }
+            [anchor, size] if anchor.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                (parse_anchor(anchor)?, parse_size(size)?)
+            }
+            [x11] if x11.contains(['+', '-']) => parse_x11(x11)?,
+            _ => return Err(GeometryError::UnknownFormat(geometry.to_string())),
+        };
+        Ok(Geometry {
+            origin,
+            x,
+            y,
+            width,
+            height,
         })
     }
 }
+
+fn parse_pixels(text: &str) -> std::result::Result<i32, GeometryError> {
+    text.parse()
+        .map_err(|_| GeometryError::InvalidLength(text.to_string()))
+}
+
+/// Parse `X,Y`.
+fn parse_position(
+    position: &str,
+) -> std::result::Result<(GeometryOffset, GeometryOffset), GeometryError> {
+    let invalid = || GeometryError::InvalidPosition(position.to_string());
+    let (x, y) = position.split_once(',').ok_or_else(invalid)?;
+    let x = GeometryLength::parse(x).ok_or_else(invalid)?;
+    let y = GeometryLength::parse(y).ok_or_else(invalid)?;
+    Ok((GeometryOffset::Start(x), GeometryOffset::Start(y)))
+}
+
+/// Parse `WxH`.
+fn parse_size(size: &str) -> std::result::Result<(GeometryLength, GeometryLength), GeometryError> {
+    let invalid = || GeometryError::InvalidSize(size.to_string());
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let parse = |length| {
+        GeometryLength::parse(length)
+            .filter(|length| !length.is_negative())
+            .ok_or_else(invalid)
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_anchor(
+    anchor: &str,
+) -> std::result::Result<(GeometryOffset, GeometryOffset), GeometryError> {
+    // 0% rather than 0 pixels, so anchors refer to the bounding box of the outputs instead of the
+    // global origin.
+    let start = GeometryOffset::Start(GeometryLength::Percent(0.0));
+    let end = GeometryOffset::End(GeometryLength::Pixels(0));
+    let center = GeometryOffset::Center;
+    Ok(match anchor {
+        "center" => (center, center),
+        "top-left" => (start, start),
+        "top" => (center, start),
+        "top-right" => (end, start),
+        "left" => (start, center),
+        "right" => (end, center),
+        "bottom-left" => (start, end),
+        "bottom" => (center, end),
+        "bottom-right" => (end, end),
+        _ => return Err(GeometryError::UnknownAnchor(anchor.to_string())),
+    })
+}
+
+type ParsedGeometry = (
+    (GeometryOffset, GeometryOffset),
+    (GeometryLength, GeometryLength),
+);
+
+/// Parse the X11 `WxH+X+Y` form.
+fn parse_x11(geometry: &str) -> std::result::Result<ParsedGeometry, GeometryError> {
+    let unknown = || GeometryError::UnknownFormat(geometry.to_string());
+    let offsets_start = geometry.find(['+', '-']).ok_or_else(unknown)?;
+    let (size, offsets) = geometry.split_at(offsets_start);
+    let size = parse_size(size)?;
+
+    // The sign in front of an offset says which edge it is measured from, a second `-` right
+    // after it makes the offset itself negative, as in `+-10`.
+    let y_start = offsets[1..]
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '+' || c == '-')
+        .map(|(index, _)| index + 1)
+        .ok_or_else(unknown)?;
+    let (x, y) = offsets.split_at(y_start);
+    let parse_offset = |offset: &str| {
+        let length = GeometryLength::parse(&offset[1..])
+            .ok_or_else(|| GeometryError::InvalidLength(offset[1..].to_string()))?;
+        Ok(if offset.starts_with('+') {
+            GeometryOffset::Start(length)
+        } else {
+            GeometryOffset::End(length)
+        })
+    };
+    Ok(((parse_offset(x)?, parse_offset(y)?), size))
+}
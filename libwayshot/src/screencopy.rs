@@ -1,11 +1,13 @@
 use std::{
+    borrow::Cow,
     ffi::CString,
     os::fd::OwnedFd,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use drm::buffer::DrmFourcc;
 use gbm::BufferObject;
-use image::{ColorType, DynamicImage, ImageBuffer, Pixel, Rgb, Rgba};
+use image::{ColorType, DynamicImage, ImageBuffer, Pixel};
 use memmap2::MmapMut;
 use rustix::{
     fs::{self, SealFlags},
@@ -18,7 +20,13 @@ use wayland_client::protocol::{
     wl_shm_pool::WlShmPool,
 };
 
-use crate::{Error, Result, region::{LogicalRegion, Size}};
+use crate::{
+    Error, Result,
+    convert::{Convert, create_converter},
+    image_util::{invert_transform, transform_region},
+    output::OutputInfo,
+    region::{EmbeddedRegion, LogicalRegion, Position, Region, Size},
+};
 
 pub struct FrameGuard {
     pub buffer: WlBuffer,
@@ -57,8 +65,8 @@ impl<T: khronos_egl::api::EGL1_5> Drop for EGLImageGuard<'_, T> {
     }
 }
 
-/// Type of frame supported by the compositor. For now we only support Argb8888, Xrgb8888, and
-/// Xbgr8888.
+/// Type of frame supported by the compositor. Only formats a [`crate::convert::Convert`]
+/// implementation exists for are used for captures.
 ///
 /// See `zwlr_screencopy_frame_v1::Event::Buffer` as it's retrieved from there.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -87,26 +95,206 @@ impl FrameFormat {
     pub fn byte_size(&self) -> u64 {
         self.stride as u64 * self.size.height as u64
     }
+
+    /// Number of bytes a single pixel takes up in the buffer.
+    pub(crate) fn bytes_per_pixel(&self) -> u32 {
+        shm_bytes_per_pixel(self.format).unwrap_or(self.stride / self.size.width.max(1))
+    }
+}
+
+/// Number of bytes per pixel of the single plane wl_shm formats, `None` for formats we do not
+/// know the layout of.
+pub(crate) fn shm_bytes_per_pixel(format: wl_shm::Format) -> Option<u32> {
+    use wl_shm::Format::*;
+    Some(match format {
+        C8 | R8 | Rgb332 | Bgr233 => 1,
+        Xrgb4444 | Xbgr4444 | Rgbx4444 | Bgrx4444 | Argb4444 | Abgr4444 | Rgba4444 | Bgra4444
+        | Xrgb1555 | Xbgr1555 | Rgbx5551 | Bgrx5551 | Argb1555 | Abgr1555 | Rgba5551 | Bgra5551
+        | Rgb565 | Bgr565 | R16 | Rg88 | Gr88 => 2,
+        Rgb888 | Bgr888 => 3,
+        Xrgb8888 | Xbgr8888 | Rgbx8888 | Bgrx8888 | Argb8888 | Abgr8888 | Rgba8888 | Bgra8888
+        | Xrgb2101010 | Xbgr2101010 | Rgbx1010102 | Bgrx1010102 | Argb2101010 | Abgr2101010
+        | Rgba1010102 | Bgra1010102 | Rg1616 | Gr1616 => 4,
+        Xrgb16161616f | Xbgr16161616f | Argb16161616f | Abgr16161616f | Xrgb16161616
+        | Xbgr16161616 | Argb16161616 | Abgr16161616 => 8,
+        _ => return None,
+    })
+}
+
+/// A DRM format together with the modifiers a buffer of that format may use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmabufFormatModifiers {
+    /// DRM fourcc code.
+    pub format: u32,
+    pub modifiers: Vec<u64>,
+}
+
+/// Buffer constraints advertised by an ext-image-copy-capture session.
+///
+/// The compositor sends a complete new set whenever they change, buffers attached to frames
+/// of the session have to satisfy the latest one.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BufferConstraints {
+    /// Size every buffer must have, in buffer pixels.
+    pub size: Size,
+    pub shm_formats: Vec<wl_shm::Format>,
+    /// `dev_t` of the device DMA-BUF buffers have to be allocated on.
+    pub dmabuf_device: Option<u64>,
+    pub dmabuf_formats: Vec<DmabufFormatModifiers>,
+}
+
+impl BufferConstraints {
+    /// Every advertised shm format we know the pixel size of, in the order the compositor sent
+    /// them.
+    pub fn shm_frame_formats(&self) -> Vec<FrameFormat> {
+        self.shm_formats
+            .iter()
+            .filter_map(|&format| {
+                let Some(bytes_per_pixel) = shm_bytes_per_pixel(format) else {
+                    tracing::debug!("Skipping shm format {format:?} with unknown pixel size");
+                    return None;
+                };
+                Some(FrameFormat {
+                    format,
+                    size: self.size,
+                    stride: self.size.width * bytes_per_pixel,
+                })
+            })
+            .collect()
+    }
+
+    /// Every advertised DMA-BUF format at the advertised buffer size.
+    pub fn dmabuf_frame_formats(&self) -> Vec<DMAFrameFormat> {
+        self.dmabuf_formats
+            .iter()
+            .map(|dmabuf_format| DMAFrameFormat {
+                format: dmabuf_format.format,
+                size: self.size,
+            })
+            .collect()
+    }
 }
 
-#[tracing::instrument(skip(frame_data))]
-fn create_image_buffer<P>(
+/// How much an shm format is preferred, lower is better: formats with more than 8 bits per
+/// channel, then 32-bit, 24-bit and 16-bit ones, then formats only a registered converter knows.
+fn shm_format_rank(format: Format) -> u8 {
+    use Format::*;
+    match format {
+        Xrgb2101010 | Argb2101010 | Xbgr2101010 | Abgr2101010 | Rgbx1010102 | Rgba1010102
+        | Xrgb16161616 | Argb16161616 | Xbgr16161616 | Abgr16161616 | Xbgr16161616f
+        | Abgr16161616f => 0,
+        Xrgb8888 | Argb8888 | Xbgr8888 | Abgr8888 => 1,
+        Rgb888 | Bgr888 => 2,
+        Rgb565 | Bgr565 => 3,
+        _ => 4,
+    }
+}
+
+/// Select the best ranked advertised wl_shm format a converter exists for, keeping the order of
+/// the compositor between formats of the same rank.
+pub(crate) fn select_shm_format(formats: &[FrameFormat]) -> Result<FrameFormat> {
+    let frame_format = formats
+        .iter()
+        .filter(|frame| create_converter(frame.format).is_some())
+        .min_by_key(|frame| shm_format_rank(frame.format))
+        .copied()
+        // Check if frame format exists.
+        .ok_or_else(|| {
+            tracing::error!("No suitable frame format found");
+            Error::NoSupportedBufferFormat
+        })?;
+    tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
+    Ok(frame_format)
+}
+
+/// Convert `height` rows of raw frame contents, `stride` bytes apart, into an image with the
+/// color type of `converter`.
+#[tracing::instrument(skip(converter, data))]
+fn convert_frame(
+    converter: &dyn Convert,
     frame_format: &FrameFormat,
-    frame_data: &FrameData,
-) -> Result<ImageBuffer<P, Vec<P::Subpixel>>>
-where
-    P: Pixel<Subpixel = u8>,
-{
+    data: &[u8],
+    stride: usize,
+) -> Result<DynamicImage> {
     tracing::debug!("Creating image buffer");
-    match frame_data {
-        FrameData::Mmap(frame_mmap) => ImageBuffer::from_vec(
-            frame_format.size.width,
-            frame_format.size.height,
-            frame_mmap.to_vec(),
-        )
-        .ok_or(Error::BufferTooSmall),
-        FrameData::GBMBo(_) => todo!(),
+    let Size { width, height } = frame_format.size;
+    let src_row_len = (width * frame_format.bytes_per_pixel()) as usize;
+    let color_type = converter.color_type();
+    let dst_row_len = width as usize * color_type.bytes_per_pixel() as usize;
+    let mut converted = vec![0; dst_row_len * height as usize];
+    if dst_row_len > 0 {
+        for (y, dst) in converted.chunks_exact_mut(dst_row_len).enumerate() {
+            let src = data
+                .get(y * stride..y * stride + src_row_len)
+                .ok_or(Error::BufferTooSmall)?;
+            converter.convert_row(src, dst);
+        }
+    }
+    image_from_bytes(color_type, width, height, converted)
+}
+
+/// Wrap converted pixel data into a [`DynamicImage`]. Channels wider than a byte are stored in
+/// native endianness.
+fn image_from_bytes(
+    color_type: ColorType,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+) -> Result<DynamicImage> {
+    fn buffer<P: Pixel>(
+        width: u32,
+        height: u32,
+        data: Vec<P::Subpixel>,
+    ) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+        ImageBuffer::from_vec(width, height, data).ok_or(Error::BufferTooSmall)
     }
+    let u16s = || -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+            .collect()
+    };
+    let f32s = || -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    };
+    Ok(match color_type {
+        ColorType::L8 => DynamicImage::ImageLuma8(buffer(width, height, data)?),
+        ColorType::La8 => DynamicImage::ImageLumaA8(buffer(width, height, data)?),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(buffer(width, height, data)?),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(buffer(width, height, data)?),
+        ColorType::L16 => DynamicImage::ImageLuma16(buffer(width, height, u16s())?),
+        ColorType::La16 => DynamicImage::ImageLumaA16(buffer(width, height, u16s())?),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(buffer(width, height, u16s())?),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(buffer(width, height, u16s())?),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(buffer(width, height, f32s())?),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(buffer(width, height, f32s())?),
+        _ => return Err(Error::InvalidColor),
+    })
+}
+
+/// Map a linear GBM buffer object and copy its rows into a tightly packed vector, dropping the
+/// padding at the end of every row.
+pub(crate) fn read_gbm_bo(
+    bo: &BufferObject<()>,
+    size: Size,
+    bytes_per_pixel: u32,
+) -> Result<Vec<u8>> {
+    let row_len = (size.width * bytes_per_pixel) as usize;
+    bo.map(0, 0, size.width, size.height, |mapped| {
+        let stride = mapped.stride() as usize;
+        if stride < row_len {
+            return Err(Error::BufferTooSmall);
+        }
+        let mut data = Vec::with_capacity(row_len * size.height as usize);
+        for row in mapped.buffer().chunks(stride).take(size.height as usize) {
+            data.extend_from_slice(row.get(..row_len).ok_or(Error::BufferTooSmall)?);
+        }
+        if data.len() != row_len * size.height as usize {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(data)
+    })?
 }
 
 #[derive(Debug)]
@@ -114,24 +302,423 @@ pub enum FrameData {
     Mmap(MmapMut),
     GBMBo(BufferObject<()>),
 }
-/// The copied frame comprising of the FrameFormat, ColorType (Rgba8), and a memory backed shm
-/// file that holds the image data in it.
+/// The copied frame comprising of the FrameFormat, the ColorType it converts to, and a memory
+/// backed shm file that holds the image data in it.
+///
+/// `frame_data` holds the pixels exactly as the compositor wrote them, in the wl_shm format of
+/// `frame_format`. They are only converted when the frame is turned into an image.
 #[derive(Debug)]
 pub struct FrameCopy {
     pub frame_format: FrameFormat,
+    /// Color type of the image after converting `frame_data`, not of `frame_data` itself.
     pub frame_color_type: ColorType,
+    /// Unconverted pixels in `frame_format.format`.
     pub frame_data: FrameData,
     pub transform: wl_output::Transform,
     /// Logical region with the transform already applied.
     pub logical_region: LogicalRegion,
     pub physical_size: Size,
+    /// Parts of the frame that changed since the previous frame, relative to `logical_region`.
+    ///
+    /// When the compositor does not report any damage the whole frame is considered damaged.
+    pub damage: Vec<EmbeddedRegion>,
+}
+
+/// Convert damage rectangles reported in buffer coordinates into regions relative to the
+/// `logical_region` of the frame. Without any reported damage the whole frame is damaged.
+pub(crate) fn damage_regions(
+    damage: Option<&[Region]>,
+    frame_format: &FrameFormat,
+    transform: wl_output::Transform,
+    logical_region: LogicalRegion,
+) -> Vec<EmbeddedRegion> {
+    let relative_to = LogicalRegion {
+        inner: Region {
+            position: Position::default(),
+            size: logical_region.inner.size,
+        },
+    };
+    let full_frame = || EmbeddedRegion {
+        relative_to: logical_region,
+        inner: relative_to.inner,
+    };
+    let Some(damage) = damage else {
+        return vec![full_frame()];
+    };
+
+    let physical = transform_region(
+        Region {
+            position: Position::default(),
+            size: frame_format.size,
+        },
+        frame_format.size,
+        transform,
+    )
+    .size;
+    if physical.width == 0 || physical.height == 0 {
+        return vec![full_frame()];
+    }
+    let scale_x = logical_region.inner.size.width as f64 / physical.width as f64;
+    let scale_y = logical_region.inner.size.height as f64 / physical.height as f64;
+
+    damage
+        .iter()
+        .filter_map(|region| {
+            let region = transform_region(*region, frame_format.size, transform);
+            let x1 = (region.position.x as f64 * scale_x).floor() as i32;
+            let y1 = (region.position.y as f64 * scale_y).floor() as i32;
+            let x2 = ((region.position.x as f64 + region.size.width as f64) * scale_x).ceil();
+            let y2 = ((region.position.y as f64 + region.size.height as f64) * scale_y).ceil();
+            let viewport = LogicalRegion {
+                inner: Region {
+                    position: Position { x: x1, y: y1 },
+                    size: Size {
+                        width: (x2 as i32 - x1).max(0) as u32,
+                        height: (y2 as i32 - y1).max(0) as u32,
+                    },
+                },
+            };
+            EmbeddedRegion::new(viewport, relative_to).map(|embedded| EmbeddedRegion {
+                relative_to: logical_region,
+                inner: embedded.inner,
+            })
+        })
+        .collect()
+}
+
+/// Map `embedded_region`, relative to the output the buffer shows, into buffer coordinates.
+///
+/// The result is rounded outwards to whole pixels and clamped to the buffer.
+pub(crate) fn buffer_region(
+    embedded_region: EmbeddedRegion,
+    frame_format: &FrameFormat,
+    transform: wl_output::Transform,
+) -> Region {
+    let full_buffer = Region {
+        position: Position::default(),
+        size: frame_format.size,
+    };
+    let physical = transform_region(full_buffer, frame_format.size, transform).size;
+    let logical = embedded_region.relative_to.inner.size;
+    if logical.width == 0 || logical.height == 0 {
+        return full_buffer;
+    }
+    let scale_x = physical.width as f64 / logical.width as f64;
+    let scale_y = physical.height as f64 / logical.height as f64;
+
+    let Region { position, size } = embedded_region.inner;
+    let x1 = ((position.x as f64 * scale_x).floor() as i32).clamp(0, physical.width as i32);
+    let y1 = ((position.y as f64 * scale_y).floor() as i32).clamp(0, physical.height as i32);
+    let x2 = (((position.x as f64 + size.width as f64) * scale_x).ceil() as i32)
+        .clamp(x1, physical.width as i32);
+    let y2 = (((position.y as f64 + size.height as f64) * scale_y).ceil() as i32)
+        .clamp(y1, physical.height as i32);
+    let physical_region = Region {
+        position: Position { x: x1, y: y1 },
+        size: Size {
+            width: (x2 - x1) as u32,
+            height: (y2 - y1) as u32,
+        },
+    };
+    transform_region(physical_region, physical, invert_transform(transform))
+}
+
+/// Copy `region`, given in buffer coordinates, out of a buffer laid out as `frame_format` into a
+/// new tightly packed one.
+///
+/// Returns the format of the new buffer and `damage` translated into it.
+fn crop_shm_buffer(
+    frame_format: FrameFormat,
+    frame_mmap: &[u8],
+    region: Region,
+    damage: Option<&[Region]>,
+) -> Result<(FrameFormat, MmapMut, Option<Vec<Region>>)> {
+    let bytes_per_pixel = frame_format.bytes_per_pixel() as usize;
+    let (x, y) = (region.position.x as usize, region.position.y as usize);
+    let row_len = region.size.width as usize * bytes_per_pixel;
+    let cropped_format = FrameFormat {
+        format: frame_format.format,
+        size: region.size,
+        stride: row_len as u32,
+    };
+
+    let mut cropped_mmap = MmapMut::map_anon((cropped_format.byte_size() as usize).max(1))?;
+    for (row, cropped_row) in cropped_mmap
+        .chunks_exact_mut(row_len.max(1))
+        .take(region.size.height as usize)
+        .enumerate()
+    {
+        let start = (y + row) * frame_format.stride as usize + x * bytes_per_pixel;
+        let source = frame_mmap
+            .get(start..start + row_len)
+            .ok_or(Error::BufferTooSmall)?;
+        cropped_row[..row_len].copy_from_slice(source);
+    }
+
+    let damage = damage.map(|damage| crop_damage(damage, region));
+    Ok((cropped_format, cropped_mmap, damage))
+}
+
+/// Clip `damage`, given in buffer coordinates, to `region` of the buffer and make it relative to
+/// the region. Damage outside of the region is dropped, possibly leaving none at all.
+pub(crate) fn crop_damage(damage: &[Region], region: Region) -> Vec<Region> {
+    let bounds = Region {
+        position: Position::default(),
+        size: region.size,
+    };
+    damage
+        .iter()
+        .filter_map(|damage| {
+            let translated = Region {
+                position: Position {
+                    x: damage.position.x - region.position.x,
+                    y: damage.position.y - region.position.y,
+                },
+                size: damage.size,
+            };
+            EmbeddedRegion::new(
+                LogicalRegion { inner: translated },
+                LogicalRegion { inner: bounds },
+            )
+            .map(|embedded| embedded.inner)
+        })
+        .collect()
 }
 
 impl FrameCopy {
+    /// Wrap the shm contents of a finished capture of `output_info` into a `FrameCopy`,
+    /// converting the pixel data in place where needed.
+    ///
+    /// With `crop` set the buffer holds the whole output even though a `capture_region` was
+    /// requested, as the ext-image-copy-capture protocol has no region captures. The region is
+    /// then cut out here, so the frame only contains the pixels of `capture_region`.
+    pub(crate) fn from_shm_mmap(
+        frame_format: FrameFormat,
+        mut frame_mmap: MmapMut,
+        output_info: &OutputInfo,
+        capture_region: Option<EmbeddedRegion>,
+        damage: Option<&[Region]>,
+        crop: bool,
+    ) -> Result<Self> {
+        let mut frame_format = frame_format;
+        let mut damage = damage.map(Cow::Borrowed);
+        if let (true, Some(embedded_region)) = (crop, capture_region) {
+            let region = buffer_region(embedded_region, &frame_format, output_info.transform);
+            tracing::debug!("Cropping {region} out of the full output buffer");
+            let (cropped_format, cropped_mmap, cropped_damage) =
+                crop_shm_buffer(frame_format, &frame_mmap, region, damage.as_deref())?;
+            frame_format = cropped_format;
+            frame_mmap = cropped_mmap;
+            damage = cropped_damage.map(Cow::Owned);
+        }
+
+        let frame_color_type = match create_converter(frame_format.format) {
+            Some(converter) => converter.color_type(),
+            None => {
+                tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
+                tracing::error!(
+                    "Register a converter for it with libwayshot::convert::register_converter."
+                );
+                return Err(Error::NoSupportedBufferFormat);
+            }
+        };
+        let rotated_physical_size = match output_info.transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => Size {
+                width: frame_format.size.height,
+                height: frame_format.size.width,
+            },
+            _ => frame_format.size,
+        };
+        let logical_region = capture_region
+            .map(|capture_region| capture_region.logical())
+            .unwrap_or(output_info.logical_region);
+        Ok(FrameCopy {
+            frame_format,
+            frame_color_type,
+            frame_data: FrameData::Mmap(frame_mmap),
+            transform: output_info.transform,
+            logical_region,
+            physical_size: rotated_physical_size,
+            damage: damage_regions(
+                damage.as_deref(),
+                &frame_format,
+                output_info.transform,
+                logical_region,
+            ),
+        })
+    }
+
+    /// Cut `embedded_region`, relative to the region this frame shows, out of the frame, leaving
+    /// the same frame as a capture of just that region.
+    ///
+    /// Frames backed by a GBM buffer object are read back, the cropped frame is always held in
+    /// memory.
+    pub(crate) fn crop(self, embedded_region: EmbeddedRegion) -> Result<Self> {
+        let region = buffer_region(embedded_region, &self.frame_format, self.transform);
+        tracing::debug!("Cropping {region} out of the frame");
+        let (frame_format, frame_mmap, _) = match &self.frame_data {
+            FrameData::Mmap(frame_mmap) => {
+                crop_shm_buffer(self.frame_format, frame_mmap, region, None)?
+            }
+            FrameData::GBMBo(bo) => {
+                let bytes_per_pixel = self.frame_format.bytes_per_pixel();
+                let data = read_gbm_bo(bo, self.frame_format.size, bytes_per_pixel)?;
+                let packed_format = FrameFormat {
+                    stride: self.frame_format.size.width * bytes_per_pixel,
+                    ..self.frame_format
+                };
+                crop_shm_buffer(packed_format, &data, region, None)?
+            }
+        };
+        let logical_region = embedded_region.logical();
+        let damage = self
+            .damage
+            .iter()
+            .filter_map(|damage| EmbeddedRegion::new(damage.logical(), logical_region))
+            .collect();
+        let physical_size = transform_region(
+            Region {
+                position: Position::default(),
+                size: frame_format.size,
+            },
+            frame_format.size,
+            self.transform,
+        )
+        .size;
+        Ok(FrameCopy {
+            frame_format,
+            frame_data: FrameData::Mmap(frame_mmap),
+            logical_region,
+            physical_size,
+            damage,
+            ..self
+        })
+    }
+
     pub(crate) fn get_image(&mut self) -> Result<DynamicImage, Error> {
         let image: DynamicImage = (self as &FrameCopy).try_into()?;
         Ok(image)
     }
+
+    /// Borrow the captured pixels exactly as the compositor wrote them, without copying or
+    /// converting them.
+    ///
+    /// Returns `None` for frames backed by a GBM buffer object, which have to be mapped first.
+    pub fn raw_frame(&self) -> Option<RawFrame<'_>> {
+        let FrameData::Mmap(mmap) = &self.frame_data else {
+            return None;
+        };
+        Some(RawFrame {
+            data: &mmap[..],
+            format: self.frame_format.format,
+            size: self.frame_format.size,
+            stride: self.frame_format.stride,
+            transform: self.transform,
+            logical_region: self.logical_region,
+            damage: &self.damage,
+        })
+    }
+}
+
+/// A frame shown on screen while the callback of [`crate::WayshotConnection::screenshot_freeze`]
+/// selects a region, together with the output it was captured from.
+///
+/// Use [`FrameCopy::raw_frame`] or convert the frame into a [`DynamicImage`] to look at its
+/// pixels, e.g. for magnifiers or colour pickers.
+#[derive(Debug, Copy, Clone)]
+pub struct FrozenFrame<'a> {
+    pub frame: &'a FrameCopy,
+    pub output: &'a OutputInfo,
+}
+
+/// Borrowed view of the raw contents of a [`FrameCopy`] or of the buffer of a
+/// [`crate::CaptureSession`].
+///
+/// Meant for consumers that handle wl_shm formats themselves, like video encoders or GPU
+/// uploads, so they do not have to go through a [`DynamicImage`].
+#[derive(Debug, Copy, Clone)]
+pub struct RawFrame<'a> {
+    /// The mapped shm buffer from the first pixel of the frame on, holding `size.height` rows
+    /// that start `stride` bytes apart. The last row may lack its padding.
+    pub data: &'a [u8],
+    pub format: Format,
+    /// Size of the buffer in pixels, before `transform` is applied.
+    pub size: Size,
+    /// Number of bytes between the start of a row and the start of the next row.
+    pub stride: u32,
+    /// Transform that has to be applied to the buffer to display it upright.
+    pub transform: wl_output::Transform,
+    /// Logical region with the transform already applied.
+    pub logical_region: LogicalRegion,
+    /// Parts of the frame that changed since the previous frame, relative to `logical_region`.
+    pub damage: &'a [EmbeddedRegion],
+}
+
+impl<'a> RawFrame<'a> {
+    /// Bytes of row `y`, without the padding at its end. `None` if `y` is out of bounds.
+    pub fn row(&self, y: u32) -> Option<&'a [u8]> {
+        if y >= self.size.height {
+            return None;
+        }
+        let bytes_per_pixel = FrameFormat {
+            format: self.format,
+            size: self.size,
+            stride: self.stride,
+        }
+        .bytes_per_pixel() as usize;
+        let start = y as usize * self.stride as usize;
+        self.data
+            .get(start..start + self.size.width as usize * bytes_per_pixel)
+    }
+
+    /// Copy the frame into an owned [`FrameCopy`] with tightly packed rows.
+    pub fn to_frame_copy(&self) -> Result<FrameCopy> {
+        let frame_color_type = create_converter(self.format)
+            .ok_or(Error::NoSupportedBufferFormat)?
+            .color_type();
+        let bytes_per_pixel = FrameFormat {
+            format: self.format,
+            size: self.size,
+            stride: self.stride,
+        }
+        .bytes_per_pixel();
+        let frame_format = FrameFormat {
+            format: self.format,
+            size: self.size,
+            stride: self.size.width * bytes_per_pixel,
+        };
+        let row_len = frame_format.stride as usize;
+        let mut frame_mmap = MmapMut::map_anon((frame_format.byte_size() as usize).max(1))?;
+        for (y, row) in frame_mmap
+            .chunks_exact_mut(row_len.max(1))
+            .take(self.size.height as usize)
+            .enumerate()
+        {
+            let source = self.row(y as u32).ok_or(Error::BufferTooSmall)?;
+            row[..row_len].copy_from_slice(source);
+        }
+        let physical_size = transform_region(
+            Region {
+                position: Position::default(),
+                size: self.size,
+            },
+            self.size,
+            self.transform,
+        )
+        .size;
+        Ok(FrameCopy {
+            frame_format,
+            frame_color_type,
+            frame_data: FrameData::Mmap(frame_mmap),
+            transform: self.transform,
+            logical_region: self.logical_region,
+            physical_size,
+            damage: self.damage.to_vec(),
+        })
+    }
 }
 
 /// Representation of a frame copied via DMA-BUF.
@@ -152,63 +739,91 @@ pub struct DMAFrameCopy {
 impl TryFrom<&FrameCopy> for DynamicImage {
     type Error = Error;
 
+    /// Convert the frame with the converter registered for its wl_shm format.
     fn try_from(value: &FrameCopy) -> Result<Self> {
-        Ok(match value.frame_color_type {
-            ColorType::Rgb8 => {
-                Self::ImageRgb8(create_image_buffer(&value.frame_format, &value.frame_data)?)
-            }
-            ColorType::Rgba8 => {
-                Self::ImageRgba8(create_image_buffer(&value.frame_format, &value.frame_data)?)
-            }
-            ColorType::Rgb16 => {
-                let (width, height) = (
-                    value.frame_format.size.width,
-                    value.frame_format.size.height,
-                );
-                let buffer = value.to_rgb16_vec()?;
-                let image = ImageBuffer::<Rgb<u16>, _>::from_vec(width, height, buffer)
-                    .ok_or(Error::BufferTooSmall)?;
-                Self::ImageRgb16(image)
-            }
-            ColorType::Rgba16 => {
-                let (width, height) = (
-                    value.frame_format.size.width,
-                    value.frame_format.size.height,
-                );
-                let buffer = value.to_rgba16_vec()?;
-                let image = ImageBuffer::<Rgba<u16>, _>::from_vec(width, height, buffer)
-                    .ok_or(Error::BufferTooSmall)?;
-                Self::ImageRgba16(image)
+        let frame_format = &value.frame_format;
+        let converter = create_converter(frame_format.format).ok_or_else(|| {
+            tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
+            Error::NoSupportedBufferFormat
+        })?;
+        match &value.frame_data {
+            FrameData::Mmap(mmap) => convert_frame(
+                &*converter,
+                frame_format,
+                mmap,
+                frame_format.stride as usize,
+            ),
+            FrameData::GBMBo(bo) => {
+                let bytes_per_pixel = frame_format.bytes_per_pixel();
+                let data = read_gbm_bo(bo, frame_format.size, bytes_per_pixel)?;
+                convert_frame(
+                    &*converter,
+                    frame_format,
+                    &data,
+                    (frame_format.size.width * bytes_per_pixel) as usize,
+                )
             }
-            _ => return Err(Error::InvalidColor),
-        })
+        }
     }
 }
 
-impl FrameCopy {
-    fn mmap_bytes(&self) -> Result<&[u8]> {
-        match &self.frame_data {
-            FrameData::Mmap(mmap) => Ok(&mmap[..]),
-            FrameData::GBMBo(_) => Err(Error::InvalidColor),
-        }
-    }
+impl TryFrom<&DMAFrameCopy> for DynamicImage {
+    type Error = Error;
 
-    fn to_rgb16_vec(&self) -> Result<Vec<u16>> {
-        let order = match self.frame_format.format {
-            wl_shm::Format::Xrgb2101010 | wl_shm::Format::Argb2101010 => ChannelOrder::Rgb,
-            wl_shm::Format::Xbgr2101010 | wl_shm::Format::Abgr2101010 => ChannelOrder::Bgr,
-            _ => return Err(Error::InvalidColor),
+    /// Read the DMA-BUF back on the CPU. 8-bit formats become `Rgba8` images, 10-bit formats are
+    /// expanded to 16-bit so no precision is lost.
+    fn try_from(value: &DMAFrameCopy) -> Result<Self> {
+        let size = value.frame_format.size;
+        let fourcc = DrmFourcc::try_from(value.frame_format.format)?;
+        let (order, has_alpha, deep) = match fourcc {
+            DrmFourcc::Xrgb8888 => (ChannelOrder::Bgr, false, false),
+            DrmFourcc::Argb8888 => (ChannelOrder::Bgr, true, false),
+            DrmFourcc::Xbgr8888 => (ChannelOrder::Rgb, false, false),
+            DrmFourcc::Abgr8888 => (ChannelOrder::Rgb, true, false),
+            DrmFourcc::Xrgb2101010 => (ChannelOrder::Rgb, false, true),
+            DrmFourcc::Argb2101010 => (ChannelOrder::Rgb, true, true),
+            DrmFourcc::Xbgr2101010 => (ChannelOrder::Bgr, false, true),
+            DrmFourcc::Abgr2101010 => (ChannelOrder::Bgr, true, true),
+            _ => {
+                tracing::error!("Unsupported DMA-BUF format: {fourcc}");
+                return Err(Error::NoSupportedBufferFormat);
+            }
         };
-        convert_10bit_to_u16(self.mmap_bytes()?, order, false)
-    }
+        let mut data = read_gbm_bo(&value.buffer_object, size, 4)?;
 
-    fn to_rgba16_vec(&self) -> Result<Vec<u16>> {
-        let order = match self.frame_format.format {
-            wl_shm::Format::Argb2101010 => ChannelOrder::Rgb,
-            wl_shm::Format::Abgr2101010 => ChannelOrder::Bgr,
-            _ => return Err(Error::InvalidColor),
-        };
-        convert_10bit_to_u16(self.mmap_bytes()?, order, true)
+        Ok(match (deep, has_alpha) {
+            (false, _) => {
+                for pixel in data.chunks_exact_mut(4) {
+                    if let ChannelOrder::Bgr = order {
+                        pixel.swap(0, 2);
+                    }
+                    // The padding byte of X formats is undefined.
+                    if !has_alpha {
+                        pixel[3] = u8::MAX;
+                    }
+                }
+                Self::ImageRgba8(
+                    ImageBuffer::from_vec(size.width, size.height, data)
+                        .ok_or(Error::BufferTooSmall)?,
+                )
+            }
+            (true, false) => Self::ImageRgb16(
+                ImageBuffer::from_vec(
+                    size.width,
+                    size.height,
+                    convert_10bit_to_u16(&data, order, false)?,
+                )
+                .ok_or(Error::BufferTooSmall)?,
+            ),
+            (true, true) => Self::ImageRgba16(
+                ImageBuffer::from_vec(
+                    size.width,
+                    size.height,
+                    convert_10bit_to_u16(&data, order, true)?,
+                )
+                .ok_or(Error::BufferTooSmall)?,
+            ),
+        })
     }
 }
 
@@ -219,7 +834,7 @@ enum ChannelOrder {
 }
 
 fn convert_10bit_to_u16(data: &[u8], order: ChannelOrder, include_alpha: bool) -> Result<Vec<u16>> {
-    if data.len() % 4 != 0 {
+    if !data.len().is_multiple_of(4) {
         return Err(Error::BufferTooSmall);
     }
     let mut out = Vec::with_capacity((data.len() / 4) * if include_alpha { 4 } else { 3 });
@@ -347,3 +962,53 @@ pub fn create_shm_fd() -> std::io::Result<OwnedFd> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_shm_format_ranks_formats() {
+        use Format::*;
+        let cases: &[(&str, &[Format], Option<Format>)] = &[
+            ("nothing advertised", &[], None),
+            ("no converter", &[Yuyv, Nv12], None),
+            (
+                "compositor order within a rank",
+                &[Xbgr8888, Argb8888],
+                Some(Xbgr8888),
+            ),
+            (
+                "10 bit over 8 bit",
+                &[Xrgb8888, Xrgb2101010],
+                Some(Xrgb2101010),
+            ),
+            (
+                "16 bit over 8 bit",
+                &[Argb8888, Abgr16161616f],
+                Some(Abgr16161616f),
+            ),
+            ("32 bit over 24 bit", &[Rgb888, Xrgb8888], Some(Xrgb8888)),
+            ("24 bit over 16 bit", &[Rgb565, Bgr888], Some(Bgr888)),
+            ("16 bit over nothing", &[Yuyv, Bgr565], Some(Bgr565)),
+        ];
+        let size = Size {
+            width: 4,
+            height: 2,
+        };
+        for (name, formats, expected) in cases {
+            let formats: Vec<FrameFormat> = formats
+                .iter()
+                .map(|&format| FrameFormat {
+                    format,
+                    size,
+                    stride: 16,
+                })
+                .collect();
+            let selected = select_shm_format(&formats)
+                .ok()
+                .map(|frame_format| frame_format.format);
+            assert_eq!(selected, *expected, "{name}");
+        }
+    }
+}
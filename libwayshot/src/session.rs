@@ -0,0 +1,425 @@
+//! Persistent capture sessions for grabbing the same output over and over.
+//!
+//! A one-shot capture through [`WayshotConnection`] creates a new event queue, binds every
+//! global it needs and allocates a fresh shm buffer for each frame. A [`CaptureSession`] does
+//! all of that once and then only issues the per-frame requests in
+//! [`CaptureSession::next_frame_raw`], which hands out the shared buffer without copying it.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    os::fd::AsFd,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use memmap2::MmapMut;
+use wayland_client::{EventQueue, protocol::wl_shm::WlShm};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_frame_v1::FailureReason,
+        ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
+        ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+    },
+};
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+
+use crate::{
+    Error, Result, WayshotConnection,
+    dispatch::CaptureFrameState,
+    output::OutputInfo,
+    region::EmbeddedRegion,
+    screencopy::{
+        BufferConstraints, FrameCopy, FrameFormat, FrameGuard, RawFrame, buffer_region,
+        create_shm_fd, crop_damage, damage_regions, select_shm_format,
+    },
+};
+
+/// The protocol object frames are requested from.
+enum SessionSource {
+    /// A long lived `ext_image_copy_capture_session_v1`.
+    Ext(ExtImageCopyCaptureSessionV1),
+    /// wlr-screencopy has no sessions, so a new frame is requested from the manager every time.
+    Wlr(ZwlrScreencopyManagerV1),
+}
+
+/// The shm buffer the compositor copies every frame into.
+struct SessionBuffer {
+    frame_format: FrameFormat,
+    mmap: MmapMut,
+    /// Whether the buffer has been handed to the compositor before. Fresh buffers have to be
+    /// damaged completely, afterwards only the compositor side damage needs to be copied.
+    attached: bool,
+    // Keeps the memfd alive for as long as the pool references it.
+    _mem_file: File,
+    guard: FrameGuard,
+}
+
+/// A capture of a single output that is kept alive between frames.
+///
+/// The negotiated [`FrameFormat`] and the shm buffer are reused for every frame, the buffer is
+/// only reallocated when the compositor changes the advertised format or size.
+///
+/// # Example usage
+///
+/// ```ignore
+/// let output = &wayshot_connection.get_all_outputs()[0];
+/// let mut session = wayshot_connection.capture_session(output, false, None)?;
+/// loop {
+///     let raw_frame = session.next_frame_raw()?;
+///     encoder.push(raw_frame.data)?;
+/// }
+/// ```
+pub struct CaptureSession<'a> {
+    connection: &'a WayshotConnection,
+    output_info: OutputInfo,
+    cursor_overlay: bool,
+    capture_region: Option<EmbeddedRegion>,
+    copy_with_damage: bool,
+    event_queue: EventQueue<CaptureFrameState>,
+    state: CaptureFrameState,
+    shm: WlShm,
+    source: SessionSource,
+    buffer: Option<SessionBuffer>,
+    /// Damage of the latest frame, borrowed by [`RawFrame`].
+    damage: Vec<EmbeddedRegion>,
+}
+
+impl<'a> CaptureSession<'a> {
+    pub(crate) fn new(
+        connection: &'a WayshotConnection,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<Self> {
+        let mut state = CaptureFrameState::default();
+        let mut event_queue = connection.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+
+        let shm = connection.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
+        let source = match connection
+            .globals
+            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
+        {
+            Ok(manager) => {
+                let output_management = connection
+                    .globals
+                    .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())?;
+                let source = output_management.create_source(&output_info.wl_output, &qh, ());
+                let options = if cursor_overlay {
+                    Options::PaintCursors
+                } else {
+                    Options::empty()
+                };
+                let session = manager.create_session(&source, options, &qh, ());
+                // The buffer constraints are sent right after the session is created.
+                while !(state.session_done || state.session_stopped) {
+                    event_queue.blocking_dispatch(&mut state)?;
+                }
+                if state.session_stopped {
+                    session.destroy();
+                    return Err(Error::SessionStopped);
+                }
+                tracing::trace!(
+                    "Received compositor frame buffer formats: {:#?}",
+                    state.formats
+                );
+                SessionSource::Ext(session)
+            }
+            Err(_) => {
+                let manager = match connection.globals.bind::<ZwlrScreencopyManagerV1, _, _>(
+                    &qh,
+                    3..=3,
+                    (),
+                ) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        tracing::error!(
+                            "Failed to create screencopy manager. Does your compositor implement ZwlrScreencopy?"
+                        );
+                        tracing::error!("err: {e}");
+                        return Err(Error::ProtocolNotFound(
+                            "ZwlrScreencopy Manager not found".to_string(),
+                        ));
+                    }
+                };
+                SessionSource::Wlr(manager)
+            }
+        };
+
+        let mut session = Self {
+            connection,
+            output_info: output_info.clone(),
+            cursor_overlay,
+            capture_region,
+            copy_with_damage: false,
+            event_queue,
+            state,
+            shm,
+            source,
+            buffer: None,
+            damage: Vec::new(),
+        };
+        if matches!(session.source, SessionSource::Ext(_)) {
+            session.apply_constraints()?;
+        }
+        Ok(session)
+    }
+
+    /// The output this session captures.
+    pub fn output_info(&self) -> &OutputInfo {
+        &self.output_info
+    }
+
+    /// The format of the currently allocated shm buffer, if a frame has been negotiated yet.
+    pub fn frame_format(&self) -> Option<FrameFormat> {
+        self.buffer.as_ref().map(|buffer| buffer.frame_format)
+    }
+
+    /// The latest buffer constraints of the ext-image-copy-capture session, `None` for sessions
+    /// on the wlr-screencopy path.
+    pub fn buffer_constraints(&self) -> Option<&BufferConstraints> {
+        match self.source {
+            SessionSource::Ext(_) => Some(&self.state.constraints),
+            SessionSource::Wlr(_) => None,
+        }
+    }
+
+    /// Use `copy_with_damage` instead of `copy` on the wlr-screencopy path.
+    ///
+    /// With this enabled [`CaptureSession::next_frame`] blocks until the output content changed
+    /// and the compositor reports the damaged regions in [`FrameCopy::damage`]. Sessions on
+    /// the ext-image-copy-capture path always receive damage and ignore this setting.
+    pub fn set_copy_with_damage(&mut self, copy_with_damage: bool) {
+        self.copy_with_damage = copy_with_damage;
+    }
+
+    /// Capture the next frame of the output and borrow it straight from the shared buffer.
+    ///
+    /// Nothing is allocated or copied. The frame points into the buffer the compositor writes
+    /// the next frame into, so it has to be consumed before capturing again. Regions of sessions
+    /// on the ext-image-copy-capture path, which always captures whole outputs, are views into
+    /// the full buffer with its stride.
+    pub fn next_frame_raw(&mut self) -> Result<RawFrame<'_>> {
+        match &self.source {
+            SessionSource::Ext(_) => self.capture_ext()?,
+            SessionSource::Wlr(_) => self.capture_wlr()?,
+        }
+
+        let buffer = self.buffer.as_ref().ok_or(Error::FramecopyFailed)?;
+        let mut frame_format = buffer.frame_format;
+        let mut damage = self.state.damage.as_deref().map(Cow::Borrowed);
+        let mut start = 0;
+        if let (SessionSource::Ext(_), Some(embedded_region)) = (&self.source, self.capture_region)
+        {
+            let region = buffer_region(embedded_region, &frame_format, self.output_info.transform);
+            start = region.position.y as usize * frame_format.stride as usize
+                + region.position.x as usize * frame_format.bytes_per_pixel() as usize;
+            frame_format.size = region.size;
+            damage = damage.map(|damage| Cow::Owned(crop_damage(&damage, region)));
+        }
+        let logical_region = self
+            .capture_region
+            .map(|capture_region| capture_region.logical())
+            .unwrap_or(self.output_info.logical_region);
+        self.damage = damage_regions(
+            damage.as_deref(),
+            &frame_format,
+            self.output_info.transform,
+            logical_region,
+        );
+
+        let end = start
+            + frame_format.stride as usize * frame_format.size.height.saturating_sub(1) as usize
+            + frame_format.size.width as usize * frame_format.bytes_per_pixel() as usize;
+        let data = buffer.mmap.get(start..end).ok_or(Error::BufferTooSmall)?;
+        Ok(RawFrame {
+            data,
+            format: frame_format.format,
+            size: frame_format.size,
+            stride: frame_format.stride,
+            transform: self.output_info.transform,
+            logical_region,
+            damage: &self.damage,
+        })
+    }
+
+    /// Capture the next frame of the output into an owned [`FrameCopy`].
+    ///
+    /// This copies the frame out of the shared buffer, so it stays valid while further frames
+    /// are captured. Use [`CaptureSession::next_frame_raw`] to avoid the copy.
+    pub fn next_frame(&mut self) -> Result<FrameCopy> {
+        self.next_frame_raw()?.to_frame_copy()
+    }
+
+    fn capture_ext(&mut self) -> Result<()> {
+        loop {
+            if self.state.session_stopped {
+                return Err(Error::SessionStopped);
+            }
+            if self.state.constraints_changed {
+                self.apply_constraints()?;
+            }
+
+            let SessionSource::Ext(session) = &self.source else {
+                unreachable!("capture_ext called on a wlr session");
+            };
+            let buffer = self.buffer.as_mut().ok_or(Error::NoSupportedBufferFormat)?;
+            let qh = self.event_queue.handle();
+
+            self.state.reset_frame();
+            let frame = session.create_frame(&qh, ());
+            frame.attach_buffer(&buffer.guard.buffer);
+            if !buffer.attached {
+                frame.damage_buffer(
+                    0,
+                    0,
+                    buffer.frame_format.size.width as i32,
+                    buffer.frame_format.size.height as i32,
+                );
+                buffer.attached = true;
+            }
+            frame.capture();
+
+            let result = self.wait_for_frame();
+            frame.destroy();
+            if result.is_ok() || self.state.failure_reason != Some(FailureReason::BufferConstraints)
+            {
+                return result;
+            }
+
+            // The buffer no longer matches the constraints. The compositor sends the new ones
+            // along with the failure, so pick them up and try again.
+            tracing::debug!("Buffer constraints changed, renegotiating");
+            self.event_queue.roundtrip(&mut self.state)?;
+            while self.state.pending_constraints.is_some() && !self.state.session_stopped {
+                self.event_queue.blocking_dispatch(&mut self.state)?;
+            }
+            if !self.state.constraints_changed && !self.state.session_stopped {
+                return result;
+            }
+        }
+    }
+
+    /// Select a format from the latest constraints of the ext session and reallocate the buffer
+    /// if it no longer satisfies them.
+    fn apply_constraints(&mut self) -> Result<()> {
+        self.state.constraints_changed = false;
+        let frame_format = select_shm_format(&self.state.formats)?;
+        self.ensure_buffer(frame_format)
+    }
+
+    fn capture_wlr(&mut self) -> Result<()> {
+        let SessionSource::Wlr(manager) = &self.source else {
+            unreachable!("capture_wlr called on an ext session");
+        };
+        let qh = self.event_queue.handle();
+
+        self.state.reset_frame();
+        self.state.formats.clear();
+        self.state.dmabuf_formats.clear();
+        self.state.buffer_done = AtomicBool::new(false);
+
+        let cursor_overlay = self.cursor_overlay as i32;
+        let frame = if let Some(embedded_region) = self.capture_region {
+            manager.capture_output_region(
+                cursor_overlay,
+                &self.output_info.wl_output,
+                embedded_region.inner.position.x,
+                embedded_region.inner.position.y,
+                embedded_region.inner.size.width as i32,
+                embedded_region.inner.size.height as i32,
+                &qh,
+                (),
+            )
+        } else {
+            manager.capture_output(cursor_overlay, &self.output_info.wl_output, &qh, ())
+        };
+
+        while !self.state.buffer_done.load(Ordering::SeqCst) {
+            self.event_queue.blocking_dispatch(&mut self.state)?;
+        }
+
+        let frame_format = select_shm_format(&self.state.formats)?;
+        self.ensure_buffer(frame_format)?;
+        let buffer = self.buffer.as_ref().ok_or(Error::NoSupportedBufferFormat)?;
+        if self.copy_with_damage {
+            frame.copy_with_damage(&buffer.guard.buffer);
+        } else {
+            frame.copy(&buffer.guard.buffer);
+        }
+
+        let result = self.wait_for_frame();
+        frame.destroy();
+        result
+    }
+
+    /// Dispatch until the compositor reports the outcome of the pending frame.
+    fn wait_for_frame(&mut self) -> Result<()> {
+        loop {
+            if let Some(result) = self.state.frame_result() {
+                return result;
+            }
+
+            self.event_queue.blocking_dispatch(&mut self.state)?;
+        }
+    }
+
+    /// (Re)allocate the shm buffer unless the current one already matches `frame_format`.
+    fn ensure_buffer(&mut self, frame_format: FrameFormat) -> Result<()> {
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.frame_format == frame_format)
+        {
+            return Ok(());
+        }
+        tracing::debug!("Allocating session buffer for {:#?}", frame_format);
+        // Drop the old buffer before creating the new one.
+        self.buffer = None;
+
+        let qh = self.event_queue.handle();
+        let mem_file = File::from(create_shm_fd()?);
+        mem_file.set_len(frame_format.byte_size())?;
+
+        let shm_pool = self.shm.create_pool(
+            mem_file.as_fd(),
+            frame_format
+                .byte_size()
+                .try_into()
+                .map_err(|_| Error::BufferTooSmall)?,
+            &qh,
+            (),
+        );
+        let buffer = shm_pool.create_buffer(
+            0,
+            frame_format.size.width as i32,
+            frame_format.size.height as i32,
+            frame_format.stride as i32,
+            frame_format.format,
+            &qh,
+            (),
+        );
+        let mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+
+        self.buffer = Some(SessionBuffer {
+            frame_format,
+            mmap,
+            attached: false,
+            _mem_file: mem_file,
+            guard: FrameGuard { buffer, shm_pool },
+        });
+        Ok(())
+    }
+}
+
+impl Drop for CaptureSession<'_> {
+    fn drop(&mut self) {
+        self.buffer = None;
+        match &self.source {
+            SessionSource::Ext(session) => session.destroy(),
+            SessionSource::Wlr(manager) => manager.destroy(),
+        }
+        let _ = self.connection.conn.flush();
+    }
+}
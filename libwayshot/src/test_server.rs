@@ -0,0 +1,1840 @@
+//! An in-process Wayland compositor for testing libwayshot and the programs built on it.
+//!
+//! [`MockCompositor`] describes the outputs and windows of a fake compositor together with the
+//! frames it hands out. [`MockCompositor::spawn`] runs it on its own thread, serving one client
+//! over a socket pair, so a [`crate::WayshotConnection`] can be created from
+//! [`MockServer::connection`] without a GPU or a real compositor:
+//!
+//! ```
+//! use libwayshot::{
+//!     WayshotConnection,
+//!     region::Size,
+//!     test_server::{MockCompositor, MockFrame, MockOutput},
+//! };
+//!
+//! let size = Size { width: 4, height: 2 };
+//! let server = MockCompositor::new()
+//!     .with_output(MockOutput::new("TEST-1", size).with_frame(MockFrame::solid(size, [255, 0, 0])))
+//!     .spawn()?;
+//! let connection = WayshotConnection::from_connection(server.connection())?;
+//! let image = connection.screenshot_all(false)?;
+//! assert_eq!(image.to_rgba8().get_pixel(0, 0).0, [255, 0, 0, 255]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! It implements `wl_output` v4, `wl_shm`, `zxdg_output_manager_v1`, `zwlr_screencopy_manager_v1`,
+//! `ext_image_copy_capture_manager_v1` with output and toplevel capture sources,
+//! `ext_foreign_toplevel_list_v1`, and enough of `wl_compositor`, `zwlr_layer_shell_v1` and
+//! `wp_fractional_scale_manager_v1` to map layer surfaces and report their preferred scale.
+//! A `wl_seat` has a pointer when a [`MockCursor`] is set, which ext-image-copy-capture cursor
+//! sessions capture. Nothing is rendered: captures copy the bytes of the scripted [`MockFrame`]s
+//! into the client's buffers as they are, and surfaces never show up in them.
+//!
+//! Each output plays a sequence of frames, one per capture, along with the damage of each frame.
+//! A frame with a new size or format renegotiates the buffer constraints of running sessions, and
+//! [`MockServer::stop_capture_sessions`] stops them.
+
+use std::{
+    fs::File,
+    io,
+    os::unix::net::UnixStream,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use memmap2::MmapMut;
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use wayland_client::{
+    Connection,
+    protocol::{wl_output::Transform, wl_shm::Format},
+};
+use wayland_protocols::{
+    ext::{
+        foreign_toplevel_list::v1::server::{
+            ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+            ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+        },
+        image_capture_source::v1::server::{
+            ext_foreign_toplevel_image_capture_source_manager_v1::{
+                self, ExtForeignToplevelImageCaptureSourceManagerV1,
+            },
+            ext_image_capture_source_v1::{self, ExtImageCaptureSourceV1},
+            ext_output_image_capture_source_manager_v1::{
+                self, ExtOutputImageCaptureSourceManagerV1,
+            },
+        },
+        image_copy_capture::v1::server::{
+            ext_image_copy_capture_cursor_session_v1::{self, ExtImageCopyCaptureCursorSessionV1},
+            ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1, FailureReason},
+            ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1},
+            ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+        },
+    },
+    wp::fractional_scale::v1::server::{
+        wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    xdg::xdg_output::zv1::server::{
+        zxdg_output_manager_v1::{self, ZxdgOutputManagerV1},
+        zxdg_output_v1::{self, ZxdgOutputV1},
+    },
+};
+use wayland_protocols_wlr::{
+    layer_shell::v1::server::{
+        zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
+        zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
+    },
+    screencopy::v1::server::{
+        zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+        zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+    },
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+    backend::{ClientData, GlobalId},
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_compositor::{self, WlCompositor},
+        wl_output::{self, WlOutput},
+        wl_pointer::{self, WlPointer},
+        wl_region::{self, WlRegion},
+        wl_seat::{self, WlSeat},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::{self, WlShmPool},
+        wl_surface::{self, WlSurface},
+    },
+};
+
+use crate::{
+    region::{EmbeddedRegion, LogicalRegion, Position, Region, Size},
+    screencopy::{FrameFormat, buffer_region},
+};
+
+/// Pixels the mock compositor copies into client buffers, in buffer coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockFrame {
+    /// Format, size and stride of `data`, advertised to clients as the only buffer format.
+    pub frame_format: FrameFormat,
+    pub data: Vec<u8>,
+    /// Damage sent with the frame, in buffer coordinates. `None` damages the whole frame.
+    pub damage: Option<Vec<Region>>,
+}
+
+impl MockFrame {
+    /// A frame holding `data`, which has to be `frame_format.stride * frame_format.size.height`
+    /// bytes long.
+    pub fn new(frame_format: FrameFormat, data: Vec<u8>) -> Self {
+        assert_eq!(
+            data.len() as u64,
+            frame_format.byte_size(),
+            "frame data doesn't match {frame_format:?}"
+        );
+        Self {
+            frame_format,
+            data,
+            damage: None,
+        }
+    }
+
+    /// Only report `damage`, in buffer coordinates, as changed when the frame is captured.
+    pub fn with_damage(mut self, damage: Vec<Region>) -> Self {
+        self.damage = Some(damage);
+        self
+    }
+
+    /// The damage sent with the frame, clipped to `region` of the buffer and relative to it.
+    fn damage_in(&self, region: Region) -> Vec<Region> {
+        match &self.damage {
+            Some(damage) => damage
+                .iter()
+                .filter_map(|damage| damage.intersection(&region))
+                .map(|damage| damage.translate(-region.position.x, -region.position.y))
+                .collect(),
+            None => vec![Region {
+                position: Position::default(),
+                size: region.size,
+            }],
+        }
+    }
+
+    /// An `Xrgb8888` frame filled with one `[r, g, b]` colour.
+    pub fn solid(size: Size, rgb: [u8; 3]) -> Self {
+        Self::from_fn(size, |_, _| rgb)
+    }
+
+    /// An `Xrgb8888` frame with the `[r, g, b]` colour `pixel(x, y)` returns for every pixel.
+    pub fn from_fn(size: Size, pixel: impl Fn(u32, u32) -> [u8; 3]) -> Self {
+        let mut data = Vec::with_capacity(size.width as usize * size.height as usize * 4);
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let [r, g, b] = pixel(x, y);
+                data.extend_from_slice(&[b, g, r, 0xff]);
+            }
+        }
+        Self::new(
+            FrameFormat {
+                format: Format::Xrgb8888,
+                size,
+                stride: size.width * 4,
+            },
+            data,
+        )
+    }
+}
+
+/// An output of the mock compositor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockOutput {
+    /// Name of the output, used to refer to it in [`MockServer`] calls.
+    pub name: String,
+    pub description: String,
+    /// Size of the current mode, before `transform` is applied.
+    pub mode: Size,
+    pub transform: Transform,
+    /// Sent through `wl_output.scale`.
+    pub integer_scale: i32,
+    /// Position in the global logical coordinate space, sent through xdg-output.
+    pub position: Position,
+    /// Sent through xdg-output. Defaults to the transformed mode divided by `integer_scale`.
+    pub logical_size: Option<Size>,
+    /// Sent through `wp_fractional_scale_v1` to surfaces mapped on the output. Defaults to the
+    /// transformed mode divided by the logical size.
+    pub preferred_scale: Option<f64>,
+    /// The contents of the output in buffer coordinates, black by default. Each capture shows
+    /// the next frame, the last one is repeated once all were shown. A frame with a different
+    /// format or size than the one before makes the compositor send new buffer constraints.
+    pub frames: Vec<MockFrame>,
+}
+
+impl MockOutput {
+    /// A black output at the origin with a `mode` sized frame, no transform and scale 1.
+    pub fn new(name: &str, mode: Size) -> Self {
+        Self {
+            name: name.to_string(),
+            description: format!("Mock output {name}"),
+            mode,
+            transform: Transform::Normal,
+            integer_scale: 1,
+            position: Position::default(),
+            logical_size: None,
+            preferred_scale: None,
+            frames: vec![MockFrame::solid(mode, [0, 0, 0])],
+        }
+    }
+
+    pub fn with_position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Set the logical size explicitly, e.g. to simulate fractional scaling.
+    pub fn with_logical_size(mut self, size: Size) -> Self {
+        self.logical_size = Some(size);
+        self
+    }
+
+    /// Set the preferred fractional scale explicitly. It is rounded to a multiple of 1/120 when
+    /// it is sent.
+    pub fn with_preferred_scale(mut self, preferred_scale: f64) -> Self {
+        self.preferred_scale = Some(preferred_scale);
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_integer_scale(mut self, integer_scale: i32) -> Self {
+        self.integer_scale = integer_scale;
+        self
+    }
+
+    /// Show `frame` in every capture.
+    pub fn with_frame(mut self, frame: MockFrame) -> Self {
+        self.frames = vec![frame];
+        self
+    }
+
+    /// Show `frames` in consecutive captures.
+    pub fn with_frames(mut self, frames: Vec<MockFrame>) -> Self {
+        assert!(!frames.is_empty(), "an output needs at least one frame");
+        self.frames = frames;
+        self
+    }
+
+    /// The frame shown by the capture after `captured` successful ones.
+    fn frame(&self, captured: usize) -> &MockFrame {
+        &self.frames[captured.min(self.frames.len() - 1)]
+    }
+
+    /// The logical region sent through xdg-output.
+    pub fn logical_region(&self) -> LogicalRegion {
+        let size = self.logical_size.unwrap_or_else(|| {
+            let Region { size, .. } = crate::image_util::transform_region(
+                Region {
+                    position: Position::default(),
+                    size: self.mode,
+                },
+                self.mode,
+                self.transform,
+            );
+            let scale = self.integer_scale.max(1) as u32;
+            Size {
+                width: size.width / scale,
+                height: size.height / scale,
+            }
+        });
+        LogicalRegion {
+            inner: Region {
+                position: self.position,
+                size,
+            },
+        }
+    }
+
+    /// The preferred scale sent through `wp_fractional_scale_v1`.
+    pub fn preferred_scale(&self) -> f64 {
+        self.preferred_scale.unwrap_or_else(|| {
+            let logical_width = self.logical_region().inner.size.width;
+            if logical_width == 0 {
+                return self.integer_scale.max(1) as f64;
+            }
+            let transformed_width = match self.transform {
+                Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+                    self.mode.height
+                }
+                _ => self.mode.width,
+            };
+            transformed_width as f64 / logical_width as f64
+        })
+    }
+}
+
+/// A window announced through the foreign toplevel list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockToplevel {
+    /// Identifier of the window, used to refer to it in [`MockServer`] calls.
+    pub identifier: String,
+    pub title: String,
+    pub app_id: String,
+    /// Sent with every captured frame of the window.
+    pub transform: Transform,
+    pub frame: MockFrame,
+}
+
+impl MockToplevel {
+    pub fn new(identifier: &str, frame: MockFrame) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            title: String::new(),
+            app_id: String::new(),
+            transform: Transform::Normal,
+            frame,
+        }
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_app_id(mut self, app_id: &str) -> Self {
+        self.app_id = app_id.to_string();
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+}
+
+/// The pointer cursor of the seat, captured through ext-image-copy-capture cursor sessions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockCursor {
+    /// Name of the output the cursor is over.
+    pub output: String,
+    /// Position of the hotspot on the output, in the output's buffer coordinates.
+    pub position: Position,
+    /// Offset of the hotspot from the top left corner of `frame`, in buffer coordinates.
+    pub hotspot: Position,
+    /// The cursor image in buffer coordinates, sent with the transform of its output.
+    pub frame: MockFrame,
+}
+
+impl MockCursor {
+    /// A cursor at the top left corner of the output called `output`, with its hotspot at the
+    /// top left corner of `frame`.
+    pub fn new(output: &str, frame: MockFrame) -> Self {
+        Self {
+            output: output.to_string(),
+            position: Position::default(),
+            hotspot: Position::default(),
+            frame,
+        }
+    }
+
+    pub fn with_position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_hotspot(mut self, hotspot: Position) -> Self {
+        self.hotspot = hotspot;
+        self
+    }
+}
+
+/// Configuration of a mock compositor, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct MockCompositor {
+    pub outputs: Vec<MockOutput>,
+    pub toplevels: Vec<MockToplevel>,
+    /// The cursor of the seat. Without one the seat has no pointer.
+    pub cursor: Option<MockCursor>,
+    /// Whether `zwlr_screencopy_manager_v1` is advertised.
+    pub wlr_screencopy: bool,
+    /// Whether `ext_image_copy_capture_manager_v1` and the capture source managers are
+    /// advertised. libwayshot prefers them over wlr-screencopy.
+    pub ext_image_copy_capture: bool,
+    /// Whether `wp_fractional_scale_manager_v1` is advertised.
+    pub fractional_scale: bool,
+}
+
+impl Default for MockCompositor {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            toplevels: Vec::new(),
+            cursor: None,
+            wlr_screencopy: true,
+            ext_image_copy_capture: true,
+            fractional_scale: true,
+        }
+    }
+}
+
+impl MockCompositor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_output(mut self, output: MockOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn with_toplevel(mut self, toplevel: MockToplevel) -> Self {
+        self.toplevels.push(toplevel);
+        self
+    }
+
+    pub fn with_cursor(mut self, cursor: MockCursor) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// Only offer wlr-screencopy for captures.
+    pub fn without_ext_image_copy_capture(mut self) -> Self {
+        self.ext_image_copy_capture = false;
+        self
+    }
+
+    /// Only offer ext-image-copy-capture for captures.
+    pub fn without_wlr_screencopy(mut self) -> Self {
+        self.wlr_screencopy = false;
+        self
+    }
+
+    /// Don't report preferred fractional scales.
+    pub fn without_fractional_scale(mut self) -> Self {
+        self.fractional_scale = false;
+        self
+    }
+
+    /// Start the compositor on its own thread.
+    pub fn spawn(self) -> io::Result<MockServer> {
+        let display = Display::<ServerState>::new().map_err(io::Error::other)?;
+        let mut dh = display.handle();
+        let (server_socket, client_socket) = UnixStream::pair()?;
+        dh.insert_client(server_socket, Arc::new(MockClient))?;
+        let connection = Connection::from_socket(client_socket).map_err(io::Error::other)?;
+
+        dh.create_global::<ServerState, WlShm, _>(1, ());
+        dh.create_global::<ServerState, ZxdgOutputManagerV1, _>(3, ());
+        dh.create_global::<ServerState, ExtForeignToplevelListV1, _>(1, ());
+        dh.create_global::<ServerState, WlCompositor, _>(4, ());
+        dh.create_global::<ServerState, ZwlrLayerShellV1, _>(1, ());
+        dh.create_global::<ServerState, WlSeat, _>(3, ());
+        if self.fractional_scale {
+            dh.create_global::<ServerState, WpFractionalScaleManagerV1, _>(1, ());
+        }
+        if self.wlr_screencopy {
+            dh.create_global::<ServerState, ZwlrScreencopyManagerV1, _>(3, ());
+        }
+        if self.ext_image_copy_capture {
+            dh.create_global::<ServerState, ExtImageCopyCaptureManagerV1, _>(1, ());
+            dh.create_global::<ServerState, ExtOutputImageCaptureSourceManagerV1, _>(1, ());
+            dh.create_global::<ServerState, ExtForeignToplevelImageCaptureSourceManagerV1, _>(
+                1,
+                (),
+            );
+        }
+        let mut state = ServerState {
+            cursor: self.cursor,
+            ..ServerState::default()
+        };
+        for output in self.outputs {
+            state.add_output(&dh, output);
+        }
+        for toplevel in self.toplevels {
+            state.add_toplevel(&dh, toplevel);
+        }
+
+        let (commands, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("mock-compositor".to_string())
+            .spawn({
+                let stop = stop.clone();
+                move || run(display, state, receiver, stop)
+            })?;
+
+        Ok(MockServer {
+            connection,
+            commands,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A running mock compositor. It is stopped when this is dropped.
+///
+/// The methods changing the compositor return once the changes were sent to the client.
+#[derive(Debug)]
+pub struct MockServer {
+    connection: Connection,
+    commands: Sender<(Command, Sender<()>)>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl MockServer {
+    /// The client end of the socket, e.g. for [`crate::WayshotConnection::from_connection`].
+    pub fn connection(&self) -> Connection {
+        self.connection.clone()
+    }
+
+    /// Plug in a new output.
+    pub fn add_output(&self, output: MockOutput) {
+        self.send(Command::AddOutput(output));
+    }
+
+    /// Replace the output with the same name, sending its new properties to the client.
+    pub fn update_output(&self, output: MockOutput) {
+        self.send(Command::UpdateOutput(output));
+    }
+
+    /// Unplug the output called `name`.
+    pub fn remove_output(&self, name: &str) {
+        self.send(Command::RemoveOutput(name.to_string()));
+    }
+
+    /// Stop every ext-image-copy-capture session capturing the output called `name`, like
+    /// compositors do when the output can no longer be captured.
+    pub fn stop_capture_sessions(&self, name: &str) {
+        self.send(Command::StopCaptureSessions(name.to_string()));
+    }
+
+    /// Open a new window.
+    pub fn add_toplevel(&self, toplevel: MockToplevel) {
+        self.send(Command::AddToplevel(toplevel));
+    }
+
+    /// Replace the window with the same identifier, sending its new title and app id.
+    pub fn update_toplevel(&self, toplevel: MockToplevel) {
+        self.send(Command::UpdateToplevel(toplevel));
+    }
+
+    /// Close the window with the given identifier.
+    pub fn close_toplevel(&self, identifier: &str) {
+        self.send(Command::CloseToplevel(identifier.to_string()));
+    }
+
+    fn send(&self, command: Command) {
+        let (done, applied) = mpsc::channel();
+        self.commands
+            .send((command, done))
+            .expect("mock compositor thread exited");
+        applied.recv().expect("mock compositor thread exited");
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take()
+            && let Ok(Err(e)) = thread.join()
+        {
+            tracing::warn!("Mock compositor failed: {e}");
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    AddOutput(MockOutput),
+    UpdateOutput(MockOutput),
+    RemoveOutput(String),
+    StopCaptureSessions(String),
+    AddToplevel(MockToplevel),
+    UpdateToplevel(MockToplevel),
+    CloseToplevel(String),
+}
+
+fn run(
+    mut display: Display<ServerState>,
+    mut state: ServerState,
+    commands: Receiver<(Command, Sender<()>)>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let timeout = Timespec {
+        tv_sec: 0,
+        tv_nsec: 5_000_000,
+    };
+    while !stop.load(Ordering::Relaxed) {
+        while let Ok((command, done)) = commands.try_recv() {
+            state.apply(&display.handle(), command);
+            display.flush_clients()?;
+            let _ = done.send(());
+        }
+        let readable = {
+            let mut fds = [PollFd::new(&display, PollFlags::IN)];
+            poll(&mut fds, Some(&timeout))?
+        };
+        if readable > 0 {
+            display.dispatch_clients(&mut state)?;
+        }
+        display.flush_clients()?;
+    }
+    Ok(())
+}
+
+struct MockClient;
+
+impl ClientData for MockClient {}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    outputs: Vec<ServerOutput>,
+    toplevels: Vec<ServerToplevel>,
+    toplevel_lists: Vec<ExtForeignToplevelListV1>,
+    /// Capture sessions that have not been stopped.
+    sessions: Vec<ExtImageCopyCaptureSessionV1>,
+    cursor: Option<MockCursor>,
+    next_id: u32,
+}
+
+#[derive(Debug)]
+struct ServerOutput {
+    id: u32,
+    output: MockOutput,
+    /// Number of successful captures, selecting the frame the next one shows.
+    captured: usize,
+    global: GlobalId,
+    wl_outputs: Vec<WlOutput>,
+    xdg_outputs: Vec<ZxdgOutputV1>,
+}
+
+#[derive(Debug)]
+struct ServerToplevel {
+    id: u32,
+    toplevel: MockToplevel,
+    handles: Vec<ExtForeignToplevelHandleV1>,
+}
+
+/// What an ext-image-copy-capture source, session or frame captures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Source {
+    Output(u32),
+    Toplevel(u32),
+    /// The cursor over the output with the given id.
+    Cursor(u32),
+}
+
+/// A `wl_buffer` created from a shm pool.
+#[derive(Debug)]
+struct ShmBuffer {
+    file: Arc<File>,
+    offset: i32,
+    width: i32,
+    height: i32,
+    stride: i32,
+    format: u32,
+}
+
+/// A `wl_surface` and the layer surface role it may have.
+#[derive(Debug, Default)]
+struct MockSurface {
+    state: Mutex<SurfaceState>,
+}
+
+#[derive(Debug, Default)]
+struct SurfaceState {
+    /// The layer surface and the id of its output.
+    layer_surface: Option<(ZwlrLayerSurfaceV1, u32)>,
+    configured: bool,
+    /// Whether a buffer is attached, i.e. the surface is mapped once it is configured.
+    has_buffer: bool,
+    fractional_scale: Option<WpFractionalScaleV1>,
+}
+
+impl MockSurface {
+    fn state(&self) -> std::sync::MutexGuard<'_, SurfaceState> {
+        self.state.lock().expect("surface lock poisoned")
+    }
+}
+
+#[derive(Debug)]
+struct WlrFrame {
+    output: u32,
+    region: Option<Region>,
+}
+
+#[derive(Debug)]
+struct ExtFrame {
+    source: Source,
+    session: ExtImageCopyCaptureSessionV1,
+    buffer: Mutex<Option<WlBuffer>>,
+}
+
+impl ServerState {
+    fn apply(&mut self, dh: &DisplayHandle, command: Command) {
+        match command {
+            Command::AddOutput(output) => self.add_output(dh, output),
+            Command::UpdateOutput(output) => {
+                if let Some(server_output) = self
+                    .outputs
+                    .iter_mut()
+                    .find(|server_output| server_output.output.name == output.name)
+                {
+                    server_output.output = output;
+                    server_output.captured = 0;
+                    server_output.send_properties();
+                }
+            }
+            Command::RemoveOutput(name) => {
+                self.outputs.retain(|server_output| {
+                    let keep = server_output.output.name != name;
+                    if !keep {
+                        dh.remove_global::<ServerState>(server_output.global.clone());
+                    }
+                    keep
+                });
+            }
+            Command::StopCaptureSessions(name) => {
+                let ids: Vec<_> = self
+                    .outputs
+                    .iter()
+                    .filter(|server_output| server_output.output.name == name)
+                    .map(|server_output| server_output.id)
+                    .collect();
+                self.sessions.retain(|session| {
+                    let keep = session.is_alive()
+                        && !matches!(
+                            session.data::<Source>(),
+                            Some(Source::Output(id) | Source::Cursor(id)) if ids.contains(id)
+                        );
+                    if !keep && session.is_alive() {
+                        session.stopped();
+                    }
+                    keep
+                });
+            }
+            Command::AddToplevel(toplevel) => self.add_toplevel(dh, toplevel),
+            Command::UpdateToplevel(toplevel) => {
+                if let Some(server_toplevel) = self.toplevels.iter_mut().find(|server_toplevel| {
+                    server_toplevel.toplevel.identifier == toplevel.identifier
+                }) {
+                    server_toplevel.toplevel = toplevel;
+                    for handle in &server_toplevel.handles {
+                        send_toplevel_properties(handle, &server_toplevel.toplevel);
+                    }
+                }
+            }
+            Command::CloseToplevel(identifier) => {
+                self.toplevels.retain(|server_toplevel| {
+                    let keep = server_toplevel.toplevel.identifier != identifier;
+                    if !keep {
+                        for handle in &server_toplevel.handles {
+                            handle.closed();
+                        }
+                    }
+                    keep
+                });
+            }
+        }
+    }
+
+    fn add_output(&mut self, dh: &DisplayHandle, output: MockOutput) {
+        let id = self.next_id();
+        let global = dh.create_global::<ServerState, WlOutput, _>(4, id);
+        self.outputs.push(ServerOutput {
+            id,
+            output,
+            captured: 0,
+            global,
+            wl_outputs: Vec::new(),
+            xdg_outputs: Vec::new(),
+        });
+    }
+
+    fn add_toplevel(&mut self, dh: &DisplayHandle, toplevel: MockToplevel) {
+        let id = self.next_id();
+        let mut server_toplevel = ServerToplevel {
+            id,
+            toplevel,
+            handles: Vec::new(),
+        };
+        self.toplevel_lists.retain(|list| list.is_alive());
+        for list in &self.toplevel_lists {
+            server_toplevel.announce(dh, list);
+        }
+        self.toplevels.push(server_toplevel);
+    }
+
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn output(&self, id: u32) -> Option<&MockOutput> {
+        self.outputs
+            .iter()
+            .find(|server_output| server_output.id == id)
+            .map(|server_output| &server_output.output)
+    }
+
+    /// The frame the next capture of the output with the given id shows.
+    fn output_frame(&self, id: u32) -> Option<&MockFrame> {
+        self.outputs
+            .iter()
+            .find(|server_output| server_output.id == id)
+            .map(|server_output| server_output.output.frame(server_output.captured))
+    }
+
+    /// Move on to the next frame of `source` after a successful capture.
+    fn advance(&mut self, source: Source) {
+        if let Source::Output(id) = source
+            && let Some(server_output) = self
+                .outputs
+                .iter_mut()
+                .find(|server_output| server_output.id == id)
+        {
+            server_output.captured += 1;
+        }
+    }
+
+    /// The cursor if it is over the output with the given id.
+    fn cursor_over(&self, output_id: u32) -> Option<&MockCursor> {
+        let output = self.output(output_id)?;
+        self.cursor
+            .as_ref()
+            .filter(|cursor| cursor.output == output.name)
+    }
+
+    /// The frame and transform a capture of `source` copies.
+    fn source_frame(&self, source: Source) -> Option<(&MockFrame, Transform)> {
+        match source {
+            Source::Output(id) => self
+                .output_frame(id)
+                .zip(self.output(id).map(|output| output.transform)),
+            Source::Toplevel(id) => self
+                .toplevels
+                .iter()
+                .find(|server_toplevel| server_toplevel.id == id)
+                .map(|server_toplevel| {
+                    (
+                        &server_toplevel.toplevel.frame,
+                        server_toplevel.toplevel.transform,
+                    )
+                }),
+            Source::Cursor(id) => self.cursor_over(id).and_then(|cursor| {
+                self.output(id)
+                    .map(|output| (&cursor.frame, output.transform))
+            }),
+        }
+    }
+}
+
+impl ServerOutput {
+    fn send_properties(&mut self) {
+        self.xdg_outputs.retain(|xdg_output| xdg_output.is_alive());
+        for xdg_output in &self.xdg_outputs {
+            send_logical_region(xdg_output, &self.output);
+        }
+        self.wl_outputs.retain(|wl_output| wl_output.is_alive());
+        for wl_output in &self.wl_outputs {
+            send_output_properties(wl_output, &self.output);
+        }
+    }
+}
+
+impl ServerToplevel {
+    fn announce(&mut self, dh: &DisplayHandle, list: &ExtForeignToplevelListV1) {
+        let Some(client) = list.client() else {
+            return;
+        };
+        let Ok(handle) = client.create_resource::<ExtForeignToplevelHandleV1, _, ServerState>(
+            dh,
+            list.version(),
+            self.id,
+        ) else {
+            return;
+        };
+        list.toplevel(&handle);
+        handle.identifier(self.toplevel.identifier.clone());
+        send_toplevel_properties(&handle, &self.toplevel);
+        self.handles.push(handle);
+    }
+}
+
+fn send_output_properties(wl_output: &WlOutput, output: &MockOutput) {
+    let position = output.logical_region().inner.position;
+    wl_output.geometry(
+        position.x,
+        position.y,
+        0,
+        0,
+        wl_output::Subpixel::Unknown,
+        "wayshot".to_string(),
+        "mock".to_string(),
+        server_transform(output.transform),
+    );
+    wl_output.mode(
+        wl_output::Mode::Current,
+        output.mode.width as i32,
+        output.mode.height as i32,
+        60_000,
+    );
+    wl_output.scale(output.integer_scale);
+    wl_output.name(output.name.clone());
+    wl_output.description(output.description.clone());
+    wl_output.done();
+}
+
+fn send_logical_region(xdg_output: &ZxdgOutputV1, output: &MockOutput) {
+    let Region { position, size } = output.logical_region().inner;
+    xdg_output.logical_position(position.x, position.y);
+    xdg_output.logical_size(size.width as i32, size.height as i32);
+}
+
+fn send_toplevel_properties(handle: &ExtForeignToplevelHandleV1, toplevel: &MockToplevel) {
+    handle.title(toplevel.title.clone());
+    handle.app_id(toplevel.app_id.clone());
+    handle.done();
+}
+
+fn server_transform(transform: Transform) -> wl_output::Transform {
+    wl_output::Transform::try_from(u32::from(transform)).unwrap_or(wl_output::Transform::Normal)
+}
+
+fn server_format(format: Format) -> wl_shm::Format {
+    wl_shm::Format::try_from(u32::from(format)).unwrap_or(wl_shm::Format::Xrgb8888)
+}
+
+/// Send the buffer constraints for frames laid out as `frame_format`.
+fn send_constraints(session: &ExtImageCopyCaptureSessionV1, frame_format: &FrameFormat) {
+    session.buffer_size(frame_format.size.width, frame_format.size.height);
+    session.shm_format(server_format(frame_format.format));
+    session.done();
+}
+
+/// Copy `region` of `frame`, or all of it, into the shm `buffer`. Fails when the buffer doesn't
+/// match the advertised constraints.
+fn copy_frame(frame: &MockFrame, region: Option<Region>, buffer: &WlBuffer) -> bool {
+    let Some(shm_buffer) = buffer.data::<ShmBuffer>() else {
+        return false;
+    };
+    let frame_format = &frame.frame_format;
+    let region = region.unwrap_or(Region {
+        position: Position::default(),
+        size: frame_format.size,
+    });
+    if shm_buffer.width as u32 != region.size.width
+        || shm_buffer.height as u32 != region.size.height
+        || shm_buffer.format != u32::from(frame_format.format)
+    {
+        tracing::warn!("Client buffer {shm_buffer:?} doesn't fit {region:?} of {frame_format:?}");
+        return false;
+    }
+    let Ok(mut mmap) = (unsafe { MmapMut::map_mut(&*shm_buffer.file) }) else {
+        return false;
+    };
+    let bytes_per_pixel = frame_format.bytes_per_pixel() as usize;
+    let row_size = region.size.width as usize * bytes_per_pixel;
+    for row in 0..region.size.height as usize {
+        let src_start = (region.position.y as usize + row) * frame_format.stride as usize
+            + region.position.x as usize * bytes_per_pixel;
+        let dst_start = shm_buffer.offset as usize + row * shm_buffer.stride as usize;
+        let (Some(src), Some(dst)) = (
+            frame.data.get(src_start..src_start + row_size),
+            mmap.get_mut(dst_start..dst_start + row_size),
+        ) else {
+            return false;
+        };
+        dst.copy_from_slice(src);
+    }
+    true
+}
+
+impl GlobalDispatch<WlOutput, u32> for ServerState {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlOutput>,
+        id: &u32,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let wl_output = data_init.init(resource, *id);
+        if let Some(server_output) = state.outputs.iter_mut().find(|output| output.id == *id) {
+            send_output_properties(&wl_output, &server_output.output);
+            server_output.wl_outputs.push(wl_output);
+        }
+    }
+}
+
+impl Dispatch<WlOutput, u32> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlOutput,
+        _: wl_output::Request,
+        _: &u32,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZxdgOutputManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZxdgOutputManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputManagerV1,
+        request: zxdg_output_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zxdg_output_manager_v1::Request::GetXdgOutput { id, output } = request {
+            let output_id = *output.data::<u32>().unwrap_or(&0);
+            let xdg_output = data_init.init(id, output_id);
+            if let Some(server_output) = state
+                .outputs
+                .iter_mut()
+                .find(|server_output| server_output.id == output_id)
+            {
+                send_logical_region(&xdg_output, &server_output.output);
+                // xdg-output version 3 is finished by wl_output.done.
+                output.done();
+                server_output.xdg_outputs.push(xdg_output);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, u32> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputV1,
+        _: zxdg_output_v1::Request,
+        _: &u32,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlShm, ()> for ServerState {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlShm>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shm = data_init.init(resource, ());
+        let mut formats = vec![wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888];
+        let frames = state
+            .outputs
+            .iter()
+            .flat_map(|server_output| &server_output.output.frames)
+            .chain(
+                state
+                    .toplevels
+                    .iter()
+                    .map(|toplevel| &toplevel.toplevel.frame),
+            );
+        for frame in frames {
+            let format = server_format(frame.frame_format.format);
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        for format in formats {
+            shm.format(format);
+        }
+    }
+}
+
+impl Dispatch<WlShm, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlShm,
+        request: wl_shm::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, fd, .. } = request {
+            data_init.init(id, Arc::new(File::from(fd)));
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, Arc<File>> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlShmPool,
+        request: wl_shm_pool::Request,
+        file: &Arc<File>,
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm_pool::Request::CreateBuffer {
+            id,
+            offset,
+            width,
+            height,
+            stride,
+            format,
+        } = request
+        {
+            let format = match format {
+                WEnum::Value(format) => format.into(),
+                WEnum::Unknown(format) => format,
+            };
+            data_init.init(
+                id,
+                ShmBuffer {
+                    file: file.clone(),
+                    offset,
+                    width,
+                    height,
+                    stride,
+                    format,
+                },
+            );
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, ShmBuffer> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlBuffer,
+        _: wl_buffer::Request,
+        _: &ShmBuffer,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (frame, output, logical_region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, output, .. } => {
+                (frame, output, None)
+            }
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                output,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => (
+                frame,
+                output,
+                Some(Region {
+                    position: Position { x, y },
+                    size: Size {
+                        width: width.max(0) as u32,
+                        height: height.max(0) as u32,
+                    },
+                }),
+            ),
+            _ => return,
+        };
+        let output_id = *output.data::<u32>().unwrap_or(&0);
+        let Some(mock_output) = state.output(output_id) else {
+            let frame = data_init.init(
+                frame,
+                WlrFrame {
+                    output: output_id,
+                    region: None,
+                },
+            );
+            frame.failed();
+            return;
+        };
+        let frame_format = state
+            .output_frame(output_id)
+            .map_or(mock_output.frames[0].frame_format, |frame| {
+                frame.frame_format
+            });
+        let region = logical_region.map(|inner| {
+            buffer_region(
+                EmbeddedRegion {
+                    relative_to: mock_output.logical_region(),
+                    inner,
+                },
+                &frame_format,
+                mock_output.transform,
+            )
+        });
+        let size = region.map_or(frame_format.size, |region| region.size);
+        let frame = data_init.init(
+            frame,
+            WlrFrame {
+                output: output_id,
+                region,
+            },
+        );
+        frame.buffer(
+            server_format(frame_format.format),
+            size.width,
+            size.height,
+            size.width * frame_format.bytes_per_pixel(),
+        );
+        frame.buffer_done();
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, WlrFrame> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &WlrFrame,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        let buffer = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer }
+            | zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => buffer,
+            _ => return,
+        };
+        let Some(mock_frame) = state.output_frame(data.output) else {
+            frame.failed();
+            return;
+        };
+        if !copy_frame(mock_frame, data.region, &buffer) {
+            frame.failed();
+            return;
+        }
+        let region = data.region.unwrap_or(Region {
+            position: Position::default(),
+            size: mock_frame.frame_format.size,
+        });
+        let damage = mock_frame.damage_in(region);
+        frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+        if frame.version() >= 2 {
+            for Region { position, size } in damage {
+                frame.damage(
+                    position.x as u32,
+                    position.y as u32,
+                    size.width,
+                    size.height,
+                );
+            }
+        }
+        frame.ready(0, 0, 0);
+        state.advance(Source::Output(data.output));
+    }
+}
+
+impl GlobalDispatch<ExtImageCopyCaptureManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ExtImageCopyCaptureManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ExtImageCopyCaptureManagerV1,
+        request: ext_image_copy_capture_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_manager_v1::Request::CreateSession {
+                session, source, ..
+            } => {
+                // Ids start at 1, so unknown sources capture nothing.
+                let source = *source.data::<Source>().unwrap_or(&Source::Output(0));
+                let session = data_init.init(session, source);
+                match state.source_frame(source) {
+                    Some((frame, _)) => {
+                        send_constraints(&session, &frame.frame_format);
+                        state.sessions.push(session);
+                    }
+                    None => session.stopped(),
+                }
+            }
+            ext_image_copy_capture_manager_v1::Request::CreatePointerCursorSession {
+                session,
+                source,
+                ..
+            } => {
+                let output_id = match source.data::<Source>() {
+                    Some(Source::Output(id)) => *id,
+                    _ => 0,
+                };
+                data_init.init(session, output_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, Source> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        session: &ExtImageCopyCaptureSessionV1,
+        request: ext_image_copy_capture_session_v1::Request,
+        source: &Source,
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_image_copy_capture_session_v1::Request::CreateFrame { frame } = request {
+            data_init.init(
+                frame,
+                ExtFrame {
+                    source: *source,
+                    session: session.clone(),
+                    buffer: Mutex::new(None),
+                },
+            );
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ExtFrame> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        frame: &ExtImageCopyCaptureFrameV1,
+        request: ext_image_copy_capture_frame_v1::Request,
+        data: &ExtFrame,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        let mut buffer = data.buffer.lock().expect("frame buffer lock poisoned");
+        match request {
+            ext_image_copy_capture_frame_v1::Request::AttachBuffer { buffer: attached } => {
+                *buffer = Some(attached);
+            }
+            ext_image_copy_capture_frame_v1::Request::Capture => {
+                let source_frame = state
+                    .source_frame(data.source)
+                    .filter(|_| state.sessions.contains(&data.session));
+                let Some((mock_frame, transform)) = source_frame else {
+                    frame.failed(FailureReason::Stopped);
+                    return;
+                };
+                let Some(buffer) = buffer.as_ref() else {
+                    frame.failed(FailureReason::BufferConstraints);
+                    return;
+                };
+                if !copy_frame(mock_frame, None, buffer) {
+                    // The frame changed its format or size, announce the new constraints.
+                    send_constraints(&data.session, &mock_frame.frame_format);
+                    frame.failed(FailureReason::BufferConstraints);
+                    return;
+                }
+                let damage = mock_frame.damage_in(Region {
+                    position: Position::default(),
+                    size: mock_frame.frame_format.size,
+                });
+                frame.transform(server_transform(transform));
+                for Region { position, size } in damage {
+                    frame.damage(
+                        position.x,
+                        position.y,
+                        size.width as i32,
+                        size.height as i32,
+                    );
+                }
+                frame.presentation_time(0, 0, 0);
+                frame.ready();
+                state.advance(data.source);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, u32> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        cursor_session: &ExtImageCopyCaptureCursorSessionV1,
+        request: ext_image_copy_capture_cursor_session_v1::Request,
+        output_id: &u32,
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_image_copy_capture_cursor_session_v1::Request::GetCaptureSession { session } =
+            request
+        {
+            let session = data_init.init(session, Source::Cursor(*output_id));
+            let Some(cursor) = &state.cursor else {
+                session.stopped();
+                return;
+            };
+            send_constraints(&session, &cursor.frame.frame_format);
+            if let Some(cursor) = state.cursor_over(*output_id) {
+                cursor_session.enter();
+                cursor_session.position(cursor.position.x, cursor.position.y);
+                cursor_session.hotspot(cursor.hotspot.x, cursor.hotspot.y);
+            }
+            state.sessions.push(session);
+        }
+    }
+}
+
+impl GlobalDispatch<ExtOutputImageCaptureSourceManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ExtOutputImageCaptureSourceManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ExtOutputImageCaptureSourceManagerV1,
+        request: ext_output_image_capture_source_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_output_image_capture_source_manager_v1::Request::CreateSource {
+            source,
+            output,
+        } = request
+        {
+            let output_id = *output.data::<u32>().unwrap_or(&0);
+            data_init.init(source, Source::Output(output_id));
+        }
+    }
+}
+
+impl GlobalDispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ExtForeignToplevelImageCaptureSourceManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ExtForeignToplevelImageCaptureSourceManagerV1,
+        request: ext_foreign_toplevel_image_capture_source_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_foreign_toplevel_image_capture_source_manager_v1::Request::CreateSource {
+            source,
+            toplevel_handle,
+        } = request
+        {
+            let toplevel_id = *toplevel_handle.data::<u32>().unwrap_or(&0);
+            data_init.init(source, Source::Toplevel(toplevel_id));
+        }
+    }
+}
+
+impl Dispatch<ExtImageCaptureSourceV1, Source> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ExtImageCaptureSourceV1,
+        _: ext_image_capture_source_v1::Request,
+        _: &Source,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ExtForeignToplevelListV1, ()> for ServerState {
+    fn bind(
+        state: &mut Self,
+        dh: &DisplayHandle,
+        _: &Client,
+        resource: New<ExtForeignToplevelListV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let list = data_init.init(resource, ());
+        for server_toplevel in &mut state.toplevels {
+            server_toplevel.announce(dh, &list);
+        }
+        state.toplevel_lists.push(list);
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        list: &ExtForeignToplevelListV1,
+        request: ext_foreign_toplevel_list_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Request::Stop = request {
+            state.toplevel_lists.retain(|other| other != list);
+            list.finished();
+        }
+    }
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, u32> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ExtForeignToplevelHandleV1,
+        _: ext_foreign_toplevel_handle_v1::Request,
+        _: &u32,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlCompositor, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlCompositor>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WlCompositor, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlCompositor,
+        request: wl_compositor::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                data_init.init(id, MockSurface::default());
+            }
+            wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, ());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlSurface, MockSurface> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &WlSurface,
+        request: wl_surface::Request,
+        surface: &MockSurface,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        let mut surface = surface.state();
+        match request {
+            wl_surface::Request::Attach { buffer, .. } => surface.has_buffer = buffer.is_some(),
+            wl_surface::Request::Commit => {
+                let Some((layer_surface, output_id)) = surface.layer_surface.clone() else {
+                    return;
+                };
+                let Some(output) = state.output(output_id) else {
+                    layer_surface.closed();
+                    return;
+                };
+                let size = output.logical_region().inner.size;
+                let preferred_scale = (output.preferred_scale() * 120.0).round() as u32;
+                if !surface.configured {
+                    // The first commit of a layer surface asks for a configure.
+                    layer_surface.configure(state.next_id(), size.width, size.height);
+                    surface.configured = true;
+                } else if surface.has_buffer
+                    && let Some(fractional_scale) = &surface.fractional_scale
+                {
+                    fractional_scale.preferred_scale(preferred_scale);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlRegion, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlRegion,
+        _: wl_region::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZwlrLayerShellV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZwlrLayerShellV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for ServerState {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZwlrLayerShellV1,
+        request: zwlr_layer_shell_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_layer_shell_v1::Request::GetLayerSurface {
+            id,
+            surface,
+            output,
+            ..
+        } = request
+        {
+            // Without an output the compositor picks one, the first one here.
+            let output_id = output
+                .and_then(|output| output.data::<u32>().copied())
+                .or_else(|| state.outputs.first().map(|server_output| server_output.id))
+                .unwrap_or(0);
+            let layer_surface = data_init.init(id, ());
+            if let Some(surface) = surface.data::<MockSurface>() {
+                surface.state().layer_surface = Some((layer_surface, output_id));
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZwlrLayerSurfaceV1,
+        _: zwlr_layer_surface_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WpFractionalScaleManagerV1, ()> for ServerState {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WpFractionalScaleManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WpFractionalScaleManagerV1,
+        request: wp_fractional_scale_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wp_fractional_scale_manager_v1::Request::GetFractionalScale { id, surface } = request
+        {
+            let fractional_scale = data_init.init(id, ());
+            if let Some(surface) = surface.data::<MockSurface>() {
+                surface.state().fractional_scale = Some(fractional_scale);
+            }
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WpFractionalScaleV1,
+        _: wp_fractional_scale_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlSeat, ()> for ServerState {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlSeat>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, ());
+        let capabilities = if state.cursor.is_some() {
+            wl_seat::Capability::Pointer
+        } else {
+            wl_seat::Capability::empty()
+        };
+        seat.capabilities(capabilities);
+    }
+}
+
+impl Dispatch<WlSeat, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlSeat,
+        request: wl_seat::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_seat::Request::GetPointer { id } = request {
+            data_init.init(id, ());
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for ServerState {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlPointer,
+        _: wl_pointer::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
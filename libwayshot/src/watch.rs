@@ -0,0 +1,573 @@
+//! Tracking outputs being plugged in, removed or reconfigured, and windows being opened or closed.
+//!
+//! [`WayshotConnection::refresh_outputs`] takes a snapshot of the outputs. Once
+//! [`WayshotConnection::watch_outputs`] was called the connection instead keeps a registry and
+//! the output objects alive, and folds their events into the output list whenever
+//! [`WayshotConnection::dispatch_output_events`] is called. Toplevels work the same way through
+//! [`WayshotConnection::watch_toplevels`].
+
+use std::io;
+
+use wayland_client::{
+    Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle, WEnum,
+    backend::WaylandError,
+    delegate_noop,
+    protocol::{
+        wl_output::{self, WlOutput},
+        wl_registry::{self, WlRegistry},
+    },
+};
+use wayland_protocols::{
+    ext::foreign_toplevel_list::v1::client::{
+        ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+        ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+    },
+    xdg::xdg_output::zv1::client::{
+        zxdg_output_manager_v1::ZxdgOutputManagerV1,
+        zxdg_output_v1::{self, ZxdgOutputV1},
+    },
+};
+
+use crate::{
+    Error, Result, WayshotConnection,
+    output::{OutputEvent, OutputInfo},
+    region::{LogicalRegion, Position, Size, TopLevel, TopLevelEvent},
+};
+
+/// The objects and event queue kept alive while outputs are watched.
+#[derive(Debug)]
+pub(crate) struct OutputWatcher {
+    event_queue: EventQueue<OutputWatchState>,
+    state: OutputWatchState,
+    _registry: WlRegistry,
+}
+
+#[derive(Debug)]
+struct OutputWatchState {
+    xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    outputs: Vec<WatchedOutput>,
+    events: Vec<OutputEvent>,
+}
+
+#[derive(Debug)]
+struct WatchedOutput {
+    /// Name of the `wl_output` global, used to match `global_remove` events.
+    global_name: u32,
+    /// Properties received so far, they take effect on the next `done` event.
+    pending: OutputInfo,
+    xdg_output: Option<ZxdgOutputV1>,
+    /// Whether the logical region arrived through xdg-output yet.
+    has_logical_region: bool,
+    /// The output as last reported to the caller, `None` until its first `done` event.
+    current: Option<OutputInfo>,
+}
+
+impl OutputWatchState {
+    /// Apply the pending properties of the output with `global_name` and report what changed.
+    fn commit(&mut self, global_name: u32) {
+        let has_xdg_output_manager = self.xdg_output_manager.is_some();
+        let Some(output) = self
+            .outputs
+            .iter_mut()
+            .find(|output| output.global_name == global_name)
+        else {
+            return;
+        };
+        // Wait for the logical region instead of announcing an output without a position.
+        if has_xdg_output_manager && !output.has_logical_region {
+            return;
+        }
+        match &output.current {
+            None => self.events.push(OutputEvent::Added(output.pending.clone())),
+            Some(current) if *current != output.pending => self
+                .events
+                .push(OutputEvent::Changed(output.pending.clone())),
+            Some(_) => return,
+        }
+        tracing::debug!("Output {} is now {:?}", output.pending, output.pending);
+        output.current = Some(output.pending.clone());
+    }
+
+    /// Record the preferred scale of an output. It is not part of the `wl_output` state, so it
+    /// is only looked up by [`WayshotConnection::refresh_preferred_scales`].
+    fn set_preferred_scale(&mut self, wl_output: &WlOutput, preferred_scale: Option<f64>) {
+        let Some(output) = self.find(wl_output) else {
+            return;
+        };
+        output.pending.preferred_scale = preferred_scale;
+        if let Some(current) = &mut output.current {
+            current.preferred_scale = preferred_scale;
+        }
+    }
+
+    fn find(&mut self, wl_output: &WlOutput) -> Option<&mut WatchedOutput> {
+        self.outputs
+            .iter_mut()
+            .find(|output| output.pending.wl_output == *wl_output)
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for OutputWatchState {
+    fn event(
+        state: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == WlOutput::interface().name => {
+                if version < 4 {
+                    tracing::error!("Ignoring a wl_output with version < 4.");
+                    return;
+                }
+                let wl_output = registry.bind::<WlOutput, _, _>(name, 4, qh, ());
+                let xdg_output = state
+                    .xdg_output_manager
+                    .as_ref()
+                    .map(|manager| manager.get_xdg_output(&wl_output, qh, name));
+                state.outputs.push(WatchedOutput {
+                    global_name: name,
+                    pending: OutputInfo {
+                        wl_output,
+                        name: String::new(),
+                        description: String::new(),
+                        transform: wl_output::Transform::Normal,
+                        physical_size: Size::default(),
+                        logical_region: LogicalRegion::default(),
+                        integer_scale: 1,
+                        preferred_scale: None,
+                    },
+                    xdg_output,
+                    has_logical_region: false,
+                    current: None,
+                });
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                let Some(index) = state
+                    .outputs
+                    .iter()
+                    .position(|output| output.global_name == name)
+                else {
+                    return;
+                };
+                let output = state.outputs.remove(index);
+                if let Some(xdg_output) = output.xdg_output {
+                    xdg_output.destroy();
+                }
+                output.pending.wl_output.release();
+                if let Some(current) = output.current {
+                    state.events.push(OutputEvent::Removed(current));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, ()> for OutputWatchState {
+    fn event(
+        state: &mut Self,
+        wl_output: &WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.find(wl_output) else {
+            return;
+        };
+        let info = &mut output.pending;
+        match event {
+            wl_output::Event::Name { name } => info.name = name,
+            wl_output::Event::Description { description } => info.description = description,
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(wl_output::Mode::Current) => {
+                info.physical_size = Size {
+                    width: width as u32,
+                    height: height as u32,
+                };
+            }
+            wl_output::Event::Geometry {
+                transform: WEnum::Value(transform),
+                ..
+            } => info.transform = transform,
+            wl_output::Event::Scale { factor } => info.integer_scale = factor,
+            wl_output::Event::Done => {
+                let global_name = output.global_name;
+                state.commit(global_name);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, u32> for OutputWatchState {
+    fn event(
+        state: &mut Self,
+        _: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        global_name: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state
+            .outputs
+            .iter_mut()
+            .find(|output| output.global_name == *global_name)
+        else {
+            return;
+        };
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                output.pending.logical_region.inner.position = Position { x, y };
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                output.pending.logical_region.inner.size = Size {
+                    width: width as u32,
+                    height: height as u32,
+                };
+                output.has_logical_region = true;
+            }
+            // Deprecated in version 3 in favour of wl_output.done, older compositors still send
+            // it.
+            zxdg_output_v1::Event::Done => state.commit(*global_name),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(OutputWatchState: ignore ZxdgOutputManagerV1);
+
+impl WayshotConnection {
+    /// Start keeping [`WayshotConnection::get_all_outputs`] up to date with outputs being plugged
+    /// in, removed, rotated or rescaled.
+    ///
+    /// The changes are applied and reported by [`WayshotConnection::dispatch_output_events`] and
+    /// [`WayshotConnection::blocking_dispatch_output_events`]. Calling this again restarts the
+    /// tracking.
+    pub fn watch_outputs(&mut self) -> Result<()> {
+        let mut event_queue = self.conn.new_event_queue::<OutputWatchState>();
+        let qh = event_queue.handle();
+        let xdg_output_manager = self
+            .globals
+            .bind::<ZxdgOutputManagerV1, _, _>(&qh, 3..=3, ())
+            .inspect_err(|e| {
+                tracing::warn!("No xdg-output, output positions will not be tracked: {e}")
+            })
+            .ok();
+        let mut state = OutputWatchState {
+            xdg_output_manager,
+            outputs: Vec::new(),
+            events: Vec::new(),
+        };
+        let registry = self.conn.display().get_registry(&qh, ());
+        // The first roundtrip announces the globals, the second delivers the output properties.
+        event_queue.roundtrip(&mut state)?;
+        event_queue.roundtrip(&mut state)?;
+        // These outputs are already known to the caller through `get_all_outputs`.
+        state.events.clear();
+        // Keep the preferred scales that were already looked up. The watcher has its own
+        // `wl_output` objects, so outputs are matched by name.
+        for output in &mut state.outputs {
+            let Some(known) = self
+                .output_infos
+                .iter()
+                .find(|known| known.name == output.pending.name)
+            else {
+                continue;
+            };
+            output.pending.preferred_scale = known.preferred_scale;
+            if let Some(current) = &mut output.current {
+                current.preferred_scale = known.preferred_scale;
+            }
+        }
+
+        let watcher = OutputWatcher {
+            event_queue,
+            state,
+            _registry: registry,
+        };
+        self.output_infos = watcher.current_outputs();
+        self.output_watcher = Some(watcher);
+        Ok(())
+    }
+
+    /// Apply output changes that arrived since the last call, without blocking, and return them.
+    ///
+    /// Use it from an event loop that polls the Wayland socket, e.g. the file descriptor of
+    /// [`WayshotConnection::conn`]. Requires [`WayshotConnection::watch_outputs`].
+    pub fn dispatch_output_events(&mut self) -> Result<Vec<OutputEvent>> {
+        let Some(watcher) = &mut self.output_watcher else {
+            return Err(Error::OutputsNotWatched);
+        };
+        dispatch_nonblocking(&self.conn, &mut watcher.event_queue, &mut watcher.state)?;
+        Ok(self.take_output_events())
+    }
+
+    /// Wait until outputs changed, apply the changes and return them.
+    ///
+    /// Requires [`WayshotConnection::watch_outputs`].
+    pub fn blocking_dispatch_output_events(&mut self) -> Result<Vec<OutputEvent>> {
+        loop {
+            let Some(watcher) = &mut self.output_watcher else {
+                return Err(Error::OutputsNotWatched);
+            };
+            watcher.event_queue.blocking_dispatch(&mut watcher.state)?;
+            let events = self.take_output_events();
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+
+    fn take_output_events(&mut self) -> Vec<OutputEvent> {
+        let Some(watcher) = &mut self.output_watcher else {
+            return Vec::new();
+        };
+        let events = std::mem::take(&mut watcher.state.events);
+        if !events.is_empty() {
+            self.output_infos = watcher.current_outputs();
+        }
+        events
+    }
+}
+
+impl OutputWatcher {
+    pub(crate) fn set_preferred_scale(
+        &mut self,
+        wl_output: &WlOutput,
+        preferred_scale: Option<f64>,
+    ) {
+        self.state.set_preferred_scale(wl_output, preferred_scale);
+    }
+
+    fn current_outputs(&self) -> Vec<OutputInfo> {
+        self.state
+            .outputs
+            .iter()
+            .filter_map(|output| output.current.clone())
+            .collect()
+    }
+}
+
+/// The list object and event queue kept alive while toplevels are watched.
+#[derive(Debug)]
+pub(crate) struct ToplevelWatcher {
+    event_queue: EventQueue<ToplevelWatchState>,
+    state: ToplevelWatchState,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ToplevelWatchState {
+    toplevels: Vec<WatchedToplevel>,
+    events: Vec<TopLevelEvent>,
+}
+
+#[derive(Debug)]
+struct WatchedToplevel {
+    /// Properties received so far, they take effect on the next `done` event.
+    pending: TopLevel,
+    /// The toplevel as last reported to the caller, `None` until its first `done` event.
+    current: Option<TopLevel>,
+}
+
+impl ToplevelWatchState {
+    pub(crate) fn current_toplevels(&self) -> Vec<TopLevel> {
+        self.toplevels
+            .iter()
+            .filter_map(|toplevel| toplevel.current.clone())
+            .collect()
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for ToplevelWatchState {
+    fn event(
+        state: &mut Self,
+        list: &ExtForeignToplevelListV1,
+        event: ext_foreign_toplevel_list_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } => {
+                state.toplevels.push(WatchedToplevel {
+                    pending: TopLevel::new(toplevel),
+                    current: None,
+                });
+            }
+            ext_foreign_toplevel_list_v1::Event::Finished => {
+                tracing::debug!("Compositor stopped sending toplevels");
+                list.destroy();
+            }
+            _ => {}
+        }
+    }
+
+    wayland_client::event_created_child!(ToplevelWatchState, ExtForeignToplevelHandleV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for ToplevelWatchState {
+    fn event(
+        state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: ext_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(index) = state
+            .toplevels
+            .iter()
+            .position(|toplevel| toplevel.pending.handle == *handle)
+        else {
+            return;
+        };
+        let toplevel = &mut state.toplevels[index];
+        let pending = &mut toplevel.pending;
+        match event {
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => pending.title = title,
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => pending.app_id = app_id,
+            ext_foreign_toplevel_handle_v1::Event::Identifier { identifier } => {
+                pending.identifier = identifier
+            }
+            ext_foreign_toplevel_handle_v1::Event::Done => {
+                match &toplevel.current {
+                    None => state.events.push(TopLevelEvent::Added(pending.clone())),
+                    Some(current)
+                        if current.title != pending.title
+                            || current.app_id != pending.app_id
+                            || current.identifier != pending.identifier =>
+                    {
+                        state.events.push(TopLevelEvent::Changed(pending.clone()))
+                    }
+                    Some(_) => return,
+                }
+                tracing::debug!("Toplevel {} is now {:?}", pending.identifier, pending);
+                toplevel.current = Some(pending.clone());
+            }
+            ext_foreign_toplevel_handle_v1::Event::Closed => {
+                let toplevel = state.toplevels.remove(index);
+                toplevel.pending.handle.destroy();
+                if let Some(mut current) = toplevel.current {
+                    current.active = false;
+                    state.events.push(TopLevelEvent::Removed(current));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl WayshotConnection {
+    /// Start keeping [`WayshotConnection::get_all_toplevels`] up to date with windows being
+    /// opened, retitled or closed.
+    ///
+    /// The changes are applied and reported by [`WayshotConnection::dispatch_toplevel_events`]
+    /// and [`WayshotConnection::blocking_dispatch_toplevel_events`]. Calling this again restarts
+    /// the tracking.
+    pub fn watch_toplevels(&mut self) -> Result<()> {
+        let (event_queue, state) = self.bind_toplevel_list()?;
+        self.toplevel_infos = state.current_toplevels();
+        self.toplevel_watcher = Some(ToplevelWatcher { event_queue, state });
+        Ok(())
+    }
+
+    /// Apply toplevel changes that arrived since the last call, without blocking, and return
+    /// them.
+    ///
+    /// Requires [`WayshotConnection::watch_toplevels`].
+    pub fn dispatch_toplevel_events(&mut self) -> Result<Vec<TopLevelEvent>> {
+        let Some(watcher) = &mut self.toplevel_watcher else {
+            return Err(Error::ToplevelsNotWatched);
+        };
+        dispatch_nonblocking(&self.conn, &mut watcher.event_queue, &mut watcher.state)?;
+        Ok(self.take_toplevel_events())
+    }
+
+    /// Wait until toplevels changed, apply the changes and return them.
+    ///
+    /// Requires [`WayshotConnection::watch_toplevels`].
+    pub fn blocking_dispatch_toplevel_events(&mut self) -> Result<Vec<TopLevelEvent>> {
+        loop {
+            let Some(watcher) = &mut self.toplevel_watcher else {
+                return Err(Error::ToplevelsNotWatched);
+            };
+            watcher.event_queue.blocking_dispatch(&mut watcher.state)?;
+            let events = self.take_toplevel_events();
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+
+    /// Find the toplevel with the given [`TopLevel::identifier`].
+    ///
+    /// Unlike titles, identifiers are unique and stay the same while the window is open, so
+    /// they can be kept to capture the same window again later.
+    pub fn find_toplevel(&self, identifier: &str) -> Option<&TopLevel> {
+        self.toplevel_infos
+            .iter()
+            .find(|toplevel| toplevel.identifier == identifier)
+    }
+
+    /// Bind a new toplevel list and wait for the toplevels that are currently open.
+    pub(crate) fn bind_toplevel_list(
+        &self,
+    ) -> Result<(EventQueue<ToplevelWatchState>, ToplevelWatchState)> {
+        let mut event_queue = self.conn.new_event_queue::<ToplevelWatchState>();
+        let qh = event_queue.handle();
+        let mut state = ToplevelWatchState::default();
+        self.globals
+            .bind::<ExtForeignToplevelListV1, _, _>(&qh, 1..=1, ())?;
+        event_queue.roundtrip(&mut state)?;
+        // These toplevels are the starting point, not changes.
+        state.events.clear();
+        Ok((event_queue, state))
+    }
+
+    fn take_toplevel_events(&mut self) -> Vec<TopLevelEvent> {
+        let Some(watcher) = &mut self.toplevel_watcher else {
+            return Vec::new();
+        };
+        let events = std::mem::take(&mut watcher.state.events);
+        if !events.is_empty() {
+            self.toplevel_infos = watcher.state.current_toplevels();
+        }
+        events
+    }
+}
+
+/// Read and dispatch the events that are available on the socket, without blocking.
+fn dispatch_nonblocking<State>(
+    conn: &Connection,
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+) -> Result<()> {
+    match conn.flush() {
+        Ok(()) => {}
+        Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+        Err(e) => return Err(DispatchError::Backend(e).into()),
+    }
+    if let Some(guard) = event_queue.prepare_read() {
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(DispatchError::Backend(e).into()),
+        }
+    }
+    event_queue.dispatch_pending(state)?;
+    Ok(())
+}
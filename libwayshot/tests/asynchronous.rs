@@ -0,0 +1,43 @@
+//! Concurrent captures through the `async` API against the in-process compositor of the
+//! `test-server` feature.
+
+mod common;
+
+use futures_executor::block_on;
+use libwayshot::WayshotConnection;
+
+use common::{BLUE, RED, size, two_outputs, xrgb};
+
+#[test]
+fn frame_copies_of_two_outputs() {
+    let compositor = two_outputs();
+    for compositor in [
+        compositor.clone().without_wlr_screencopy(),
+        compositor.without_ext_image_copy_capture(),
+    ] {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let outputs: Vec<_> = connection
+            .get_all_outputs()
+            .iter()
+            .map(|output_info| (output_info.clone(), None))
+            .collect();
+
+        let frames = block_on(connection.capture_frame_copies_async(&outputs, false)).unwrap();
+        assert_eq!(frames.len(), 2);
+        for ((frame_copy, _, output_info), (frame_size, rgb)) in frames
+            .into_iter()
+            .zip([(size(40, 20), RED), (size(80, 40), BLUE)])
+        {
+            let raw_frame = frame_copy.raw_frame().unwrap();
+            assert_eq!(raw_frame.size, frame_size, "{output_info}");
+            for y in 0..frame_size.height {
+                let row = raw_frame.row(y).unwrap();
+                assert!(
+                    row.chunks_exact(4).all(|pixel| pixel == xrgb(rgb)),
+                    "{output_info}"
+                );
+            }
+        }
+    }
+}
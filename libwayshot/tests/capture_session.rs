@@ -0,0 +1,165 @@
+//! Repeated captures through a `CaptureSession` against the in-process compositor of the
+//! `test-server` feature.
+
+mod common;
+
+use libwayshot::{
+    Error, WayshotConnection,
+    region::{EmbeddedRegion, LogicalRegion, Position, Region, Size},
+    test_server::{MockCompositor, MockFrame, MockOutput},
+};
+
+use common::{BLUE, GREEN, RED, size, xrgb};
+
+const SIZE: Size = size(16, 8);
+
+/// A compositor with a single output playing `frames`, once for each capture protocol.
+fn compositors(frames: Vec<MockFrame>) -> [MockCompositor; 2] {
+    let compositor =
+        MockCompositor::new().with_output(MockOutput::new("TEST-1", SIZE).with_frames(frames));
+    [
+        compositor.clone().without_wlr_screencopy(),
+        compositor.without_ext_image_copy_capture(),
+    ]
+}
+
+#[test]
+fn frames_reuse_the_session_buffer() {
+    let frames = [RED, GREEN, BLUE].map(|rgb| MockFrame::solid(SIZE, rgb));
+    for compositor in compositors(frames.to_vec()) {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let output = connection.get_all_outputs()[0].clone();
+        let mut session = connection.capture_session(&output, false, None).unwrap();
+
+        let mut buffers = Vec::new();
+        for rgb in [RED, GREEN, BLUE, BLUE] {
+            let frame = session.next_frame_raw().unwrap();
+            assert_eq!(frame.size, SIZE);
+            assert_eq!(frame.row(0).unwrap()[..4], xrgb(rgb));
+            buffers.push(frame.data.as_ptr());
+        }
+        assert!(buffers.iter().all(|buffer| *buffer == buffers[0]));
+
+        // Owned frames are copies that outlive the next capture.
+        let frame_copy = session.next_frame().unwrap();
+        session.next_frame_raw().unwrap();
+        let raw_frame = frame_copy.raw_frame().unwrap();
+        assert_ne!(raw_frame.data.as_ptr(), buffers[0]);
+        assert_eq!(raw_frame.row(7).unwrap()[..4], xrgb(BLUE));
+    }
+}
+
+#[test]
+fn region_frames_are_views_into_the_buffer() {
+    let frame = MockFrame::from_fn(SIZE, |x, y| [x as u8, y as u8, 0]);
+    for compositor in compositors(vec![frame]) {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let output = connection.get_all_outputs()[0].clone();
+        let region = EmbeddedRegion {
+            relative_to: output.logical_region,
+            inner: Region {
+                position: Position { x: 3, y: 2 },
+                size: size(5, 4),
+            },
+        };
+        let mut session = connection
+            .capture_session(&output, false, Some(region))
+            .unwrap();
+
+        let frame = session.next_frame_raw().unwrap();
+        assert_eq!(frame.size, region.inner.size);
+        assert_eq!(
+            frame.logical_region,
+            LogicalRegion {
+                inner: region.inner
+            }
+        );
+        assert_eq!(frame.row(0).unwrap()[..4], xrgb([3, 2, 0]));
+        assert_eq!(frame.row(3).unwrap()[16..], xrgb([7, 5, 0]));
+        assert!(frame.row(4).is_none());
+
+        let frame_copy = session.next_frame().unwrap();
+        assert_eq!(frame_copy.frame_format.size, region.inner.size);
+        assert_eq!(frame_copy.frame_format.stride, 5 * 4);
+    }
+}
+
+#[test]
+fn changed_constraints_renegotiate_the_buffer() {
+    let smaller = size(8, 4);
+    let frames = vec![MockFrame::solid(SIZE, RED), MockFrame::solid(smaller, BLUE)];
+    for compositor in compositors(frames) {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let output = connection.get_all_outputs()[0].clone();
+        let mut session = connection.capture_session(&output, false, None).unwrap();
+
+        assert_eq!(session.next_frame_raw().unwrap().size, SIZE);
+        assert_eq!(session.frame_format().unwrap().size, SIZE);
+
+        let frame = session.next_frame_raw().unwrap();
+        assert_eq!(frame.size, smaller);
+        assert_eq!(frame.row(0).unwrap()[..4], xrgb(BLUE));
+        assert_eq!(session.frame_format().unwrap().size, smaller);
+        if let Some(constraints) = session.buffer_constraints() {
+            assert_eq!(constraints.size, smaller);
+        }
+    }
+}
+
+#[test]
+fn stopped_sessions_fail_with_session_stopped() {
+    let [compositor, _] = compositors(vec![MockFrame::solid(SIZE, RED)]);
+    let server = compositor.spawn().unwrap();
+    let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    let output = connection.get_all_outputs()[0].clone();
+    let mut session = connection.capture_session(&output, false, None).unwrap();
+    session.next_frame_raw().unwrap();
+
+    server.stop_capture_sessions("TEST-1");
+    assert!(matches!(
+        session.next_frame_raw(),
+        Err(Error::SessionStopped)
+    ));
+    assert!(matches!(session.next_frame(), Err(Error::SessionStopped)));
+}
+
+#[test]
+fn damage_outside_the_region_is_dropped() {
+    let damage = Region {
+        position: Position { x: 10, y: 5 },
+        size: size(4, 2),
+    };
+    let frame = MockFrame::solid(SIZE, RED).with_damage(vec![damage]);
+    let [compositor, _] = compositors(vec![frame]);
+    let server = compositor.spawn().unwrap();
+    let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    let output = connection.get_all_outputs()[0].clone();
+    let region = |x, y| EmbeddedRegion {
+        relative_to: output.logical_region,
+        inner: Region {
+            position: Position { x, y },
+            size: size(5, 4),
+        },
+    };
+
+    let mut session = connection
+        .capture_session(&output, false, Some(region(0, 0)))
+        .unwrap();
+    assert!(session.next_frame_raw().unwrap().damage.is_empty());
+
+    let mut session = connection
+        .capture_session(&output, false, Some(region(8, 4)))
+        .unwrap();
+    let frame = session.next_frame_raw().unwrap();
+    let damage: Vec<_> = frame.damage.iter().map(|damage| damage.inner).collect();
+    assert_eq!(
+        damage,
+        [Region {
+            position: Position { x: 2, y: 1 },
+            size: size(3, 2),
+        }]
+    );
+}
@@ -0,0 +1,65 @@
+//! Fixtures shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use libwayshot::{
+    region::{LogicalRegion, Position, Region, Size},
+    test_server::{MockCompositor, MockFrame, MockOutput},
+};
+use wayland_client::protocol::wl_output::Transform;
+
+pub const RED: [u8; 3] = [255, 0, 0];
+pub const GREEN: [u8; 3] = [0, 255, 0];
+pub const BLUE: [u8; 3] = [0, 0, 255];
+
+/// Every output transform, with a name for golden files and messages.
+pub const TRANSFORMS: [(Transform, &str); 8] = [
+    (Transform::Normal, "normal"),
+    (Transform::_90, "90"),
+    (Transform::_180, "180"),
+    (Transform::_270, "270"),
+    (Transform::Flipped, "flipped"),
+    (Transform::Flipped90, "flipped_90"),
+    (Transform::Flipped180, "flipped_180"),
+    (Transform::Flipped270, "flipped_270"),
+];
+
+/// Whether `transform` swaps width and height.
+pub fn rotated(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+pub const fn size(width: u32, height: u32) -> Size {
+    Size { width, height }
+}
+
+pub fn region(x: i32, y: i32, width: u32, height: u32) -> LogicalRegion {
+    LogicalRegion {
+        inner: Region {
+            position: Position { x, y },
+            size: size(width, height),
+        },
+    }
+}
+
+/// The `Xrgb8888` bytes of an `[r, g, b]` colour, the format the mock compositor captures in.
+pub fn xrgb([r, g, b]: [u8; 3]) -> [u8; 4] {
+    [b, g, r, 0xff]
+}
+
+/// Two side by side outputs of 40x20 logical pixels, a red one with scale 1 and a blue HiDPI one
+/// with scale 2.
+pub fn two_outputs() -> MockCompositor {
+    MockCompositor::new()
+        .with_output(
+            MockOutput::new("LEFT-1", size(40, 20)).with_frame(MockFrame::solid(size(40, 20), RED)),
+        )
+        .with_output(
+            MockOutput::new("RIGHT-1", size(80, 40))
+                .with_integer_scale(2)
+                .with_position(Position { x: 40, y: 0 })
+                .with_frame(MockFrame::solid(size(80, 40), BLUE)),
+        )
+}
@@ -0,0 +1,142 @@
+//! Parsing geometry strings and placing them on the outputs of the mock compositor.
+
+mod common;
+
+use libwayshot::{
+    Error, GeometryError, WayshotConnection,
+    output::OutputInfo,
+    region::{Geometry, GeometryLength, GeometryOffset, GeometryOrigin, LogicalRegion, Position},
+};
+
+use common::{region, two_outputs};
+
+/// The two outputs of [`two_outputs`], with the scale 2 output moved down a bit.
+fn outputs() -> Vec<OutputInfo> {
+    let mut compositor = two_outputs();
+    compositor.outputs[1].position = Position { x: 40, y: 10 };
+    let server = compositor.spawn().unwrap();
+    WayshotConnection::from_connection(server.connection())
+        .unwrap()
+        .get_all_outputs()
+        .to_vec()
+}
+
+fn resolve(geometry: &str) -> Result<LogicalRegion, Error> {
+    geometry.parse::<Geometry>()?.resolve(&outputs())
+}
+
+fn parse_error(geometry: &str) -> GeometryError {
+    geometry.parse::<Geometry>().unwrap_err()
+}
+
+#[test]
+fn slurp_format() {
+    assert_eq!(
+        "10,-20 800x600".parse::<Geometry>().unwrap(),
+        Geometry {
+            origin: GeometryOrigin::Global,
+            x: GeometryOffset::Start(GeometryLength::Pixels(10)),
+            y: GeometryOffset::Start(GeometryLength::Pixels(-20)),
+            width: GeometryLength::Pixels(800),
+            height: GeometryLength::Pixels(600),
+        }
+    );
+    assert_eq!(resolve(" 5,6   7x8 ").unwrap(), region(5, 6, 7, 8));
+}
+
+#[test]
+fn x11_format() {
+    assert_eq!(resolve("7x8+5+6").unwrap(), region(5, 6, 7, 8));
+    assert_eq!(resolve("7x8+-5+6").unwrap(), region(-5, 6, 7, 8));
+    // Measured from the right and bottom edge of the bounding box of both outputs.
+    assert_eq!(resolve("10x10-0-0").unwrap(), region(70, 20, 10, 10));
+    assert_eq!(resolve("10x10-5+0").unwrap(), region(65, 0, 10, 10));
+}
+
+#[test]
+fn output_relative() {
+    assert_eq!(resolve("RIGHT-1:1,2 3x4").unwrap(), region(41, 12, 3, 4));
+    assert_eq!(resolve("RIGHT-1:3x4+1+2").unwrap(), region(41, 12, 3, 4));
+    assert_eq!(
+        resolve("RIGHT-1:0,0 100%x100%").unwrap(),
+        region(40, 10, 40, 20)
+    );
+    assert_eq!(resolve("RIGHT-1:3x4-0-0").unwrap(), region(77, 26, 3, 4));
+}
+
+#[test]
+fn slurp_output_format_is_cropped() {
+    assert_eq!(
+        resolve("RIGHT-1 30 5 20 10").unwrap(),
+        region(40, 10, 10, 5)
+    );
+    assert!(matches!(
+        resolve("LEFT-1 50 0 10 10"),
+        Err(Error::InvalidGeometry(GeometryError::OutsideOutput(output))) if output == "LEFT-1"
+    ));
+}
+
+#[test]
+fn anchors_and_percentages() {
+    assert_eq!(resolve("center 20x10").unwrap(), region(30, 10, 20, 10));
+    assert_eq!(resolve("top-left 20x10").unwrap(), region(0, 0, 20, 10));
+    assert_eq!(
+        resolve("bottom-right 20x10").unwrap(),
+        region(60, 20, 20, 10)
+    );
+    assert_eq!(
+        resolve("LEFT-1:center 50%x50%").unwrap(),
+        region(10, 5, 20, 10)
+    );
+    assert_eq!(
+        resolve("RIGHT-1:bottom 10x4").unwrap(),
+        region(55, 26, 10, 4)
+    );
+    assert_eq!(resolve("25%,50% 50%x10%").unwrap(), region(20, 15, 40, 3));
+}
+
+#[test]
+fn clear_errors() {
+    assert_eq!(parse_error("  "), GeometryError::Empty);
+    assert_eq!(
+        parse_error("1,2"),
+        GeometryError::UnknownFormat("1,2".to_string())
+    );
+    assert_eq!(
+        parse_error("1;2 3x4"),
+        GeometryError::UnknownFormat("1;2 3x4".to_string())
+    );
+    assert_eq!(
+        parse_error("1,a 3x4"),
+        GeometryError::InvalidPosition("1,a".to_string())
+    );
+    assert_eq!(
+        parse_error("1,2 3x-4"),
+        GeometryError::InvalidSize("3x-4".to_string())
+    );
+    assert_eq!(
+        parse_error("3x4+1"),
+        GeometryError::UnknownFormat("3x4+1".to_string())
+    );
+    assert_eq!(
+        parse_error("3x4+1+2px"),
+        GeometryError::InvalidLength("2px".to_string())
+    );
+    assert_eq!(
+        parse_error("middle 3x4"),
+        GeometryError::UnknownAnchor("middle".to_string())
+    );
+    assert_eq!(parse_error(":1,2 3x4"), GeometryError::MissingOutputName);
+    assert_eq!(
+        parse_error("DP-1 1 2 -3 4"),
+        GeometryError::InvalidSize("-3x4".to_string())
+    );
+    assert_eq!(
+        parse_error("DP-1 1 2 3 x"),
+        GeometryError::InvalidLength("x".to_string())
+    );
+    assert!(matches!(
+        resolve("DP-9:1,2 3x4"),
+        Err(Error::InvalidGeometry(GeometryError::UnknownOutput(output))) if output == "DP-9"
+    ));
+}
@@ -0,0 +1,256 @@
+//! Golden-image tests of multi-output composites, captured from the mock compositor of the
+//! `test-server` feature.
+//!
+//! Every case is captured through both wlr-screencopy and ext-image-copy-capture and compared
+//! against `tests/golden/<name>.png`. Run with `WAYSHOT_UPDATE_GOLDEN=1` to write the current
+//! results as the new goldens after checking them by eye; on a mismatch the actual image is saved
+//! next to the test binaries.
+
+mod common;
+
+use std::path::PathBuf;
+
+use image::{
+    DynamicImage, RgbaImage,
+    imageops::{self, FilterType},
+};
+use libwayshot::{
+    ScalePolicy, WayshotConnection,
+    region::{Position, Size},
+    test_server::{MockCompositor, MockFrame, MockOutput},
+};
+
+use common::{TRANSFORMS, region, size};
+
+/// A frame whose red and green channels grow along the buffer's x and y axes, with a white
+/// marker in the top left buffer corner, so flips and rotations are easy to spot.
+fn pattern(mode: Size, blue: u8) -> MockFrame {
+    MockFrame::from_fn(mode, |x, y| {
+        if x < mode.width / 4 && y < mode.height / 4 {
+            return [255, 255, 255];
+        }
+        [
+            (x * 255 / (mode.width - 1)) as u8,
+            (y * 255 / (mode.height - 1)) as u8,
+            blue,
+        ]
+    })
+}
+
+fn output(name: &str, mode: Size, blue: u8) -> MockOutput {
+    MockOutput::new(name, mode).with_frame(pattern(mode, blue))
+}
+
+/// Capture with `capture` through each protocol and compare the results with the golden `name`.
+fn assert_golden(
+    name: &str,
+    compositor: MockCompositor,
+    scale_policy: ScalePolicy,
+    capture: impl Fn(&mut WayshotConnection) -> DynamicImage,
+) {
+    let protocols = [
+        ("ext", compositor.clone().without_wlr_screencopy()),
+        ("wlr", compositor.without_ext_image_copy_capture()),
+    ];
+    for (protocol, compositor) in protocols {
+        let server = compositor.spawn().unwrap();
+        let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        connection.set_scale_policy(scale_policy);
+        compare(name, protocol, capture(&mut connection).to_rgba8());
+    }
+}
+
+fn compare(name: &str, protocol: &str, actual: RgbaImage) {
+    let golden_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    if std::env::var_os("WAYSHOT_UPDATE_GOLDEN").is_some() {
+        actual.save(&golden_path).unwrap();
+        return;
+    }
+    let golden = image::open(&golden_path)
+        .unwrap_or_else(|e| panic!("{}: {e}", golden_path.display()))
+        .to_rgba8();
+    if golden != actual {
+        let actual_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("{name}-{protocol}.actual.png"));
+        actual.save(&actual_path).unwrap();
+        panic!(
+            "{name} captured through {protocol} doesn't match {}, see {}",
+            golden_path.display(),
+            actual_path.display()
+        );
+    }
+}
+
+#[test]
+fn every_transform() {
+    for (transform, transform_name) in TRANSFORMS {
+        let compositor = MockCompositor::new()
+            .with_output(output("OUT-1", size(16, 8), 0).with_transform(transform));
+        assert_golden(
+            &format!("transform_{transform_name}"),
+            compositor,
+            ScalePolicy::Max,
+            |connection| connection.screenshot_all(false).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn every_transform_scaled_region() {
+    // Half of a rotated or flipped HiDPI output, so the crop has to follow the transform.
+    for (transform, transform_name) in TRANSFORMS {
+        let compositor = MockCompositor::new().with_output(
+            output("OUT-1", size(32, 16), 0)
+                .with_transform(transform)
+                .with_integer_scale(2),
+        );
+        let logical_size = compositor.outputs[0].logical_region().inner.size;
+        assert_golden(
+            &format!("transform_{transform_name}_scaled_region"),
+            compositor,
+            ScalePolicy::Max,
+            |connection| {
+                connection
+                    .screenshot(
+                        region(0, 0, logical_size.width / 2, logical_size.height),
+                        false,
+                    )
+                    .unwrap()
+            },
+        );
+    }
+}
+
+/// A scale 1 output next to a scale 2 and a scale 1.5 output, all 16x8 logical pixels.
+fn mixed_scales() -> MockCompositor {
+    MockCompositor::new()
+        .with_output(output("LOW-1", size(16, 8), 0))
+        .with_output(
+            output("HIGH-1", size(32, 16), 128)
+                .with_integer_scale(2)
+                .with_position(Position { x: 16, y: 0 }),
+        )
+        .with_output(
+            output("FRACTIONAL-1", size(24, 12), 255)
+                .with_integer_scale(2)
+                .with_logical_size(size(16, 8))
+                .with_position(Position { x: 32, y: 0 }),
+        )
+}
+
+#[test]
+fn mixed_scales_per_policy() {
+    let policies = [
+        (ScalePolicy::Max, "max"),
+        (ScalePolicy::Min, "min"),
+        (ScalePolicy::Logical, "logical"),
+        (ScalePolicy::Native, "native"),
+    ];
+    for (scale_policy, policy_name) in policies {
+        assert_golden(
+            &format!("mixed_scales_{policy_name}"),
+            mixed_scales(),
+            scale_policy,
+            |connection| connection.screenshot_all(false).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn mixed_scales_nearest_neighbour() {
+    assert_golden(
+        "mixed_scales_max_nearest",
+        mixed_scales(),
+        ScalePolicy::Max,
+        |connection| {
+            connection.set_resample_filter(FilterType::Nearest);
+            connection.screenshot_all(false).unwrap()
+        },
+    );
+}
+
+#[test]
+fn mixed_scales_region_across_outputs() {
+    assert_golden(
+        "mixed_scales_region",
+        mixed_scales(),
+        ScalePolicy::Max,
+        |connection| connection.screenshot(region(10, 2, 30, 4), false).unwrap(),
+    );
+}
+
+/// Outputs at negative positions with a gap and a vertical offset between them.
+fn gaps() -> MockCompositor {
+    MockCompositor::new()
+        .with_output(output("LEFT-1", size(16, 8), 0).with_position(Position { x: -20, y: -4 }))
+        .with_output(
+            output("RIGHT-1", size(32, 16), 255)
+                .with_integer_scale(2)
+                .with_position(Position { x: 0, y: 2 }),
+        )
+}
+
+#[test]
+fn gaps_between_outputs() {
+    assert_golden("gaps_all", gaps(), ScalePolicy::Max, |connection| {
+        connection.screenshot_all(false).unwrap()
+    });
+}
+
+#[test]
+fn region_partly_outside_every_output() {
+    assert_golden("gaps_region", gaps(), ScalePolicy::Max, |connection| {
+        connection
+            .screenshot(region(-24, -6, 44, 20), false)
+            .unwrap()
+    });
+}
+
+#[test]
+fn filtered_scaling_matches_imageops_resize() {
+    let filters = [
+        FilterType::Triangle,
+        FilterType::CatmullRom,
+        FilterType::Gaussian,
+        FilterType::Lanczos3,
+    ];
+    for (transform, transform_name) in TRANSFORMS {
+        // The rotated output is upscaled to the scale of the other one.
+        let compositor = MockCompositor::new()
+            .with_output(output("LOW-1", size(16, 8), 0).with_transform(transform))
+            .with_output(
+                output("HIGH-1", size(32, 32), 255)
+                    .with_integer_scale(2)
+                    .with_position(Position { x: 16, y: 0 }),
+            )
+            .without_wlr_screencopy();
+        let server = compositor.spawn().unwrap();
+        let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let low = connection.get_all_outputs()[0].logical_region.inner.size;
+
+        connection.set_scale_policy(ScalePolicy::Native);
+        let native = connection.screenshot_all(false).unwrap().to_rgba8();
+        let upright = imageops::crop_imm(&native, 0, 0, low.width, low.height).to_image();
+        connection.set_scale_policy(ScalePolicy::Max);
+        for filter in filters {
+            connection.set_resample_filter(filter);
+            let screenshot = connection.screenshot_all(false).unwrap().to_rgba8();
+            let actual =
+                imageops::crop_imm(&screenshot, 0, 0, low.width * 2, low.height * 2).to_image();
+            let expected = imageops::resize(&upright, low.width * 2, low.height * 2, filter);
+            let difference = expected
+                .as_raw()
+                .iter()
+                .zip(actual.as_raw())
+                .map(|(expected, actual)| expected.abs_diff(*actual))
+                .max();
+            // Rounding to 8 bits may differ by one.
+            assert!(
+                difference <= Some(1),
+                "{transform_name} with {filter:?} differs by {difference:?}"
+            );
+        }
+    }
+}
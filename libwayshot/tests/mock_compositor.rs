@@ -0,0 +1,403 @@
+//! Captures against the in-process compositor of the `test-server` feature.
+
+mod common;
+
+use image::{DynamicImage, Rgba, imageops};
+use libwayshot::{
+    Error, ToplevelSize, WayshotConnection,
+    output::OutputEvent,
+    region::{Position, Region, Size, TopLevelEvent},
+    test_server::{MockCompositor, MockCursor, MockFrame, MockOutput, MockToplevel},
+};
+use wayland_client::protocol::wl_output::Transform;
+
+use common::{BLUE, RED, region, size, two_outputs};
+
+fn rgba([r, g, b]: [u8; 3]) -> Rgba<u8> {
+    Rgba([r, g, b, 255])
+}
+
+#[test]
+fn outputs_are_reported() {
+    let server = two_outputs().spawn().unwrap();
+    let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+
+    let outputs = connection.get_all_outputs();
+    assert_eq!(outputs.len(), 2);
+    let right = outputs
+        .iter()
+        .find(|output| output.name == "RIGHT-1")
+        .unwrap();
+    assert_eq!(right.description, "Mock output RIGHT-1");
+    assert_eq!(right.integer_scale, 2);
+    assert_eq!(right.scale(), 2.0);
+    assert_eq!(right.logical_region, region(40, 0, 40, 20));
+}
+
+#[test]
+fn screenshot_through_each_protocol() {
+    let compositors = [
+        two_outputs().without_wlr_screencopy(),
+        two_outputs().without_ext_image_copy_capture(),
+    ];
+    for compositor in compositors {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+
+        // Upscaled to the largest output scale by default.
+        let image = connection.screenshot_all(false).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (160, 40));
+        assert_eq!(*image.get_pixel(0, 0), rgba(RED));
+        assert_eq!(*image.get_pixel(79, 39), rgba(RED));
+        assert_eq!(*image.get_pixel(80, 0), rgba(BLUE));
+        assert_eq!(*image.get_pixel(159, 39), rgba(BLUE));
+    }
+}
+
+#[test]
+fn scripted_frames_and_damage() {
+    let mode = size(16, 8);
+    let damage = Region {
+        position: Position { x: 2, y: 1 },
+        size: size(4, 3),
+    };
+    let output = MockOutput::new("TEST-1", mode).with_frames(vec![
+        MockFrame::solid(mode, RED),
+        MockFrame::solid(mode, BLUE).with_damage(vec![damage]),
+    ]);
+    let compositor = MockCompositor::new().with_output(output);
+    let protocols = [
+        compositor.clone().without_wlr_screencopy(),
+        compositor.without_ext_image_copy_capture(),
+    ];
+    for compositor in protocols {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let output = connection.get_all_outputs()[0].clone();
+        let logical_region = output.logical_region;
+
+        let mut captures = Vec::new();
+        // The last frame is repeated once all were shown.
+        for _ in 0..3 {
+            let mut frames = connection
+                .capture_frame_copies(&[(output.clone(), None)], false)
+                .unwrap();
+            let (frame_copy, _, _) = frames.remove(0);
+            let image = DynamicImage::try_from(&frame_copy).unwrap().to_rgba8();
+            for damage in &frame_copy.damage {
+                assert_eq!(damage.relative_to, logical_region);
+            }
+            let damage: Vec<_> = frame_copy
+                .damage
+                .iter()
+                .map(|damage| damage.inner)
+                .collect();
+            captures.push((*image.get_pixel(0, 0), damage));
+        }
+        let full = Region {
+            position: Position::default(),
+            size: mode,
+        };
+        let partial = damage;
+        assert_eq!(
+            captures,
+            [
+                (rgba(RED), vec![full]),
+                (rgba(BLUE), vec![partial]),
+                (rgba(BLUE), vec![partial]),
+            ]
+        );
+    }
+}
+
+#[test]
+fn region_screenshot_spanning_outputs() {
+    let server = two_outputs()
+        .without_ext_image_copy_capture()
+        .spawn()
+        .unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    connection.set_scale_policy(libwayshot::ScalePolicy::Logical);
+
+    let image = connection
+        .screenshot(region(30, 5, 20, 10), false)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(image.dimensions(), (20, 10));
+    assert_eq!(*image.get_pixel(9, 0), rgba(RED));
+    assert_eq!(*image.get_pixel(10, 9), rgba(BLUE));
+}
+
+#[test]
+fn freeze_matches_region_screenshot() {
+    // Outputs with scale 1, a rotated one with scale 2 and a flipped one with scale 1.5, each
+    // showing a gradient so every pixel differs.
+    let gradient = |mode: Size, blue: u8| {
+        MockFrame::from_fn(mode, move |x, y| [(x * 8) as u8, (y * 8) as u8, blue])
+    };
+    let output = |name: &str, mode: Size, blue: u8| {
+        MockOutput::new(name, mode).with_frame(gradient(mode, blue))
+    };
+    let compositor = MockCompositor::new()
+        .with_output(output("LOW-1", size(16, 8), 0))
+        .with_output(
+            output("ROTATED-1", size(32, 16), 128)
+                .with_transform(Transform::_90)
+                .with_integer_scale(2)
+                .with_position(Position { x: 16, y: 0 }),
+        )
+        .with_output(
+            output("FRACTIONAL-1", size(24, 12), 255)
+                .with_transform(Transform::Flipped270)
+                .with_integer_scale(2)
+                .with_logical_size(size(8, 16))
+                .with_position(Position { x: 24, y: 0 }),
+        );
+    let region = region(5, 3, 24, 9);
+
+    let protocols = [
+        compositor.clone().without_wlr_screencopy(),
+        compositor.without_ext_image_copy_capture(),
+    ];
+    for compositor in protocols {
+        let server = compositor.spawn().unwrap();
+        let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+        let frozen = connection
+            .screenshot_freeze(
+                move |_, frames| {
+                    assert_eq!(frames.len(), 3);
+                    Ok(region)
+                },
+                false,
+            )
+            .unwrap()
+            .to_rgba8();
+        let screenshot = connection.screenshot(region, false).unwrap().to_rgba8();
+        assert_eq!(frozen.dimensions(), (48, 18));
+        assert_eq!(frozen, screenshot);
+    }
+}
+
+#[test]
+fn toplevel_capture_applies_its_transform() {
+    let frame_size = size(6, 4);
+    // Red left column in buffer coordinates, which becomes the top row after rotating by 90°.
+    let frame = MockFrame::from_fn(frame_size, |x, _| if x == 0 { RED } else { BLUE });
+    let mock_toplevel = MockToplevel::new("window-1", frame)
+        .with_title("Terminal")
+        .with_app_id("foot")
+        .with_transform(Transform::_90);
+    let server = two_outputs()
+        .with_toplevel(mock_toplevel.clone())
+        .spawn()
+        .unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+
+    let toplevel = connection.find_toplevel("window-1").unwrap().clone();
+    assert_eq!(toplevel.id_and_title(), "foot Terminal");
+
+    let image = connection
+        .screenshot_toplevel(toplevel.clone(), false)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(image.dimensions(), (4, 6));
+    assert_eq!(*image.get_pixel(3, 0), rgba(RED));
+    assert_eq!(*image.get_pixel(0, 5), rgba(BLUE));
+
+    // The outputs have scales 1 and 2, so the scale of the window can't be told.
+    connection.set_toplevel_size(ToplevelSize::Logical);
+    assert!(matches!(
+        connection.screenshot_toplevel(toplevel, false),
+        Err(Error::ToplevelScaleUnknown)
+    ));
+
+    let server = MockCompositor::new()
+        .with_output(MockOutput::new("HIDPI-1", size(80, 40)).with_integer_scale(2))
+        .with_toplevel(mock_toplevel)
+        .spawn()
+        .unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    connection.set_toplevel_size(ToplevelSize::Logical);
+    let toplevel = connection.find_toplevel("window-1").unwrap().clone();
+    let image = connection.screenshot_toplevel(toplevel, false).unwrap();
+    assert_eq!((image.width(), image.height()), (2, 3));
+}
+
+#[test]
+fn cursor_on_a_rotated_scaled_output() {
+    // The hotspot of the 4x6 cursor image is the only red pixel.
+    let hotspot = Position { x: 1, y: 3 };
+    let cursor_frame = MockFrame::from_fn(size(4, 6), |x, y| {
+        if (x as i32, y as i32) == (hotspot.x, hotspot.y) {
+            RED
+        } else {
+            BLUE
+        }
+    });
+    let output = MockOutput::new("ROTATED-1", size(32, 16))
+        .with_transform(Transform::_90)
+        .with_integer_scale(2)
+        .with_position(Position { x: 100, y: 0 });
+    let server = MockCompositor::new()
+        .with_output(output)
+        .with_output(MockOutput::new("OTHER-1", size(8, 8)))
+        .with_cursor(
+            MockCursor::new("ROTATED-1", cursor_frame)
+                .with_position(Position { x: 9, y: 5 })
+                .with_hotspot(hotspot),
+        )
+        .without_wlr_screencopy()
+        .spawn()
+        .unwrap();
+    let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    let outputs = connection.get_all_outputs().to_vec();
+
+    let cursor = connection.capture_cursor(&outputs[0]).unwrap().unwrap();
+    // Buffer pixel (9, 5) of the 32x16 buffer is pixel (10, 9) of the output turned upright.
+    assert_eq!(cursor.position, Position { x: 10, y: 9 });
+    assert_eq!(cursor.hotspot, Position { x: 2, y: 1 });
+    assert_eq!(cursor.image.transform, Transform::_90);
+    assert_eq!(cursor.image.physical_size, size(6, 4));
+    assert_eq!(cursor.image.logical_region, region(104, 4, 3, 2));
+    let upright = imageops::rotate90(&DynamicImage::try_from(&cursor.image).unwrap().to_rgba8());
+    let red = upright
+        .enumerate_pixels()
+        .find(|(_, _, pixel)| **pixel == rgba(RED))
+        .map(|(x, y, _)| (x as i32, y as i32));
+    assert_eq!(red, Some((cursor.hotspot.x, cursor.hotspot.y)));
+
+    assert!(connection.capture_cursor(&outputs[1]).unwrap().is_none());
+
+    let server = two_outputs().without_wlr_screencopy().spawn().unwrap();
+    let connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    let output = connection.get_all_outputs()[0].clone();
+    assert!(connection.capture_cursor(&output).unwrap().is_none());
+}
+
+#[test]
+fn output_changes_are_tracked() {
+    let server = two_outputs().spawn().unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    connection.watch_outputs().unwrap();
+
+    let mode = size(10, 10);
+    server.add_output(MockOutput::new("NEW-1", mode).with_position(Position { x: 0, y: 20 }));
+    let events = connection.blocking_dispatch_output_events().unwrap();
+    assert!(matches!(&events[..], [OutputEvent::Added(output)] if output.name == "NEW-1"));
+    assert_eq!(connection.get_all_outputs().len(), 3);
+
+    server.update_output(
+        MockOutput::new("NEW-1", mode)
+            .with_position(Position { x: 0, y: 20 })
+            .with_transform(Transform::_90),
+    );
+    let events = connection.blocking_dispatch_output_events().unwrap();
+    assert!(matches!(
+        &events[..],
+        [OutputEvent::Changed(output)] if output.transform == Transform::_90
+    ));
+
+    server.remove_output("LEFT-1");
+    let events = connection.blocking_dispatch_output_events().unwrap();
+    assert!(matches!(&events[..], [OutputEvent::Removed(output)] if output.name == "LEFT-1"));
+    let names: Vec<_> = connection
+        .get_all_outputs()
+        .iter()
+        .map(|output| output.name.as_str())
+        .collect();
+    assert_eq!(names, ["RIGHT-1", "NEW-1"]);
+}
+
+#[test]
+fn preferred_scales_are_reported() {
+    let mode = size(30, 20);
+    let compositor = MockCompositor::new()
+        .with_output(MockOutput::new("DERIVED-1", mode).with_integer_scale(2))
+        .with_output(
+            MockOutput::new("FRACTIONAL-1", mode)
+                .with_preferred_scale(1.25)
+                .with_position(Position { x: 30, y: 0 }),
+        );
+    let preferred_scales = |connection: &WayshotConnection| -> Vec<Option<f64>> {
+        connection
+            .get_all_outputs()
+            .iter()
+            .map(|output| output.preferred_scale)
+            .collect()
+    };
+
+    // Only looked up on request, and never used as the capture scale.
+    let server = compositor.clone().spawn().unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    assert_eq!(preferred_scales(&connection), [None, None]);
+    connection.refresh_preferred_scales().unwrap();
+    assert_eq!(preferred_scales(&connection), [Some(2.0), Some(1.25)]);
+    let output = &connection.get_all_outputs()[1];
+    assert_eq!(
+        output.scale(),
+        output.transformed_physical_size().width as f64
+            / output.logical_region.inner.size.width as f64
+    );
+
+    connection.watch_outputs().unwrap();
+    assert_eq!(preferred_scales(&connection), [Some(2.0), Some(1.25)]);
+    server.update_output(
+        MockOutput::new("FRACTIONAL-1", mode)
+            .with_preferred_scale(1.5)
+            .with_integer_scale(2)
+            .with_position(Position { x: 30, y: 0 }),
+    );
+    let events = connection.blocking_dispatch_output_events().unwrap();
+    assert!(matches!(
+        &events[..],
+        [OutputEvent::Changed(output)] if output.preferred_scale == Some(1.25)
+    ));
+    connection.refresh_preferred_scales().unwrap();
+    assert_eq!(preferred_scales(&connection), [Some(2.0), Some(1.5)]);
+    assert!(connection.dispatch_output_events().unwrap().is_empty());
+
+    let server = compositor.without_fractional_scale().spawn().unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    assert!(connection.refresh_preferred_scales().is_err());
+    assert_eq!(preferred_scales(&connection), [None, None]);
+    assert_eq!(connection.get_all_outputs()[0].scale(), 2.0);
+}
+
+#[test]
+fn toplevel_changes_are_tracked() {
+    let frame = MockFrame::solid(size(2, 2), RED);
+    let server = two_outputs()
+        .with_toplevel(MockToplevel::new("window-1", frame.clone()).with_title("First"))
+        .spawn()
+        .unwrap();
+    let mut connection = WayshotConnection::from_connection(server.connection()).unwrap();
+    connection.watch_toplevels().unwrap();
+    assert_eq!(connection.get_all_toplevels().len(), 1);
+
+    server.add_toplevel(MockToplevel::new("window-2", frame.clone()).with_title("Second"));
+    let events = connection.blocking_dispatch_toplevel_events().unwrap();
+    assert!(matches!(
+        &events[..],
+        [TopLevelEvent::Added(toplevel)] if toplevel.identifier == "window-2"
+    ));
+
+    server.update_toplevel(MockToplevel::new("window-1", frame).with_title("Renamed"));
+    let events = connection.blocking_dispatch_toplevel_events().unwrap();
+    assert!(matches!(
+        &events[..],
+        [TopLevelEvent::Changed(toplevel)] if toplevel.title == "Renamed"
+    ));
+    assert_eq!(
+        connection.find_toplevel("window-1").unwrap().title,
+        "Renamed"
+    );
+
+    server.close_toplevel("window-2");
+    let events = connection.blocking_dispatch_toplevel_events().unwrap();
+    assert!(matches!(
+        &events[..],
+        [TopLevelEvent::Removed(toplevel)] if !toplevel.active
+    ));
+    assert!(connection.find_toplevel("window-2").is_none());
+    assert_eq!(connection.get_all_toplevels().len(), 1);
+}
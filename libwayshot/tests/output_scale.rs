@@ -0,0 +1,172 @@
+//! Scale and size calculations of [`OutputInfo`] for every output transform.
+
+use std::os::unix::net::UnixStream;
+
+use libwayshot::{
+    output::OutputInfo,
+    region::{LogicalRegion, Position, Region, Size},
+};
+use wayland_client::{
+    Connection, Proxy,
+    protocol::wl_output::{Transform, WlOutput},
+};
+
+const ROTATING: [Transform; 4] = [
+    Transform::_90,
+    Transform::_270,
+    Transform::Flipped90,
+    Transform::Flipped270,
+];
+const NON_ROTATING: [Transform; 4] = [
+    Transform::Normal,
+    Transform::_180,
+    Transform::Flipped,
+    Transform::Flipped180,
+];
+
+/// An output with a 3840x2160 mode whose logical size is `logical_size`.
+fn output(transform: Transform, logical_size: Size, integer_scale: i32) -> OutputInfo {
+    let (socket, _peer) = UnixStream::pair().expect("socket pair");
+    let conn = Connection::from_socket(socket).expect("connection");
+    OutputInfo {
+        wl_output: WlOutput::inert(conn.backend().downgrade()),
+        name: "TEST-1".to_string(),
+        description: String::new(),
+        transform,
+        physical_size: Size {
+            width: 3840,
+            height: 2160,
+        },
+        logical_region: LogicalRegion {
+            inner: Region {
+                position: Position { x: 0, y: 0 },
+                size: logical_size,
+            },
+        },
+        integer_scale,
+        preferred_scale: None,
+    }
+}
+
+#[test]
+fn transformed_physical_size_swaps_for_rotations() {
+    for transform in NON_ROTATING {
+        let output = output(transform, Size::default(), 1);
+        assert_eq!(
+            output.transformed_physical_size(),
+            Size {
+                width: 3840,
+                height: 2160
+            },
+            "{transform:?}"
+        );
+    }
+    for transform in ROTATING {
+        let output = output(transform, Size::default(), 1);
+        assert_eq!(
+            output.transformed_physical_size(),
+            Size {
+                width: 2160,
+                height: 3840
+            },
+            "{transform:?}"
+        );
+    }
+}
+
+#[test]
+fn integer_scale() {
+    for transform in NON_ROTATING {
+        let logical_size = Size {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(
+            output(transform, logical_size, 2).scale(),
+            2.0,
+            "{transform:?}"
+        );
+    }
+    for transform in ROTATING {
+        let logical_size = Size {
+            width: 1080,
+            height: 1920,
+        };
+        assert_eq!(
+            output(transform, logical_size, 2).scale(),
+            2.0,
+            "{transform:?}"
+        );
+    }
+}
+
+#[test]
+fn fractional_scale() {
+    for transform in NON_ROTATING {
+        let logical_size = Size {
+            width: 2560,
+            height: 1440,
+        };
+        assert_eq!(
+            output(transform, logical_size, 2).scale(),
+            1.5,
+            "{transform:?}"
+        );
+    }
+    for transform in ROTATING {
+        let logical_size = Size {
+            width: 1440,
+            height: 2560,
+        };
+        assert_eq!(
+            output(transform, logical_size, 2).scale(),
+            1.5,
+            "{transform:?}"
+        );
+    }
+}
+
+#[test]
+fn preferred_scale_is_informational() {
+    // The scale always matches the sizes the compositor reported, even where they disagree with
+    // the preferred scale.
+    for transform in NON_ROTATING {
+        let logical_size = Size {
+            width: 3071,
+            height: 1728,
+        };
+        let mut output = output(transform, logical_size, 2);
+        output.preferred_scale = Some(1.25);
+        assert_eq!(output.scale(), 3840.0 / 3071.0, "{transform:?}");
+    }
+    for transform in ROTATING {
+        let logical_size = Size {
+            width: 1727,
+            height: 3072,
+        };
+        let mut output = output(transform, logical_size, 2);
+        output.preferred_scale = Some(1.25);
+        assert_eq!(output.scale(), 2160.0 / 1727.0, "{transform:?}");
+    }
+    for transform in NON_ROTATING.into_iter().chain(ROTATING) {
+        let mut output = output(transform, Size::default(), 2);
+        output.preferred_scale = Some(1.5);
+        assert_eq!(output.scale(), 2.0, "{transform:?}");
+    }
+}
+
+#[test]
+fn falls_back_to_integer_scale_without_logical_size() {
+    for transform in NON_ROTATING.into_iter().chain(ROTATING) {
+        assert_eq!(
+            output(transform, Size::default(), 3).scale(),
+            3.0,
+            "{transform:?}"
+        );
+        assert_eq!(
+            output(transform, Size::default(), 0).scale(),
+            1.0,
+            "{transform:?}"
+        );
+    }
+}
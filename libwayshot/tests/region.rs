@@ -0,0 +1,258 @@
+//! Properties of the region operations and of the conversions between logical and physical
+//! regions.
+
+mod common;
+
+use libwayshot::{
+    WayshotConnection,
+    output::OutputInfo,
+    region::{EmbeddedRegion, LogicalRegion, PhysicalRegion, Position, Region},
+    test_server::{MockCompositor, MockOutput},
+};
+use proptest::{prelude::*, sample::select};
+use wayland_client::protocol::wl_output::Transform;
+
+use common::{TRANSFORMS, rotated, size};
+
+/// Outputs with every transform at scale 1, 2, 3 and 1.5, as reported by the mock compositor.
+fn outputs() -> Vec<OutputInfo> {
+    let mut compositor = MockCompositor::new();
+    for (index, (transform, _)) in TRANSFORMS.into_iter().enumerate() {
+        let position = Position {
+            x: index as i32 * 100 - 300,
+            y: index as i32 * 7 - 20,
+        };
+        let logical_size = if rotated(transform) {
+            size(8, 16)
+        } else {
+            size(16, 8)
+        };
+        for scale in 1..=3 {
+            compositor = compositor.with_output(
+                MockOutput::new(&format!("OUT-{index}-{scale}"), size(16 * scale, 8 * scale))
+                    .with_transform(transform)
+                    .with_integer_scale(scale as i32)
+                    .with_position(position),
+            );
+        }
+        compositor = compositor.with_output(
+            MockOutput::new(&format!("OUT-{index}-fractional"), size(24, 12))
+                .with_transform(transform)
+                .with_integer_scale(2)
+                .with_logical_size(logical_size)
+                .with_position(position),
+        );
+    }
+    let server = compositor.spawn().unwrap();
+    WayshotConnection::from_connection(server.connection())
+        .unwrap()
+        .get_all_outputs()
+        .to_vec()
+}
+
+fn region() -> impl Strategy<Value = Region> {
+    (-1000..1000, -1000..1000, 0u32..500, 0u32..500).prop_map(|(x, y, width, height)| Region {
+        position: Position { x, y },
+        size: size(width, height),
+    })
+}
+
+/// A single pixel.
+fn point() -> impl Strategy<Value = Region> {
+    (-1000..1500, -1000..1500).prop_map(|(x, y)| Region {
+        position: Position { x, y },
+        size: size(1, 1),
+    })
+}
+
+/// An output and a pixel inside of it, relative to its top left corner.
+fn output_and_pixel() -> impl Strategy<Value = (OutputInfo, u32, u32)> {
+    select(outputs()).prop_flat_map(|output| {
+        let size = output.logical_region.inner.size;
+        (Just(output), 0..size.width, 0..size.height)
+    })
+}
+
+fn logical(inner: Region) -> LogicalRegion {
+    LogicalRegion { inner }
+}
+
+proptest! {
+    #[test]
+    fn intersection_is_commutative(a in region(), b in region()) {
+        prop_assert_eq!(a.intersection(&b), b.intersection(&a));
+    }
+
+    #[test]
+    fn intersection_covers_shared_pixels(a in region(), b in region(), point in point()) {
+        let in_both = a.contains(&point) && b.contains(&point);
+        let in_intersection = a
+            .intersection(&b)
+            .is_some_and(|intersection| intersection.contains(&point));
+        prop_assert_eq!(in_intersection, in_both);
+    }
+
+    #[test]
+    fn intersection_is_inside_both(a in region(), b in region()) {
+        if let Some(intersection) = a.intersection(&b) {
+            prop_assert!(!intersection.is_empty());
+            prop_assert!(a.contains(&intersection));
+            prop_assert!(b.contains(&intersection));
+        }
+    }
+
+    #[test]
+    fn embedded_region_is_the_intersection(a in region(), b in region()) {
+        prop_assert_eq!(
+            EmbeddedRegion::new(logical(a), logical(b)).map(|embedded| embedded.logical()),
+            logical(a).intersection(&logical(b))
+        );
+    }
+
+    #[test]
+    fn union_contains_both(a in region(), b in region()) {
+        let union = a.union(&b);
+        prop_assert!(union.contains(&a));
+        prop_assert!(union.contains(&b));
+        prop_assert_eq!(union, b.union(&a));
+    }
+
+    #[test]
+    fn union_is_associative(a in region(), b in region(), c in region()) {
+        prop_assert_eq!(a.union(&b).union(&c), a.union(&b.union(&c)));
+    }
+
+    #[test]
+    fn contains_matches_union(a in region(), b in region()) {
+        prop_assert!(a.contains(&a));
+        prop_assert_eq!(a.contains(&b), a.union(&b) == a);
+    }
+
+    #[test]
+    fn translate_keeps_size(a in region(), b in region(), x in -1000..1000, y in -1000..1000) {
+        let translated = a.translate(x, y);
+        prop_assert_eq!(translated.size, a.size);
+        prop_assert_eq!(translated.translate(-x, -y), a);
+        prop_assert_eq!(
+            a.intersection(&b).map(|intersection| intersection.translate(x, y)),
+            translated.intersection(&b.translate(x, y))
+        );
+    }
+
+    #[test]
+    fn integer_scale_is_exact(a in region(), factor in 1..8) {
+        let scaled = a.scale(factor as f64);
+        prop_assert_eq!(scaled.position.x, a.position.x * factor);
+        prop_assert_eq!(scaled.position.y, a.position.y * factor);
+        prop_assert_eq!(scaled.size.width, a.size.width * factor as u32);
+        prop_assert_eq!(scaled.size.height, a.size.height * factor as u32);
+    }
+
+    #[test]
+    fn fractional_scale_rounds_outwards(a in region(), factor in 0.1f64..8.0) {
+        let scaled = a.scale(factor);
+        let left = a.position.x as f64 * factor;
+        let right = (a.position.x as f64 + a.size.width as f64) * factor;
+        let x1 = scaled.position.x as f64;
+        let x2 = x1 + scaled.size.width as f64;
+        prop_assert!(x1 <= left && left < x1 + 1.0);
+        prop_assert!(x2 >= right && right > x2 - 1.0);
+    }
+
+    #[test]
+    fn clamp_stays_in_bounds(a in region(), bounds in region()) {
+        let clamped = a.clamp_to(&bounds);
+        prop_assert!(bounds.contains(&clamped));
+        prop_assert_eq!(clamped.clamp_to(&bounds), clamped);
+        match a.intersection(&bounds) {
+            Some(intersection) => prop_assert_eq!(clamped, intersection),
+            None => prop_assert!(clamped.is_empty()),
+        }
+        if bounds.contains(&a) {
+            prop_assert_eq!(clamped, a);
+        }
+    }
+
+    #[test]
+    fn physical_round_trip(output in select(outputs()), a in region()) {
+        let physical = PhysicalRegion::from_logical(logical(a), &output);
+        let round_trip = physical.to_logical(&output);
+        if output.scale().fract() == 0.0 {
+            let scale = output.scale() as u32;
+            let (width, height) = if rotated(output.transform) {
+                (a.size.height, a.size.width)
+            } else {
+                (a.size.width, a.size.height)
+            };
+            prop_assert_eq!(physical.inner.size, size(width * scale, height * scale));
+            prop_assert_eq!(round_trip, logical(a));
+        } else {
+            prop_assert!(round_trip.contains(&logical(a)));
+        }
+
+        let buffer = PhysicalRegion { inner: a };
+        prop_assert!(PhysicalRegion::from_logical(buffer.to_logical(&output), &output).contains(&buffer));
+    }
+
+    #[test]
+    fn physical_region_of_the_output(output in select(outputs())) {
+        prop_assert_eq!(
+            PhysicalRegion::from_logical(output.logical_region, &output),
+            PhysicalRegion::from(&output)
+        );
+        prop_assert_eq!(PhysicalRegion::from(&output).to_logical(&output), output.logical_region);
+    }
+
+    #[test]
+    fn physical_pixels_follow_the_transform((output, x, y) in output_and_pixel()) {
+        // Each logical pixel lands where the transform moves it to in the buffer, e.g. the top
+        // left logical pixel of a 90° output comes from the bottom left of the buffer.
+        let origin = output.logical_region.inner.position;
+        let logical_size = output.logical_region.inner.size;
+        let pixel = Region {
+            position: Position { x: origin.x + x as i32, y: origin.y + y as i32 },
+            size: size(1, 1),
+        };
+        let physical = PhysicalRegion::from_logical(logical(pixel), &output).inner;
+        prop_assert!(PhysicalRegion::from(&output).inner.contains(&physical));
+
+        let (width, height) = (logical_size.width, logical_size.height);
+        let expected = match output.transform {
+            Transform::_90 => (y, width - 1 - x),
+            Transform::_180 => (width - 1 - x, height - 1 - y),
+            Transform::_270 => (height - 1 - y, x),
+            Transform::Flipped => (width - 1 - x, y),
+            Transform::Flipped90 => (height - 1 - y, width - 1 - x),
+            Transform::Flipped180 => (x, height - 1 - y),
+            Transform::Flipped270 => (y, x),
+            _ => (x, y),
+        };
+        let scale = output.scale();
+        let expected = Position {
+            x: (expected.0 as f64 * scale).floor() as i32,
+            y: (expected.1 as f64 * scale).floor() as i32,
+        };
+        prop_assert_eq!(physical.position, expected);
+    }
+}
+
+#[test]
+fn operations_saturate_instead_of_overflowing() {
+    let region = Region {
+        position: Position {
+            x: i32::MAX - 1,
+            y: i32::MIN,
+        },
+        size: size(u32::MAX, u32::MAX),
+    };
+    let translated = region.translate(10, -10);
+    assert_eq!(
+        translated.position,
+        Position {
+            x: i32::MAX,
+            y: i32::MIN
+        }
+    );
+    assert_eq!(region.scale(4.0).position, translated.position);
+    assert_eq!(region.union(&translated), region);
+}
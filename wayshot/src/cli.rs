@@ -1,15 +1,16 @@
 use std::path::PathBuf;
 
 use clap::{
-    Parser, arg,
+    Parser,
     builder::{
         Styles,
         styling::{AnsiColor, Effects},
     },
 };
+use libwayshot::region::Geometry;
 use tracing::Level;
 
-use crate::utils::EncodingFormat;
+use crate::utils::{ColorFormat, EncodingFormat, ResampleFilter, ScalePolicy, ToplevelSize};
 
 fn get_styles() -> Styles {
     Styles::styled()
@@ -42,9 +43,22 @@ pub struct Cli {
     #[arg(short, long)]
     pub geometry: bool,
 
-    /// Capture a geometry specified as `x,y widthxheight` (from slurp)
+    /// Capture a geometry such as `x,y widthxheight` (from slurp), `widthxheight+x+y`,
+    /// `center widthxheight` or `OUTPUT:x,y widthxheight`. Positions and sizes may be percentages
     #[arg(long, value_name = "GEOMETRY", conflicts_with = "geometry")]
-    pub geometry_str: Option<String>,
+    pub geometry_str: Option<Geometry>,
+
+    /// Pick a color interactively and print it instead of taking a screenshot.
+    /// Combine with --clipboard to also copy the color.
+    #[arg(
+        long,
+        verbatim_doc_comment,
+        value_name = "COLOR_FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "hex",
+        conflicts_with_all = ["geometry", "geometry_str", "output", "choose_output", "toplevel", "choose_toplevel", "dmabuf"]
+    )]
+    pub pick_color: Option<ColorFormat>,
 
     /// Enable cursor in screenshots
     #[arg(short, long)]
@@ -83,6 +97,21 @@ pub struct Cli {
     #[arg(long, alias = "choose-window", conflicts_with_all = ["geometry", "output", "choose_output", "toplevel"])]
     pub choose_toplevel: bool,
 
+    /// How to size screenshots spanning outputs with different scales.
+    /// Defaults to config value (`max`)
+    #[arg(long, verbatim_doc_comment, value_name = "POLICY")]
+    pub scale_policy: Option<ScalePolicy>,
+
+    /// Filter used to resample outputs to the screenshot's scale.
+    /// Defaults to config value (`gaussian`)
+    #[arg(long, verbatim_doc_comment, value_name = "FILTER")]
+    pub resample_filter: Option<ResampleFilter>,
+
+    /// How to size window screenshots.
+    /// Defaults to config value (`native`)
+    #[arg(long, verbatim_doc_comment, value_name = "SIZE")]
+    pub toplevel_size: Option<ToplevelSize>,
+
     /// Output file name's formatting.
     /// Defaults to config value (`wayshot-%Y_%m_%d-%H_%M_%S`)
     #[arg(long, verbatim_doc_comment)]
@@ -96,15 +125,15 @@ pub struct Cli {
     #[arg(long, verbatim_doc_comment)]
     pub config: Option<PathBuf>,
 
-    /// Capture directly into a DMA-BUF backed buffer and dump the raw contents to disk.
-    /// Optionally override the DRM render node to use (defaults to `/dev/dri/renderD128`).
+    /// Capture the output through a DMA-BUF backed buffer instead of shared memory.
+    /// 10-bit formats are kept at full precision. Optionally override the DRM render node to use (defaults to `/dev/dri/renderD128`).
     #[arg(
         long,
         value_name = "DRM_RENDER_NODE",
         num_args = 0..=1,
         default_missing_value = "/dev/dri/renderD128",
         requires = "output",
-        conflicts_with_all = ["geometry", "choose_output"]
+        conflicts_with_all = ["geometry", "geometry_str", "choose_output"]
     )]
     pub dmabuf: Option<PathBuf>,
 
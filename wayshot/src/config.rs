@@ -1,4 +1,4 @@
-use crate::utils::EncodingFormat;
+use crate::utils::{EncodingFormat, ResampleFilter, ScalePolicy, ToplevelSize};
 use serde::{Deserialize, Serialize};
 use std::{env, io::Read, path::PathBuf};
 use tracing::Level;
@@ -43,6 +43,9 @@ pub struct Base {
     pub stdout: Option<bool>,
     pub log_level: Option<String>,
     pub notifications: Option<bool>,
+    pub scale_policy: Option<ScalePolicy>,
+    pub resample_filter: Option<ResampleFilter>,
+    pub toplevel_size: Option<ToplevelSize>,
 }
 
 impl Default for Base {
@@ -55,6 +58,9 @@ impl Default for Base {
             stdout: Some(false),
             log_level: Some("info".to_string()),
             notifications: Some(true),
+            scale_policy: Some(ScalePolicy::Max),
+            resample_filter: Some(ResampleFilter::Gaussian),
+            toplevel_size: Some(ToplevelSize::Native),
         }
     }
 }
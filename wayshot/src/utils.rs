@@ -9,8 +9,8 @@ use std::{
 };
 
 use chrono::Local;
-use libwayshot::Result;
 use libwayshot::region::{LogicalRegion, Position, Region, Size};
+use libwayshot::{PickedColor, Result};
 
 pub fn waysip_to_region(
     size: libwaysip::Size,
@@ -34,73 +34,14 @@ pub fn waysip_to_region(
     })
 }
 
-pub fn parse_geometry_str(geometry: &str) -> Result<LogicalRegion> {
-    // Expected format: "x,y widthxheight"
-    let mut parts = geometry.split_whitespace();
-    let xy = parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-    let wh = parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-
-    if parts.next().is_some() {
-        return Err(libwayshot::Error::FreezeCallbackError(
-            "invalid geometry".to_string(),
-        ));
-    }
-
-    let mut xy_parts = xy.split(',');
-    let x: i32 = xy_parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?
-        .parse()
-        .map_err(|_| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-    let y: i32 = xy_parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?
-        .parse()
-        .map_err(|_| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-
-    if xy_parts.next().is_some() {
-        return Err(libwayshot::Error::FreezeCallbackError(
-            "invalid geometry".to_string(),
-        ));
-    }
-
-    let mut wh_parts = wh.split('x');
-    let width: u32 = wh_parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?
-        .parse()
-        .map_err(|_| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-    let height: u32 = wh_parts
-        .next()
-        .ok_or_else(|| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?
-        .parse()
-        .map_err(|_| libwayshot::Error::FreezeCallbackError("invalid geometry".to_string()))?;
-
-    if wh_parts.next().is_some() {
-        return Err(libwayshot::Error::FreezeCallbackError(
-            "invalid geometry".to_string(),
-        ));
-    }
-
-    Ok(LogicalRegion {
-        inner: Region {
-            position: Position { x, y },
-            size: Size { width, height },
-        },
-    })
-}
-
 /// Supported image encoding formats.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EncodingFormat {
     /// JPG/JPEG encoder.
     Jpg,
     /// PNG encoder.
+    #[default]
     Png,
     /// PPM encoder.
     Ppm,
@@ -112,12 +53,6 @@ pub enum EncodingFormat {
     Avif,
 }
 
-impl Default for EncodingFormat {
-    fn default() -> Self {
-        Self::Png
-    }
-}
-
 impl From<EncodingFormat> for image::ImageFormat {
     fn from(format: EncodingFormat) -> Self {
         match format {
@@ -187,6 +122,128 @@ impl FromStr for EncodingFormat {
     }
 }
 
+/// How screenshots spanning outputs with different scales are sized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalePolicy {
+    /// Upscale every output to the largest output scale.
+    #[default]
+    Max,
+    /// Downscale every output to the smallest output scale.
+    Min,
+    /// One pixel per logical pixel.
+    Logical,
+    /// Keep the native pixels of every output.
+    Native,
+}
+
+impl From<ScalePolicy> for libwayshot::ScalePolicy {
+    fn from(policy: ScalePolicy) -> Self {
+        match policy {
+            ScalePolicy::Max => libwayshot::ScalePolicy::Max,
+            ScalePolicy::Min => libwayshot::ScalePolicy::Min,
+            ScalePolicy::Logical => libwayshot::ScalePolicy::Logical,
+            ScalePolicy::Native => libwayshot::ScalePolicy::Native,
+        }
+    }
+}
+
+/// How window screenshots are sized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToplevelSize {
+    /// One pixel per pixel the window rendered.
+    #[default]
+    Native,
+    /// One pixel per logical pixel of the window, when all outputs have the same scale.
+    Logical,
+}
+
+impl From<ToplevelSize> for libwayshot::ToplevelSize {
+    fn from(size: ToplevelSize) -> Self {
+        match size {
+            ToplevelSize::Native => libwayshot::ToplevelSize::Native,
+            ToplevelSize::Logical => libwayshot::ToplevelSize::Logical,
+        }
+    }
+}
+
+/// Filters for resampling outputs to the screenshot's scale.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResampleFilter {
+    /// Nearest neighbour, keeps integer scale factors sharp.
+    Nearest,
+    /// Linear filter.
+    Triangle,
+    /// Cubic filter.
+    CatmullRom,
+    /// Gaussian filter.
+    #[default]
+    Gaussian,
+    /// Lanczos filter with a window of 3.
+    Lanczos3,
+}
+
+impl From<ResampleFilter> for image::imageops::FilterType {
+    fn from(filter: ResampleFilter) -> Self {
+        match filter {
+            ResampleFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResampleFilter::Triangle => image::imageops::FilterType::Triangle,
+            ResampleFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResampleFilter::Gaussian => image::imageops::FilterType::Gaussian,
+            ResampleFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Notations for printing picked colors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorFormat {
+    /// `#rrggbb`.
+    #[default]
+    Hex,
+    /// `rgb(r, g, b)`.
+    Rgb,
+    /// `hsl(h, s%, l%)`.
+    Hsl,
+}
+
+impl ColorFormat {
+    pub fn format(self, color: &PickedColor) -> String {
+        let [r, g, b, _] = color.srgb8();
+        match self {
+            ColorFormat::Hex => format!("#{r:02x}{g:02x}{b:02x}"),
+            ColorFormat::Rgb => format!("rgb({r}, {g}, {b})"),
+            ColorFormat::Hsl => {
+                let [r, g, b] = [r, g, b].map(|channel| channel as f64 / 255.0);
+                let max = r.max(g).max(b);
+                let min = r.min(g).min(b);
+                let lightness = (max + min) / 2.0;
+                let delta = max - min;
+                let (hue, saturation) = if delta == 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    let hue = if max == r {
+                        ((g - b) / delta).rem_euclid(6.0)
+                    } else if max == g {
+                        (b - r) / delta + 2.0
+                    } else {
+                        (r - g) / delta + 4.0
+                    };
+                    (hue * 60.0, delta / (1.0 - (2.0 * lightness - 1.0).abs()))
+                };
+                format!(
+                    "hsl({:.0}, {:.0}%, {:.0}%)",
+                    hue,
+                    saturation * 100.0,
+                    lightness * 100.0
+                )
+            }
+        }
+    }
+}
+
 pub fn get_absolute_path(path: &Path) -> PathBuf {
     if path.is_absolute() {
         path.to_path_buf()
@@ -229,4 +286,93 @@ pub fn get_full_file_name(path: &Path, filename_format: &str, encoding: Encoding
     }
 }
 
-const TIMEOUT: i32 = 5000;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: u8, g: u8, b: u8) -> PickedColor {
+        PickedColor {
+            rgba: [r, g, b, 255].map(|channel| channel as u16 * 257),
+            bit_depth: 8,
+        }
+    }
+
+    #[test]
+    fn format_known_colors() {
+        let cases = [
+            ((0, 0, 0), "#000000", "rgb(0, 0, 0)", "hsl(0, 0%, 0%)"),
+            (
+                (255, 255, 255),
+                "#ffffff",
+                "rgb(255, 255, 255)",
+                "hsl(0, 0%, 100%)",
+            ),
+            (
+                (128, 128, 128),
+                "#808080",
+                "rgb(128, 128, 128)",
+                "hsl(0, 0%, 50%)",
+            ),
+            (
+                (255, 0, 0),
+                "#ff0000",
+                "rgb(255, 0, 0)",
+                "hsl(0, 100%, 50%)",
+            ),
+            (
+                (0, 255, 0),
+                "#00ff00",
+                "rgb(0, 255, 0)",
+                "hsl(120, 100%, 50%)",
+            ),
+            (
+                (0, 0, 255),
+                "#0000ff",
+                "rgb(0, 0, 255)",
+                "hsl(240, 100%, 50%)",
+            ),
+            (
+                (255, 255, 0),
+                "#ffff00",
+                "rgb(255, 255, 0)",
+                "hsl(60, 100%, 50%)",
+            ),
+            (
+                (0, 255, 255),
+                "#00ffff",
+                "rgb(0, 255, 255)",
+                "hsl(180, 100%, 50%)",
+            ),
+            (
+                (255, 0, 255),
+                "#ff00ff",
+                "rgb(255, 0, 255)",
+                "hsl(300, 100%, 50%)",
+            ),
+            (
+                (128, 0, 0),
+                "#800000",
+                "rgb(128, 0, 0)",
+                "hsl(0, 100%, 25%)",
+            ),
+            (
+                (255, 128, 0),
+                "#ff8000",
+                "rgb(255, 128, 0)",
+                "hsl(30, 100%, 50%)",
+            ),
+            (
+                (64, 128, 192),
+                "#4080c0",
+                "rgb(64, 128, 192)",
+                "hsl(210, 50%, 50%)",
+            ),
+        ];
+        for ((r, g, b), hex, rgb, hsl) in cases {
+            let color = color(r, g, b);
+            assert_eq!(ColorFormat::Hex.format(&color), hex);
+            assert_eq!(ColorFormat::Rgb.format(&color), rgb);
+            assert_eq!(ColorFormat::Hsl.format(&color), hsl, "{rgb}");
+        }
+    }
+}
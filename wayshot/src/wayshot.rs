@@ -1,27 +1,32 @@
 use config::Config;
 use std::{
+    cell::Cell,
     env,
     fs::File,
     io::{self, BufWriter, Cursor, Write},
-    path::{Path, PathBuf},
+    path::Path,
+    rc::Rc,
 };
 
 use clap::Parser;
 use eyre::{Result, bail, eyre};
 use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Rgb, Rgba};
-use libwayshot::WayshotConnection;
+use libwayshot::{
+    PickedColor, WayshotConnection,
+    region::{LogicalRegion, Position, Region, Size},
+};
 
 mod cli;
 mod config;
 mod utils;
 
 use dialoguer::{FuzzySelect, theme::ColorfulTheme};
-use tracing::{info, warn};
-use utils::{EncodingFormat, get_absolute_path, get_expanded_path, parse_geometry_str, waysip_to_region};
+use tracing::warn;
+use utils::{EncodingFormat, get_absolute_path, get_expanded_path, waysip_to_region};
 
 use wl_clipboard_rs::copy::{MimeType, Options, Source};
 
-use rustix::{mm::{MapFlags, ProtFlags, mmap, munmap}, runtime::{self, Fork}};
+use rustix::runtime::{self, Fork};
 
 fn select_output<T>(outputs: &[T]) -> Option<usize>
 where
@@ -129,7 +134,14 @@ fn main() -> Result<()> {
 
     let dmabuf_device = cli.dmabuf.as_ref().map(|path| get_expanded_path(path));
 
-    let wayshot_conn = if let Some(device_path) = dmabuf_device.clone() {
+    let scale_policy = cli.scale_policy.or(base.scale_policy).unwrap_or_default();
+    let resample_filter = cli
+        .resample_filter
+        .or(base.resample_filter)
+        .unwrap_or_default();
+    let toplevel_size = cli.toplevel_size.or(base.toplevel_size).unwrap_or_default();
+
+    let mut wayshot_conn = if let Some(device_path) = dmabuf_device.clone() {
         let render_node = device_path
             .to_str()
             .ok_or_else(|| eyre!("render node path must be valid UTF-8"))?;
@@ -137,6 +149,9 @@ fn main() -> Result<()> {
     } else {
         WayshotConnection::new()?
     };
+    wayshot_conn.set_scale_policy(scale_policy.into());
+    wayshot_conn.set_resample_filter(resample_filter.into());
+    wayshot_conn.set_toplevel_size(toplevel_size.into());
 
     let stdout = io::stdout();
     let mut writer = BufWriter::new(stdout.lock());
@@ -152,48 +167,55 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    if dmabuf_device.is_some() {
-        if stdout_print {
-            bail!("--dmabuf does not support writing to stdout");
-        }
+    if let Some(color_format) = cli.pick_color {
+        let picked = Rc::new(Cell::new(None));
+        let picked_in_callback = picked.clone();
+        wayshot_conn.screenshot_freeze(
+            move |w_conn, frozen_frames| {
+                let info = libwaysip::get_area(
+                    Some(libwaysip::WaysipConnection {
+                        connection: &w_conn.conn,
+                        globals: &w_conn.globals,
+                    }),
+                    libwaysip::SelectionType::Point,
+                )
+                .map_err(|e| libwayshot::Error::FreezeCallbackError(e.to_string()))?
+                .ok_or(libwayshot::Error::FreezeCallbackError(
+                    "Failed to pick a point".to_string(),
+                ))?;
+                let point = info.left_top_point();
+                let region = LogicalRegion {
+                    inner: Region {
+                        position: Position {
+                            x: point.x,
+                            y: point.y,
+                        },
+                        size: Size {
+                            width: 1,
+                            height: 1,
+                        },
+                    },
+                };
+                picked_in_callback.set(Some(PickedColor::from_frames(frozen_frames, region)?));
+                Ok(region)
+            },
+            // The cursor would sit right on the picked pixel.
+            false,
+        )?;
+        let color = picked.get().ok_or_else(|| eyre!("no color was picked"))?;
+        let color = color_format.format(&color);
+        writeln!(writer, "{color}")?;
+        writer.flush()?;
+
         if clipboard {
-            bail!("--dmabuf cannot be combined with --clipboard");
+            clipboard_daemonize(Cursor::new(color.into_bytes()), MimeType::Text)?;
         }
 
-        let output_name = output
-            .clone()
-            .ok_or_else(|| eyre!("--dmabuf requires an explicit --output"))?;
-        let file_path = file
-            .clone()
-            .ok_or_else(|| eyre!("--dmabuf requires an explicit output file path"))?;
-
-        let output_info = wayshot_conn
-            .get_all_outputs()
-            .iter()
-            .find(|info| info.name == output_name)
-            .cloned()
-            .ok_or_else(|| eyre!("No output named '{output_name}'"))?;
-
-        let mut frames =
-            wayshot_conn.capture_frame_copies_dmabuf(&[(output_info, None)], cursor)?;
-        let (frame_copy, frame_guard, _) = frames
-            .pop()
-            .ok_or_else(|| eyre!("Failed to capture DMA-BUF frame"))?;
-
-        let metadata_path = persist_dmabuf(&file_path, &frame_copy)?;
-        drop(frame_guard);
-
-        info!(
-            "Saved DMA-BUF capture to {} (metadata: {})",
-            file_path.display(),
-            metadata_path.display()
-        );
-
         return Ok(());
     }
 
-    let image_buffer = if let Some(geometry_str) = &cli.geometry_str {
-        let region = parse_geometry_str(geometry_str)?;
+    let image_buffer = if let Some(geometry) = &cli.geometry_str {
+        let region = geometry.resolve(wayshot_conn.get_all_outputs())?;
         wayshot_conn
             .screenshot_region(region, cursor)
             .or_else(|err| match err {
@@ -202,7 +224,7 @@ fn main() -> Result<()> {
             })?
     } else if cli.geometry {
         wayshot_conn.screenshot_freeze(
-            |w_conn| {
+            |w_conn, _frozen_frames| {
                 let info = libwaysip::get_area(
                     Some(libwaysip::WaysipConnection {
                         connection: &w_conn.conn,
@@ -221,7 +243,11 @@ fn main() -> Result<()> {
     } else if let Some(output_name) = output {
         let outputs = wayshot_conn.get_all_outputs();
         if let Some(output) = outputs.iter().find(|output| output.name == output_name) {
-            wayshot_conn.screenshot_single_output(output, cursor)?
+            if dmabuf_device.is_some() {
+                wayshot_conn.screenshot_single_output_dmabuf(output, cursor)?
+            } else {
+                wayshot_conn.screenshot_single_output(output, cursor)?
+            }
         } else {
             bail!("No output found!");
         }
@@ -252,13 +278,16 @@ fn main() -> Result<()> {
     }
 
     if clipboard {
-        clipboard_daemonize(match image_buf.take() {
-            Some(buf) => buf,
-            None => {
-                let bytes = encode_image_to_vec(&image_buffer, encoding, cli.embed_hdr_icc)?;
-                Cursor::new(bytes)
-            }
-        })?;
+        clipboard_daemonize(
+            match image_buf.take() {
+                Some(buf) => buf,
+                None => {
+                    let bytes = encode_image_to_vec(&image_buffer, encoding, cli.embed_hdr_icc)?;
+                    Cursor::new(bytes)
+                }
+            },
+            MimeType::Autodetect,
+        )?;
     }
 
     if let Some((tone_path, tone_encoding)) = tone_map_target {
@@ -276,8 +305,8 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-/// Daemonize and copy the given buffer containing the encoded image to the clipboard
-fn clipboard_daemonize(buffer: Cursor<Vec<u8>>) -> Result<()> {
+/// Daemonize and copy the given buffer containing the encoded image or text to the clipboard
+fn clipboard_daemonize(buffer: Cursor<Vec<u8>>, mime_type: MimeType) -> Result<()> {
     let mut opts = Options::new();
     opts.foreground(false);
 
@@ -285,10 +314,7 @@ fn clipboard_daemonize(buffer: Cursor<Vec<u8>>) -> Result<()> {
         Ok(Fork::ParentOf(_)) => Ok(()),
         Ok(Fork::Child(_)) => {
             opts.foreground(true);
-            opts.copy(
-                Source::Bytes(buffer.into_inner().into()),
-                MimeType::Autodetect,
-            )?;
+            opts.copy(Source::Bytes(buffer.into_inner().into()), mime_type)?;
             Ok(())
         }
         Err(e) => {
@@ -296,69 +322,12 @@ fn clipboard_daemonize(buffer: Cursor<Vec<u8>>) -> Result<()> {
                 "Fork failed with error: {e}, couldn't offer image on the clipboard persistently.
                  Use a clipboard manager to record screenshot."
             );
-            opts.copy(
-                Source::Bytes(buffer.into_inner().into()),
-                MimeType::Autodetect,
-            )?;
+            opts.copy(Source::Bytes(buffer.into_inner().into()), mime_type)?;
             Ok(())
         }
     }
 }
 
-fn persist_dmabuf(path: &Path, frame: &libwayshot::DMAFrameCopy) -> Result<PathBuf> {
-    let mut file = File::create(path)?;
-
-    let fd = frame
-        .buffer_object
-        .fd_for_plane(0)
-        .map_err(|_| eyre!("failed to export dma-buf fd"))?;
-    let length = frame.buffer_object.stride() as usize * frame.frame_format.size.height as usize;
-
-    let ptr = unsafe {
-        mmap(
-            std::ptr::null_mut(),
-            length,
-            ProtFlags::READ,
-            MapFlags::SHARED,
-            &fd,
-            0,
-        )?
-    };
-
-    let slice = unsafe { std::slice::from_raw_parts(ptr as *const u8, length) };
-    file.write_all(slice)?;
-    file.flush()?;
-
-    unsafe {
-        munmap(ptr, length)?;
-    }
-
-    let base_ext = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("raw");
-    let metadata_path = path.with_extension(format!("{base_ext}.metadata"));
-    let modifier: u64 = frame.buffer_object.modifier().into();
-
-    let metadata = format!(
-        "format=0x{format:08x}\nwidth={width}\nheight={height}\nstride={stride}\nmodifier=0x{modifier:016x}\ntransform={transform:?}\nlogical_x={logical_x}\nlogical_y={logical_y}\nlogical_width={logical_width}\nlogical_height={logical_height}\n",
-        format = frame.frame_format.format,
-        width = frame.frame_format.size.width,
-        height = frame.frame_format.size.height,
-        stride = frame.buffer_object.stride(),
-        modifier = modifier,
-        transform = frame.transform,
-        logical_x = frame.logical_region.inner.position.x,
-        logical_y = frame.logical_region.inner.position.y,
-        logical_width = frame.logical_region.inner.size.width,
-        logical_height = frame.logical_region.inner.size.height,
-    );
-
-    std::fs::write(&metadata_path, metadata)?;
-
-    Ok(metadata_path)
-}
-
 fn save_image_with_options(
     image: &DynamicImage,
     path: &Path,